@@ -0,0 +1,34 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// Generates `keycode_table.rs`, the Rust version of `keycodes.txt`.
+fn main() {
+    println!("cargo:rerun-if-changed=keycodes.txt");
+
+    let src = fs::read_to_string("keycodes.txt").expect("Cannot read keycodes.txt");
+    let mut table = String::from(
+        "// Generated by build.rs from keycodes.txt. Do not edit.\n\
+         pub(crate) const KEYCODES: &[(CGKeyCode, &[&str])] = &[\n",
+    );
+    for (i, line) in src.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut columns = line.split_whitespace();
+        let keycode: u16 = columns
+            .next()
+            .and_then(|keycode| keycode.parse().ok())
+            .unwrap_or_else(|| panic!("keycodes.txt:{}: invalid keycode", i + 1));
+        let names: Vec<&str> = columns.collect();
+        if names.is_empty() {
+            panic!("keycodes.txt:{}: keycode {} has no name", i + 1, keycode);
+        }
+        table.push_str(&format!("    ({}, &{:?}),\n", keycode, names));
+    }
+    table.push_str("];\n");
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("keycode_table.rs");
+    fs::write(out, table).expect("Cannot write keycode_table.rs");
+}
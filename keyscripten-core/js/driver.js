@@ -4,95 +4,192 @@ const $$IDS = [];
 const $$NAMES = [];
 const $$DESCRIPTIONS = [];
 const $$CALLBACKS = {};
+// The realm.js object of the realm which registered the plugin.
+const $$REALMS = {};
 const $$CONFIG_SCHEMAS = {};
 const $$CONFIG = {};
-const app_config = JSON.parse($$loadAppConfigJson());
+const $$STATS = {};
+// Plugins which receive the pointer movements. Set by js.rs from the manifests.
+const $$MOUSE_MOTION = {};
+const $$MOTION_TYPES = ["mouseMoved", "leftMouseDragged", "rightMouseDragged", "otherMouseDragged"];
+// $$IDS sorted by the priorities in the configuration. Rebuilt when the plugins or the
+// configuration change.
+let $$ORDER = null;
+let app_config = JSON.parse($$loadAppConfigJson());
+// The plugin whose callback is running. js.rs reads it when boa aborts the callback.
+let $$RUNNING_ID = null;
 
-// public API
-function registerPlugin(id, name, description, callback, config_schema) {
+// called by js_realm.rs
+// The plugin realms call the functions of the driver with their realm.js object, `realm`, as the
+// first argument. The objects passed to a plugin are made by its `realm`.
+function $$registerPlugin(realm, id, name, description, callback, config_schema) {
 	if (!$$IDS.includes(id)) {
 		$$IDS.push(id);
+		$$ORDER = null;
 	}
 
 	$$NAMES[id] = name;
 	$$DESCRIPTIONS[id] = description;
 	$$CALLBACKS[id] = callback;
+	$$REALMS[id] = realm;
 	$$CONFIG_SCHEMAS[id] = config_schema;
-	$$CONFIG[id] = buildConfig(id, config_schema);
+	$$CONFIG[id] = buildConfig(id);
 
 	console.log(
 		`Registered plugin: id=${id} name=${name} config=${JSON.stringify($$CONFIG[id])}`,
 	);
 }
 
+// public API, for the code evaluated in the driver realm.
+function registerPlugin(...args) {
+	$$registerPlugin($$REALM, ...args);
+}
+
 const reloadConfig = () => {
+	app_config = JSON.parse($$loadAppConfigJson());
+	$$ORDER = null;
 	for (const id of Object.keys($$CONFIG)) {
-		$$CONFIG[id] = buildConfig(id, $$CONFIG_SCHEMAS[id]);
+		$$CONFIG[id] = buildConfig(id);
 	}
 };
 
-const buildConfig = (id, config_schema) => {
-	const config = {};
-	for (const item of config_schema) {
-		const value =
-			(((app_config.plugins || {})[id] || {}).config || {})[item.name] ||
-			item.default;
-
-		switch (item.type) {
-			case "hotkey": {
-				console.log(`Parsing hotkey: ${id}: ${value}`);
-				const hotkey = new HotKey(value);
-				config[item.name] = hotkey;
-				break;
-			}
-			case "string":
-				config[item.name] = value;
-				break;
-			case "integer":
-				config[item.name] = Number.parseInt(value, 10);
-				break;
-			default:
-				throw new Error(
-					`Unknown type for plugin '${id}'(${item.name}): '${item.type}'`,
-				);
-		}
-		console.log(JSON.stringify([item, value]));
-	}
-	console.log(JSON.stringify(config));
-	return config;
+const buildConfig = (id) => {
+	const values = ((app_config.plugins || {})[id] || {}).config || {};
+	return $$REALMS[id].buildConfig(
+		id,
+		$$CONFIG_SCHEMAS[id],
+		JSON.stringify(values),
+	);
 };
 
 // called by js.rs
-function $$invokeEvent(event, needsConfigReload) {
-	if (needsConfigReload) {
-		console.log("Reloading configuration file");
-		reloadConfig();
-	}
+// Returns false if a plugin swallowed the event, or the event object to deliver otherwise.
+// A plugin may return an event object instead of true. The following plugins receive it,
+// and the application gets it instead of the original event.
+function $$invokeEvent(event) {
+	$$RUNNING_ID = null;
+	const motion = $$MOTION_TYPES.includes(event.type);
 
-	for (let i = 0; i < $$IDS.length; i++) {
-		const id = $$IDS[i];
+	for (const id of $$dispatchOrder()) {
 		const callback = $$CALLBACKS[id];
 		const config = $$CONFIG[id];
+		const realm = $$REALMS[id];
+		if (!$$isEnabled(id) || (motion && !$$MOUSE_MOTION[id])) {
+			continue;
+		}
 
+		const stats = $$statsOf(id);
+		stats.calls++;
 		try {
-			const result = callback(event, config);
+			$$RUNNING_ID = id;
+			// Each plugin gets its own copy, so a change made in place is kept as a rewrite.
+			const copy = realm.copyEvent(JSON.stringify(event));
+			const result = callback(copy, config);
+			// Each plugin has its own realm, so its Promise isn't an instance of ours.
+			if (result !== null && typeof result === "object" && typeof result.then === "function") {
+				// An async callback can't decide on the event any more once it awaits, so the
+				// event always passes. Swallow or rewrite events in a synchronous callback.
+				realm.watchAsync(id, result);
+				$$RUNNING_ID = null;
+				continue;
+			}
+			$$RUNNING_ID = null;
 			if (!result) {
-				return result;
+				stats.swallowed++;
+				return false;
 			}
+			event = typeof result === "object" ? result : copy;
 		} catch (e) {
+			$$RUNNING_ID = null;
+			stats.exceptions++;
 			console.log(`Cannot invoke the ${id}: ${e}`);
-			return true;
+			return event;
 		}
 	}
-	return true;
+	return event;
+}
+
+// called by js.rs
+function $$isEnabled(id) {
+	return ((app_config.plugins || {})[id] || {}).enabled !== false;
+}
+
+const $$priorityOf = (id) =>
+	((app_config.plugins || {})[id] || {}).priority || 0;
+
+// Higher priority first. The sort is stable, so ties keep the loading order.
+function $$dispatchOrder() {
+	if ($$ORDER === null) {
+		$$ORDER = [...$$IDS].sort((a, b) => $$priorityOf(b) - $$priorityOf(a));
+	}
+	return $$ORDER;
+}
+
+// called by js.rs
+function $$runningPluginId() {
+	return $$RUNNING_ID;
+}
+
+// called by js.rs
+// Takes effect until the configuration is reloaded. js.rs saves it to the configuration.
+function $$setPluginEnabled(id, enabled) {
+	app_config.plugins = app_config.plugins || {};
+	app_config.plugins[id] = app_config.plugins[id] || {};
+	app_config.plugins[id].enabled = enabled;
+	console.log(`${enabled ? "Enabled" : "Disabled"} plugin: id=${id}`);
+}
+
+// called by js.rs
+// Takes effect until the configuration is reloaded. js.rs saves it to the configuration.
+function $$setPluginPriority(id, priority) {
+	app_config.plugins = app_config.plugins || {};
+	app_config.plugins[id] = app_config.plugins[id] || {};
+	app_config.plugins[id].priority = priority;
+	$$ORDER = null;
+}
+
+// called by js.rs
+function $$setMouseMotion(id, enabled) {
+	$$MOUSE_MOTION[id] = enabled;
+}
+
+// called by js.rs
+// Whether the backend should listen to the pointer movements.
+function $$wantsMouseMotion() {
+	return $$IDS.some((id) => $$MOUSE_MOTION[id] && $$isEnabled(id));
+}
+
+// called by js.rs
+function $$pluginStats() {
+	return JSON.stringify(
+		$$dispatchOrder().map((id) => ({
+			id: id,
+			name: $$NAMES[id],
+			enabled: $$isEnabled(id),
+			...$$statsOf(id),
+		})),
+	);
+}
+
+const $$statsOf = (id) => {
+	if (!$$STATS[id]) {
+		$$STATS[id] = { calls: 0, swallowed: 0, exceptions: 0 };
+	}
+	return $$STATS[id];
+};
+
+// called by js.rs
+function $$reloadConfig() {
+	console.log("Reloading configuration file");
+	reloadConfig();
 }
 
 // called by js.rs
+// The plugins are listed in the dispatch order.
 function $$getConfigSchema(event) {
 	const result = [];
 
-	for (let i = 0; i < $$IDS.length; i++) {
-		const id = $$IDS[i];
+	for (const id of $$dispatchOrder()) {
 		const name = $$NAMES[id];
 		const description = $$DESCRIPTIONS[id];
 		const config_schema = $$CONFIG_SCHEMAS[id];
@@ -121,13 +218,143 @@ function $$unloadPlugin(plugin_id) {
 	const index = $$IDS.indexOf(plugin_id);
 	if (index > -1) {
 		$$IDS.splice(index, 1);
+		$$ORDER = null;
 	}
 
 	delete $$NAMES[plugin_id];
 	delete $$DESCRIPTIONS[plugin_id];
 	delete $$CALLBACKS[plugin_id];
+	delete $$REALMS[plugin_id];
 	delete $$CONFIG_SCHEMAS[plugin_id];
 	delete $$CONFIG[plugin_id];
+	delete $$STATS[plugin_id];
+	delete $$MOUSE_MOTION[plugin_id];
 
 	console.log(`Unloaded plugin: id=${plugin_id}`);
 }
+
+// called by js_realm.rs, whenever a plugin changes its `storage`.
+// Writes are batched, so that a plugin updating the storage on every key doesn't write the file
+// every time.
+let $$storageFlushTimer = null;
+
+function $$scheduleStorageFlush() {
+	if ($$storageFlushTimer === null) {
+		$$storageFlushTimer = setTimeout(() => {
+			$$storageFlushTimer = null;
+			$$storageFlush();
+		}, 1000);
+	}
+}
+
+// Timers. `$$now()` is the clock of js.rs, and js.rs runs the due timers whenever a timer may be
+// due.
+const $$TIMERS = new Map();
+let $$nextTimerId = 1;
+
+function $$addTimer(realm, callback, delay, args, repeat) {
+	if (typeof callback !== "function") {
+		throw new TypeError("Timer callback must be a function");
+	}
+	const id = $$nextTimerId++;
+	const interval = Math.max(Number(delay) || 0, 0);
+	$$TIMERS.set(id, {
+		realm,
+		callback,
+		args,
+		interval: repeat ? Math.max(interval, 1) : interval,
+		due: $$now() + interval,
+		repeat,
+	});
+	return id;
+}
+
+// called by js_realm.rs
+function $$setTimeout(realm, callback, delay, ...args) {
+	return $$addTimer(realm, callback, delay, args, false);
+}
+
+// called by js_realm.rs
+function $$setInterval(realm, callback, delay, ...args) {
+	return $$addTimer(realm, callback, delay, args, true);
+}
+
+// called by js_realm.rs
+// A realm can only clear its own timers.
+function $$clearTimer(realm, id) {
+	if ($$TIMERS.get(id)?.realm === realm) {
+		$$TIMERS.delete(id);
+	}
+}
+
+// public API, for the code evaluated in the driver realm.
+function setTimeout(callback, delay, ...args) {
+	return $$setTimeout($$REALM, callback, delay, ...args);
+}
+
+// public API, for the code evaluated in the driver realm.
+function setInterval(callback, delay, ...args) {
+	return $$setInterval($$REALM, callback, delay, ...args);
+}
+
+// public API, for the code evaluated in the driver realm.
+function clearTimeout(id) {
+	$$clearTimer($$REALM, id);
+}
+
+// public API, for the code evaluated in the driver realm.
+function clearInterval(id) {
+	$$clearTimer($$REALM, id);
+}
+
+// called by js.rs
+// Returns the ids of the due timers as JSON, in the order to run them. Timers added by the
+// callbacks run on the next call, so that a zero delay can't loop forever.
+function $$dueTimers(now) {
+	const due = [...$$TIMERS.entries()]
+		.filter(([, timer]) => timer.due <= now)
+		.sort(([idA, a], [idB, b]) => a.due - b.due || idA - idB)
+		.map(([id]) => id);
+	return JSON.stringify(due);
+}
+
+// called by js.rs, for each id returned by $$dueTimers. The callback runs as the plugin which owns
+// the realm of the timer, so that js.rs charges it when boa aborts the callback.
+function $$runTimer(id, now) {
+	$$RUNNING_ID = null;
+	const timer = $$TIMERS.get(id);
+	if (!timer) {
+		// cleared by the preceding callback
+		return;
+	}
+	if (timer.repeat) {
+		timer.due = now + timer.interval;
+	} else {
+		$$TIMERS.delete(id);
+	}
+
+	const owner = timer.realm.owner();
+	if (owner !== null && !$$isEnabled(owner)) {
+		return;
+	}
+	try {
+		$$RUNNING_ID = owner;
+		timer.callback(...timer.args);
+	} catch (e) {
+		console.log(`Exception in timer callback: ${e}`);
+	}
+	$$RUNNING_ID = null;
+}
+
+// called by js.rs
+// Returns milliseconds until the next timer, or -1 if there's none.
+function $$nextTimerDelay() {
+	let next = -1;
+	for (const timer of $$TIMERS.values()) {
+		const delay = Math.max(timer.due - $$now(), 0);
+		if (next < 0 || delay < next) {
+			next = delay;
+		}
+	}
+	return next;
+}
@@ -1,12 +1,12 @@
 (() => {
-	let latest_flags;
 	const buffer = [];
 
 	/**
 	 * Run dynamic macro.
+	 * @param {number} flags Modifier flags to restore after sending the macro.
 	 * @returns {boolean} Return true if sent keyboard event, false otherwise.
 	 */
-	function run_dynamic_macro() {
+	function run_dynamic_macro(flags) {
 		const repeatSize = checkRepeat(buffer);
 
 		if (repeatSize !== null) {
@@ -18,7 +18,7 @@
 				sendKeyboardEvent(keyState[0], keyState[1], true);
 			}
 
-			sendFlagsChangedEvent(latest_flags);
+			sendFlagsChangedEvent(flags);
 
 			return true;
 		} else {
@@ -32,7 +32,7 @@
 					buffer.unshift(keyState);
 				}
 
-				sendFlagsChangedEvent(latest_flags);
+				sendFlagsChangedEvent(flags);
 
 				return true;
 			} else {
@@ -95,19 +95,31 @@
 		(event, config) => {
 			// console.log(`config=${JSON.stringify(config)}`);
 
-			if (event.type === "flagsChanged") {
-				latest_flags = event.flags;
-			} else if (event.type === "keyDown") {
-				if (config.hotkey.matches(latest_flags, event.keycode)) {
-					return !run_dynamic_macro();
+			if (event.type === "keyDown") {
+				const state = config.hotkey.feed(event);
+				// The keys swallowed for a cancelled partial match.
+				for (const { keycode, flags } of config.hotkey.takeCancelled()) {
+					sendKeyboardEvent(keycode, flags, true);
+					sendKeyboardEvent(keycode, flags, false);
+					buffer.unshift([keycode, flags]);
+				}
+				if (buffer.length > 10) {
+					buffer.length = 10;
+				}
+				if (state === "match") {
+					return !run_dynamic_macro(event.flags);
+				}
+				if (state === "partial") {
+					// prefix of the hotkey sequence
+					return false;
 				}
 
-				buffer.unshift([event.keycode, latest_flags]);
+				buffer.unshift([event.keycode, event.flags]);
 				if (buffer.length > 10) {
 					buffer.pop();
 				}
 			}
-			// console.log(`event detected :::${JSON.stringify(event)}, ${latest_flags}`);
+			// console.log(`event detected :::${JSON.stringify(event)}`);
 
 			return true; // send event to the normal destination
 		},
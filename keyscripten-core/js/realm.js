@@ -0,0 +1,64 @@
+/** Evaluated in each realm before the plugin code runs. */
+
+// driver.js calls into a plugin only through these functions. The driver passes strings or the
+// objects of the plugin itself, and gets back the objects of this realm, so that the plugin never
+// holds an object of the driver realm. The builtins are captured before the plugin can replace them.
+(() => {
+	const parse = JSON.parse;
+	const log = console.log;
+	const Hotkey = HotKey;
+	const parseInt = Number.parseInt;
+
+	return {
+		// Copies the event, given as JSON, into this realm.
+		copyEvent(json) {
+			return parse(json);
+		},
+
+		// `values` is the JSON of the configured values of the plugin, by item name.
+		buildConfig(id, configSchema, values) {
+			values = parse(values);
+			const config = {};
+			for (const item of configSchema) {
+				const value = values[item.name] || item.default;
+				switch (item.type) {
+					case "hotkey":
+						log(`Parsing hotkey: ${id}: ${value}`);
+						config[item.name] = new Hotkey(value, {
+							timeout: item.timeout,
+							chordTimeout: item.chordTimeout,
+						});
+						break;
+					case "string":
+						config[item.name] = value;
+						break;
+					case "integer":
+						config[item.name] = parseInt(value, 10);
+						break;
+					default:
+						throw new Error(
+							`Unknown type for plugin '${id}'(${item.name}): '${item.type}'`,
+						);
+				}
+			}
+			return config;
+		},
+
+		// An async callback can't decide on the event any more once it awaits. Reports the values
+		// which would have.
+		watchAsync(id, promise) {
+			promise.then(
+				(result) => {
+					if (result === false || (result && typeof result === "object")) {
+						log(
+							`${id}: the value resolved by an async callback is ignored. The event was already passed`,
+						);
+					}
+				},
+				(e) => {
+					log(`Exception in the async callback of ${id}: ${e}`);
+				},
+			);
+		},
+	};
+})();
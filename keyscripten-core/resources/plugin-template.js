@@ -1,6 +1,10 @@
+// ==KeyScripten==
+// @id /*ID_TEXT*/
+// @name /*NAME_TEXT*/
+// @version 0.1.0
+// ==/KeyScripten==
 (() => {
 	const id = /*ID*/ {};
-	let latestFlags;
 
 	registerPlugin(
 		id,
@@ -9,12 +13,12 @@
 		(event, config) => {
 			if (event.type === "flagsChanged") {
 				console.log(`[${id}] flagsChanged: ${event.flags}`);
-				latestFlags = event.flags;
 			} else if (event.type === "keyDown") {
+				/* event.shift, event.ctrl, event.alt, event.cmd, event.fn and event.capsLock are set on every event. */
 				console.log(
-					`[${id}] keyDown: keycode=${event.keycode} flags=${latestFlags}`,
+					`[${id}] keyDown: keycode=${event.keycode} flags=${event.flags} ctrl=${event.ctrl} autorepeat=${event.autorepeat}`,
 				);
-				if (config.hotkey.matches(latestFlags, event.keycode)) {
+				if (config.hotkey.matches(event.flags, event.keycode)) {
 					console.log(`[${id}] Handled hotkey`);
 					return false;
 				}
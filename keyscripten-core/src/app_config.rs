@@ -1,4 +1,7 @@
 use crate::APP_NAME;
+use anyhow::anyhow;
+use boa_engine::JsData;
+use boa_gc::{Finalize, Trace};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
@@ -6,7 +9,7 @@ use std::fs::{File, OpenOptions};
 use std::io::Write;
 use std::path::PathBuf;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AppConfig {
     // key is the plugin id.
     // values are map of configurations.
@@ -18,6 +21,13 @@ pub struct AppConfig {
 pub struct PluginConfig {
     pub enabled: bool,
     pub config: Option<HashMap<String, String>>,
+    // Why the plugin was disabled automatically, e.g. by the watchdog. The UI shows it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disabled_reason: Option<String>,
+    // Plugins with a higher priority see the events first. Unset is 0, and ties run in the loading
+    // order.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i64>,
 }
 
 impl Default for PluginConfig {
@@ -25,6 +35,8 @@ impl Default for PluginConfig {
         Self {
             enabled: true,
             config: Some(HashMap::new()),
+            disabled_reason: None,
+            priority: None,
         }
     }
 }
@@ -74,6 +86,124 @@ impl AppConfig {
         f.write_all(json.as_bytes())?;
         Ok(())
     }
+
+    /// Gets the value by the dotted key. Supported keys are `log_level`, `plugins.<id>.enabled`,
+    /// `plugins.<id>.priority` and `plugins.<id>.config.<name>`.
+    pub fn get_value(&self, key: &str) -> anyhow::Result<Option<String>> {
+        match parse_key(key)? {
+            ConfigKey::LogLevel => Ok(Some(self.log_level.clone())),
+            ConfigKey::Enabled(plugin_id) => Ok(self
+                .plugins
+                .as_ref()
+                .and_then(|plugins| plugins.get(plugin_id))
+                .map(|plugin| plugin.enabled.to_string())),
+            ConfigKey::Priority(plugin_id) => Ok(self
+                .plugins
+                .as_ref()
+                .and_then(|plugins| plugins.get(plugin_id))
+                .and_then(|plugin| plugin.priority)
+                .map(|priority| priority.to_string())),
+            ConfigKey::PluginConfig(plugin_id, name) => Ok(self
+                .plugins
+                .as_ref()
+                .and_then(|plugins| plugins.get(plugin_id))
+                .and_then(|plugin| plugin.config.as_ref())
+                .and_then(|config| config.get(name))
+                .cloned()),
+        }
+    }
+
+    /// Sets the value by the dotted key. See `get_value` for the keys.
+    pub fn set_value(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+        match parse_key(key)? {
+            ConfigKey::LogLevel => {
+                self.log_level = value.to_string();
+            }
+            ConfigKey::Enabled(plugin_id) => {
+                let enabled = value
+                    .parse::<bool>()
+                    .map_err(|err| anyhow!("`{}` must be true or false: {:?}", key, err))?;
+                let plugin_config = self.plugin_config_mut(plugin_id);
+                plugin_config.enabled = enabled;
+                if enabled {
+                    plugin_config.disabled_reason = None;
+                }
+            }
+            ConfigKey::Priority(plugin_id) => {
+                let priority = value
+                    .parse::<i64>()
+                    .map_err(|err| anyhow!("`{}` must be an integer: {:?}", key, err))?;
+                self.plugin_config_mut(plugin_id).priority = Some(priority);
+            }
+            ConfigKey::PluginConfig(plugin_id, name) => {
+                self.plugin_config_mut(plugin_id)
+                    .config
+                    .get_or_insert(HashMap::new())
+                    .insert(name.to_string(), value.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Disables the plugin, recording why.
+    pub fn disable_plugin(&mut self, plugin_id: &str, reason: &str) {
+        let plugin_config = self.plugin_config_mut(plugin_id);
+        plugin_config.enabled = false;
+        plugin_config.disabled_reason = Some(reason.to_string());
+    }
+
+    /// Sets the priorities so that the plugins run in the order of `plugin_ids`.
+    pub fn set_plugin_order(&mut self, plugin_ids: &[String]) {
+        for (plugin_id, priority) in order_priorities(plugin_ids) {
+            self.plugin_config_mut(plugin_id).priority = Some(priority);
+        }
+    }
+
+    fn plugin_config_mut(&mut self, plugin_id: &str) -> &mut PluginConfig {
+        self.plugins
+            .get_or_insert(HashMap::new())
+            .entry(plugin_id.to_string())
+            .or_default()
+    }
+}
+
+/// The priorities which make the plugins run in the order of `plugin_ids`. The last one gets 1, so
+/// that it still runs before the plugins without a priority.
+pub fn order_priorities(plugin_ids: &[String]) -> impl Iterator<Item = (&str, i64)> {
+    let len = plugin_ids.len() as i64;
+    plugin_ids
+        .iter()
+        .enumerate()
+        .map(move |(i, plugin_id)| (plugin_id.as_str(), len - i as i64))
+}
+
+enum ConfigKey<'a> {
+    LogLevel,
+    Enabled(&'a str),
+    Priority(&'a str),
+    PluginConfig(&'a str, &'a str),
+}
+
+// Plugin ids may contain dots, e.g. `builtin.dynamicmacro`. So the key is parsed from the end.
+fn parse_key(key: &str) -> anyhow::Result<ConfigKey<'_>> {
+    if key == "log_level" {
+        return Ok(ConfigKey::LogLevel);
+    }
+    if let Some(rest) = key.strip_prefix("plugins.") {
+        if let Some((plugin_id, name)) = rest.rsplit_once(".config.") {
+            return Ok(ConfigKey::PluginConfig(plugin_id, name));
+        }
+        if let Some(plugin_id) = rest.strip_suffix(".enabled") {
+            return Ok(ConfigKey::Enabled(plugin_id));
+        }
+        if let Some(plugin_id) = rest.strip_suffix(".priority") {
+            return Ok(ConfigKey::Priority(plugin_id));
+        }
+    }
+    Err(anyhow!(
+        "Unknown configuration key: `{}`. Use `log_level`, `plugins.<id>.enabled`, `plugins.<id>.priority` or `plugins.<id>.config.<name>`",
+        key
+    ))
 }
 
 impl Default for AppConfig {
@@ -84,3 +214,57 @@ impl Default for AppConfig {
         }
     }
 }
+
+// Holder to put the configuration into the boa `Context`. None reads config.json every time
+// driver.js asks for it.
+#[derive(Trace, Finalize, JsData, Default)]
+pub(crate) struct ConfigData {
+    #[unsafe_ignore_trace]
+    pub(crate) config: Option<AppConfig>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_and_set_value() -> anyhow::Result<()> {
+        let mut config = AppConfig::default();
+        assert_eq!(config.get_value("log_level")?, Some("info".to_string()));
+        assert_eq!(
+            config.get_value("plugins.builtin.dynamicmacro.config.hotkey")?,
+            None
+        );
+
+        config.set_value("log_level", "debug")?;
+        config.set_value("plugins.builtin.dynamicmacro.config.hotkey", "C-y")?;
+        config.set_value("plugins.builtin.dynamicmacro.enabled", "false")?;
+
+        assert_eq!(config.get_value("log_level")?, Some("debug".to_string()));
+        assert_eq!(
+            config.get_value("plugins.builtin.dynamicmacro.config.hotkey")?,
+            Some("C-y".to_string())
+        );
+        assert_eq!(
+            config.get_value("plugins.builtin.dynamicmacro.enabled")?,
+            Some("false".to_string())
+        );
+
+        config.disable_plugin("foo", "Too slow");
+        assert_eq!(config.get_value("plugins.foo.enabled")?, Some("false".to_string()));
+        config.set_value("plugins.foo.enabled", "true")?;
+        assert_eq!(config.plugins.as_ref().unwrap()["foo"].disabled_reason, None);
+
+        config.set_value("plugins.foo.priority", "-5")?;
+        assert_eq!(config.get_value("plugins.foo.priority")?, Some("-5".to_string()));
+        assert!(config.set_value("plugins.foo.priority", "high").is_err());
+        config.set_plugin_order(&["b".to_string(), "foo".to_string()]);
+        assert_eq!(config.get_value("plugins.b.priority")?, Some("2".to_string()));
+        assert_eq!(config.get_value("plugins.foo.priority")?, Some("1".to_string()));
+
+        assert!(config.set_value("plugins.foo.enabled", "yes").is_err());
+        assert!(config.get_value("unknown").is_err());
+        assert!(config.get_value("plugins.foo").is_err());
+        Ok(())
+    }
+}
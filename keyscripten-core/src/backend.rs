@@ -0,0 +1,92 @@
+use crate::cg_constants::{CGEventFlags, CGEventType, CGKeyCode};
+use crate::js::JS;
+use crate::keycode::text_keystrokes;
+use boa_gc::{Finalize, Trace};
+use boa_engine::JsData;
+use std::sync::Arc;
+
+/// Every event injected by this application is tagged with this value, so that the capture side
+/// can recognize it and doesn't feed it back to the JS handlers.
+pub const USER_DATA_FROM_THIS_APP: i64 = 5963;
+
+/// Platform specific implementation of the key capture and key injection.
+///
+/// Implementations must tag injected keyboard events with [`USER_DATA_FROM_THIS_APP`] (or an
+/// equivalent marker), and must not pass tagged events to [`JS::send_event`].
+pub trait InputBackend: Send + Sync {
+    /// Installs the event hook. Captured events are passed to `js`.
+    fn grab_setup(&self, js: JS) -> anyhow::Result<()>;
+
+    /// Processes captured events on the current thread. This method doesn't return.
+    fn grab_run(&self);
+
+    fn send_keyboard_event(
+        &self,
+        keycode: CGKeyCode,
+        flags: CGEventFlags,
+        keydown: bool,
+    ) -> anyhow::Result<()>;
+
+    fn send_flags_changed_event(&self, flags: CGEventFlags) -> anyhow::Result<()>;
+
+    /// Types `text`.
+    ///
+    /// This default implementation presses the keys of the US layout for each character, so it
+    /// fails on characters which aren't on the keyboard. Nothing is typed in that case. Backends
+    /// which can attach a unicode string to keyboard events override it.
+    fn send_text(&self, text: &str) -> anyhow::Result<()> {
+        let keystrokes = text_keystrokes(text)?;
+        for (keycode, flags) in &keystrokes {
+            self.send_keyboard_event(*keycode, *flags, true)?;
+            self.send_keyboard_event(*keycode, *flags, false)?;
+        }
+        if keystrokes.iter().any(|(_, flags)| *flags != 0) {
+            self.send_flags_changed_event(0)?;
+        }
+        Ok(())
+    }
+
+    /// Posts a mouse button, movement or drag event. The cursor stays where it is if `position`
+    /// is None.
+    fn send_mouse_event(
+        &self,
+        cg_event_type: CGEventType,
+        button: i64,
+        position: Option<(f64, f64)>,
+        flags: CGEventFlags,
+    ) -> anyhow::Result<()>;
+
+    /// Posts a scroll wheel event. Deltas are in lines; positive `delta_y` scrolls up.
+    fn send_scroll_event(
+        &self,
+        delta_x: i64,
+        delta_y: i64,
+        flags: CGEventFlags,
+    ) -> anyhow::Result<()>;
+}
+
+/// Returns the backend for the running platform.
+#[cfg(target_os = "macos")]
+pub fn default_backend() -> anyhow::Result<Arc<dyn InputBackend>> {
+    Ok(Arc::new(crate::grab::CoreGraphicsBackend::new()))
+}
+
+#[cfg(target_os = "linux")]
+pub fn default_backend() -> anyhow::Result<Arc<dyn InputBackend>> {
+    Ok(Arc::new(crate::linux_backend::EvdevBackend::new()?))
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+pub fn default_backend() -> anyhow::Result<Arc<dyn InputBackend>> {
+    Err(anyhow::anyhow!(
+        "There's no input backend for this platform: {}",
+        std::env::consts::OS
+    ))
+}
+
+// Holder to put the backend into the boa `Context`, so builtin functions can reach it.
+#[derive(Trace, Finalize, JsData)]
+pub(crate) struct BackendData {
+    #[unsafe_ignore_trace]
+    pub(crate) backend: Arc<dyn InputBackend>,
+}
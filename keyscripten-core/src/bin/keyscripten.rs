@@ -0,0 +1,182 @@
+use anyhow::anyhow;
+use clap::{Parser, Subcommand};
+use keyscripten_core::app_config::AppConfig;
+use keyscripten_core::backend::default_backend;
+use keyscripten_core::check::check_plugins;
+use keyscripten_core::js::JS;
+use keyscripten_core::plugin::Plugins;
+use std::sync::Arc;
+
+/// Headless interface of KeyScripten. Runs the key handler without the GUI, and manages plugins and
+/// configuration.
+#[derive(Parser)]
+#[command(name = "keyscripten", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Capture the keyboard and run the plugins, without the GUI.
+    Run,
+    /// Manage user plugins.
+    Plugin {
+        #[command(subcommand)]
+        command: PluginCommand,
+    },
+    /// Print the configuration schema of the loaded plugins as JSON.
+    Schema {
+        /// Print the schema of this plugin only.
+        #[arg(long)]
+        plugin: Option<String>,
+    },
+    /// Get or set configuration values.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Load every plugin file without capturing the keyboard, and report problems.
+    /// Exits with status 1 if any problem is found.
+    Check {
+        /// Plugin directory to check. Defaults to the plugin directory of the app.
+        #[arg(long)]
+        dir: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum PluginCommand {
+    /// List the plugin files.
+    List,
+    /// Create a new plugin from the template.
+    Add {
+        plugin_id: String,
+        name: String,
+        #[arg(long, default_value = "")]
+        description: String,
+    },
+    /// Delete the plugin file which registers the plugin.
+    Delete { plugin_id: String },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print the value. Keys: `log_level`, `plugins.<id>.enabled`, `plugins.<id>.priority`,
+    /// `plugins.<id>.config.<name>`.
+    Get { key: String },
+    /// Update the value and save the configuration file.
+    Set { key: String, value: String },
+}
+
+fn build_js() -> anyhow::Result<JS> {
+    let plugins = Plugins::new()?;
+    let mut js = JS::new(None, None, Some(plugins), None)?;
+    js.load_user_scripts()?;
+    Ok(js)
+}
+
+fn run() -> anyhow::Result<()> {
+    let backend = default_backend()?;
+    let plugins = Plugins::new()?;
+    let mut js = JS::new(None, None, Some(plugins), Some(Arc::clone(&backend)))?;
+    js.load_user_scripts()?;
+
+    backend.grab_setup(js)?;
+    backend.grab_run();
+    Ok(())
+}
+
+fn plugin(command: PluginCommand) -> anyhow::Result<()> {
+    let plugins = Plugins::new()?;
+    match command {
+        PluginCommand::List => {
+            for filename in plugins.list()? {
+                println!("{}", filename);
+            }
+        }
+        PluginCommand::Add {
+            plugin_id,
+            name,
+            description,
+        } => {
+            plugins.add(plugin_id, name, description)?;
+        }
+        PluginCommand::Delete { plugin_id } => {
+            // Files without a manifest are found only by running them.
+            let filename = match plugins.find_by_plugin_id(&plugin_id)? {
+                Some(filename) => filename,
+                None => build_js()?
+                    .get_filename_by_plugin_id(&plugin_id)
+                    .ok_or_else(|| anyhow!("There's no plugin file found for {}", plugin_id))?,
+            };
+            plugins.delete(filename)?;
+        }
+    }
+    Ok(())
+}
+
+fn schema(plugin_id: Option<String>) -> anyhow::Result<()> {
+    let mut js = build_js()?;
+    let schema_list = js.get_config_schema()?;
+    let json = match plugin_id {
+        Some(plugin_id) => {
+            let schema = schema_list
+                .plugins
+                .iter()
+                .find(|plugin| plugin.id == plugin_id)
+                .ok_or_else(|| anyhow!("Cannot load configuration schema for {}", plugin_id))?;
+            serde_json::to_string_pretty(schema)?
+        }
+        None => serde_json::to_string_pretty(&schema_list)?,
+    };
+    println!("{}", json);
+    Ok(())
+}
+
+fn config(command: ConfigCommand) -> anyhow::Result<()> {
+    let mut config = AppConfig::load()?;
+    match command {
+        ConfigCommand::Get { key } => match config.get_value(&key)? {
+            Some(value) => println!("{}", value),
+            None => return Err(anyhow!("`{}` is not set", key)),
+        },
+        ConfigCommand::Set { key, value } => {
+            config.set_value(&key, &value)?;
+            config.save()?;
+        }
+    }
+    Ok(())
+}
+
+fn check(dir: Option<String>) -> anyhow::Result<()> {
+    let plugins = match dir {
+        Some(dir) => Plugins::new_with_basedir(dir),
+        None => Plugins::new()?,
+    };
+    let report = check_plugins(&plugins)?;
+    println!("{}", report);
+    if !report.is_ok() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    let log_level = AppConfig::load()
+        .map(|config| config.log_level)
+        .unwrap_or_else(|_| "info".to_string());
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(log_level))
+        .target(env_logger::Target::Stderr)
+        .init();
+
+    match cli.command {
+        Command::Run => run(),
+        Command::Plugin { command } => plugin(command),
+        Command::Schema { plugin } => schema(plugin),
+        Command::Config { command } => config(command),
+        Command::Check { dir } => check(dir),
+    }
+}
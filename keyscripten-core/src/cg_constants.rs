@@ -1,18 +1,52 @@
-use apple_sys::CoreGraphics::{
-    CGEventField, CGEventFlags, CGEventSourceStateID, CGEventTapLocation, CGEventTapOptions,
-    CGEventTapPlacement, CGEventType,
-};
+#![allow(non_upper_case_globals)]
+
+// These mirror the CoreGraphics typedefs so that the constants (and the JS API built on top of
+// them) are available on every platform, not only where apple-sys can be linked.
+pub type CGKeyCode = u16;
+pub type CGEventFlags = u64;
+pub type CGEventType = u32;
+pub type CGEventField = u32;
+pub type CGEventTapLocation = u32;
+pub type CGEventTapPlacement = u32;
+pub type CGEventTapOptions = u32;
+pub type CGEventSourceStateID = i32;
+pub type CGMouseButton = u32;
+pub type CGScrollEventUnit = u32;
+
 
 // CGEventType
 pub const kCGEventNull: CGEventType = 0;
 pub const kCGEventKeyDown: CGEventType = 10;
 pub const kCGEventKeyUp: CGEventType = 11;
 pub const kCGEventFlagsChanged: CGEventType = 12;
+pub const kCGEventLeftMouseDown: CGEventType = 1;
+pub const kCGEventLeftMouseUp: CGEventType = 2;
+pub const kCGEventRightMouseDown: CGEventType = 3;
+pub const kCGEventRightMouseUp: CGEventType = 4;
+pub const kCGEventMouseMoved: CGEventType = 5;
+pub const kCGEventLeftMouseDragged: CGEventType = 6;
+pub const kCGEventRightMouseDragged: CGEventType = 7;
+pub const kCGEventScrollWheel: CGEventType = 22;
+pub const kCGEventOtherMouseDown: CGEventType = 25;
+pub const kCGEventOtherMouseUp: CGEventType = 26;
+pub const kCGEventOtherMouseDragged: CGEventType = 27;
 
 // CGEventField
+pub const kCGMouseEventButtonNumber: CGEventField = 3;
+pub const kCGScrollWheelEventDeltaAxis1: CGEventField = 11;
+pub const kCGScrollWheelEventDeltaAxis2: CGEventField = 12;
+pub const kCGKeyboardEventAutorepeat: CGEventField = 8;
 pub const kCGKeyboardEventKeycode: CGEventField = 9;
 pub const kCGEventSourceUserData: CGEventField = 100;
 
+// CGMouseButton
+pub const kCGMouseButtonLeft: CGMouseButton = 0;
+pub const kCGMouseButtonRight: CGMouseButton = 1;
+pub const kCGMouseButtonCenter: CGMouseButton = 2;
+
+// CGScrollEventUnit
+pub const kCGScrollEventUnitLine: CGScrollEventUnit = 1;
+
 // CGEventTapLocation
 pub const kCGHIDEventTap: CGEventTapLocation = 0;
 
@@ -35,3 +69,14 @@ pub const kCGEventFlagMaskHelp: CGEventFlags = 0x00400000;
 pub const kCGEventFlagMaskSecondaryFn: CGEventFlags = 0x00800000;
 pub const kCGEventFlagMaskNumericPad: CGEventFlags = 0x00200000;
 pub const kCGEventFlagMaskNonCoalesced: CGEventFlags = 0x01000000;
+
+// Device dependent flags (NX_DEVICE*KEYMASK in IOKit). They tell which side of the modifier is
+// pressed, in addition to the device independent flags above.
+pub const kCGEventFlagMaskLeftControl: CGEventFlags = 0x00000001;
+pub const kCGEventFlagMaskLeftShift: CGEventFlags = 0x00000002;
+pub const kCGEventFlagMaskRightShift: CGEventFlags = 0x00000004;
+pub const kCGEventFlagMaskLeftCommand: CGEventFlags = 0x00000008;
+pub const kCGEventFlagMaskRightCommand: CGEventFlags = 0x00000010;
+pub const kCGEventFlagMaskLeftAlternate: CGEventFlags = 0x00000020;
+pub const kCGEventFlagMaskRightAlternate: CGEventFlags = 0x00000040;
+pub const kCGEventFlagMaskRightControl: CGEventFlags = 0x00002000;
@@ -0,0 +1,292 @@
+use crate::app_config::AppConfig;
+use crate::hotkey::KeySequence;
+use crate::js::JS;
+use crate::plugin::Plugins;
+use anyhow::anyhow;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+
+// Config types supported by `buildConfig` in driver.js.
+const CONFIG_TYPES: &[&str] = &["hotkey", "string", "integer"];
+
+// Records every id passed to `registerPlugin`, including the ones registered twice.
+const CHECK_PRELUDE: &str = r#"
+const $$checkRegisteredIds = [];
+{
+	const register = $$registerPlugin;
+	$$registerPlugin = (realm, ...args) => {
+		$$checkRegisteredIds.push(args[0]);
+		return register(realm, ...args);
+	};
+}
+"#;
+
+#[derive(Debug)]
+pub struct CheckProblem {
+    pub filename: String,
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    pub checked: usize,
+    pub problems: Vec<CheckProblem>,
+}
+
+impl CheckReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    fn add(&mut self, filename: &str, message: String) {
+        self.problems.push(CheckProblem {
+            filename: filename.to_string(),
+            message,
+        });
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for problem in &self.problems {
+            writeln!(f, "{}: {}", problem.filename, problem.message)?;
+        }
+        write!(
+            f,
+            "{} files checked, {} problems",
+            self.checked,
+            self.problems.len()
+        )
+    }
+}
+
+/// Loads every plugin file into its own fresh `JS`, and reports the mistakes which otherwise only
+/// show up at runtime.
+pub fn check_plugins(plugins: &Plugins) -> anyhow::Result<CheckReport> {
+    let mut report = CheckReport::default();
+
+    // plugin id -> the file which registered it first. Bundled plugins are registered by driver.
+    let mut registered: HashMap<String, String> = HashMap::new();
+    for plugin_id in JS::new_with_config(None, None, None, None, AppConfig::default())?
+        .loaded_plugins()?
+    {
+        registered.insert(plugin_id, "(bundled)".to_string());
+    }
+
+    for filename in plugins.list()? {
+        report.checked += 1;
+        let snippet = match plugins.read(filename.clone()) {
+            Ok(snippet) => snippet,
+            Err(err) => {
+                report.add(&filename, format!("{}", err));
+                continue;
+            }
+        };
+        let filename = snippet.filename.as_str();
+        if let Some(err) = &snippet.manifest_error {
+            report.add(filename, err.clone());
+            continue;
+        }
+
+        // The default configuration, so that the result doesn't depend on the user's one.
+        let mut js = JS::new_with_config(None, None, None, None, AppConfig::default())?;
+        js.use_storage(None);
+        if let Err(err) = js.check_syntax(&snippet.src) {
+            report.add(filename, format!("{}", err));
+            continue;
+        }
+
+        js.eval(CHECK_PRELUDE.to_string())?;
+        match js.run_plugin(&snippet.src, snippet.manifest.as_ref()) {
+            Ok(Ok(_)) => {}
+            Ok(Err(err)) => report.add(filename, format!("Exception while loading: {}", err)),
+            Err(err) => report.add(filename, format!("Exception while loading: {}", err)),
+        }
+
+        let ids = registered_ids(&mut js)?;
+        if let Some(manifest) = &snippet.manifest {
+            if !ids
+                .iter()
+                .any(|id| id.as_str() == Some(manifest.id.as_str()))
+            {
+                report.add(
+                    filename,
+                    format!(
+                        "The manifest declares `{}`, but it isn't registered",
+                        manifest.id
+                    ),
+                );
+            }
+        }
+        for id in &ids {
+            let Some(id) = id.as_str() else {
+                report.add(filename, format!("Plugin id must be a string: {}", id));
+                continue;
+            };
+            match registered.get(id) {
+                Some(other) => report.add(
+                    filename,
+                    format!("Duplicate plugin id `{}`, already registered by {}", id, other),
+                ),
+                None => {
+                    registered.insert(id.to_string(), filename.to_string());
+                }
+            }
+        }
+
+        for plugin in config_schema(&mut js)? {
+            if !ids.contains(&plugin["id"]) {
+                continue;
+            }
+            check_schema(&mut report, filename, &plugin);
+        }
+    }
+    Ok(report)
+}
+
+fn registered_ids(js: &mut JS) -> anyhow::Result<Vec<Value>> {
+    let json = js.eval("JSON.stringify($$checkRegisteredIds)".to_string())?;
+    let json = json
+        .as_string()
+        .ok_or_else(|| anyhow!("$$checkRegisteredIds isn't serializable"))?
+        .to_std_string_escaped();
+    Ok(serde_json::from_str(&json)?)
+}
+
+// `JS::get_config_schema` can't be used here. It fails on the schema we want to report.
+fn config_schema(js: &mut JS) -> anyhow::Result<Vec<Value>> {
+    let json = js.eval("$$getConfigSchema()".to_string())?;
+    let json = json
+        .as_string()
+        .ok_or_else(|| anyhow!("$$getConfigSchema doesn't return string"))?
+        .to_std_string_escaped();
+    let schema: Value = serde_json::from_str(&json)?;
+    match schema["plugins"].as_array() {
+        Some(plugins) => Ok(plugins.clone()),
+        None => Err(anyhow!("Unexpected result from $$getConfigSchema: {}", json)),
+    }
+}
+
+fn check_schema(report: &mut CheckReport, filename: &str, plugin: &Value) {
+    let id = plugin["id"].as_str().unwrap_or_default();
+    let Some(items) = plugin["config"].as_array() else {
+        report.add(
+            filename,
+            format!("Config schema of `{}` must be an array", id),
+        );
+        return;
+    };
+
+    for item in items {
+        let name = item["name"].as_str().unwrap_or("(unnamed)");
+        let item_type = item["type"].as_str().unwrap_or_default();
+        if !CONFIG_TYPES.contains(&item_type) {
+            report.add(
+                filename,
+                format!(
+                    "Unknown type for `{}`({}): `{}`. Supported types are {}",
+                    id,
+                    name,
+                    item_type,
+                    CONFIG_TYPES.join(", ")
+                ),
+            );
+            continue;
+        }
+
+        if item_type == "hotkey" {
+            let default = item["default"].as_str().unwrap_or_default();
+            if let Err(err) = KeySequence::from_str(default) {
+                report.add(
+                    filename,
+                    format!(
+                        "Invalid hotkey default for `{}`({}): `{}`: {}",
+                        id, name, default, err
+                    ),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn plugin_src(id: &str, config: &str) -> String {
+        format!(
+            r#"registerPlugin("{}", "name", "description", (event, config) => true, {});"#,
+            id, config
+        )
+    }
+
+    #[test]
+    fn test_check_plugins() -> anyhow::Result<()> {
+        let temp_dir = TempDir::with_prefix("check")?;
+        let plugins = Plugins::new_with_basedir(temp_dir.path().to_str().unwrap().to_string());
+
+        plugins.add(
+            "good".to_string(),
+            "Good".to_string(),
+            "description".to_string(),
+        )?;
+        plugins.write("syntax.js".to_string(), "registerPlugin(".to_string())?;
+        plugins.write(
+            "throw.js".to_string(),
+            "throw new Error('oops');".to_string(),
+        )?;
+        plugins.write("zdup.js".to_string(), plugin_src("good", "[]"))?;
+        plugins.write(
+            "type.js".to_string(),
+            plugin_src(
+                "type",
+                r#"[{"name": "n", "type": "float", "default": "1"}]"#,
+            ),
+        )?;
+        plugins.write(
+            "hotkey.js".to_string(),
+            plugin_src(
+                "hotkey",
+                r#"[{"name": "k", "type": "hotkey", "default": "C-unknown"}]"#,
+            ),
+        )?;
+
+        plugins.write(
+            "manifest.js".to_string(),
+            format!(
+                "// ==KeyScripten==\n// @id other\n// ==/KeyScripten==\n{}",
+                plugin_src("manifest", "[]")
+            ),
+        )?;
+        plugins.write(
+            "badmanifest.js".to_string(),
+            "// ==KeyScripten==\n// @permission storage\n// ==/KeyScripten==\n".to_string(),
+        )?;
+
+        let report = check_plugins(&plugins)?;
+        assert_eq!(report.checked, 8);
+
+        let problems: HashMap<&str, Vec<&str>> =
+            report.problems.iter().fold(HashMap::new(), |mut map, p| {
+                map.entry(p.filename.as_str())
+                    .or_default()
+                    .push(p.message.as_str());
+                map
+            });
+        assert!(!problems.contains_key("good.js"), "{}", report);
+        assert!(problems["syntax.js"][0].starts_with("Syntax error"));
+        assert!(problems["throw.js"][0].starts_with("Exception while loading: Error: oops"));
+        assert!(problems["zdup.js"][0].contains("Duplicate plugin id `good`"));
+        assert!(problems["type.js"]
+            .iter()
+            .any(|m| m.starts_with("Unknown type for `type`(n): `float`")));
+        assert!(problems["hotkey.js"]
+            .iter()
+            .any(|m| m.starts_with("Invalid hotkey default for `hotkey`(k)")));
+        assert!(problems["manifest.js"][0].contains("declares `other`"));
+        assert!(problems["badmanifest.js"][0].contains("Invalid manifest"));
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+use boa_engine::JsData;
+use boa_gc::{Finalize, Trace};
+use std::cell::Cell;
+use std::time::Instant;
+
+/// Time source of the JS runtime, in milliseconds.
+///
+/// The replay engine uses the virtual clock, so that the recorded trace runs the same regardless of
+/// how fast the machine replays it.
+#[derive(Debug, Clone, Copy)]
+pub enum Clock {
+    Monotonic(Instant),
+    Virtual(u64),
+}
+
+impl Clock {
+    pub fn now_ms(&self) -> u64 {
+        match self {
+            Clock::Monotonic(started_at) => started_at.elapsed().as_millis() as u64,
+            Clock::Virtual(now) => *now,
+        }
+    }
+
+    /// Moves the virtual clock forward to `ms`. It never goes backward.
+    pub fn advance_to(&mut self, ms: u64) {
+        if let Clock::Virtual(now) = self {
+            *now = (*now).max(ms);
+        }
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock::Monotonic(Instant::now())
+    }
+}
+
+// Holder to put the clock into the boa `Context`, so `$$now()` and timers read the same clock as
+// `JS::now_ms`.
+#[derive(Trace, Finalize, JsData, Default)]
+pub(crate) struct ClockData {
+    #[unsafe_ignore_trace]
+    pub(crate) clock: Cell<Clock>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_virtual_clock() {
+        let mut clock = Clock::Virtual(0);
+        clock.advance_to(100);
+        assert_eq!(clock.now_ms(), 100);
+        clock.advance_to(50);
+        assert_eq!(clock.now_ms(), 100);
+    }
+}
@@ -1,48 +1,323 @@
-use apple_sys::CoreGraphics::{CGEventGetFlags, CGEventGetIntegerValueField, CGEventRef, CGEventType};
 use crate::cg_constants::{
-    kCGEventFlagsChanged, kCGEventKeyDown, kCGEventKeyUp, kCGKeyboardEventKeycode,
+    kCGEventFlagsChanged, kCGEventKeyDown, kCGEventKeyUp, kCGEventLeftMouseDown,
+    kCGEventLeftMouseDragged, kCGEventLeftMouseUp, kCGEventMouseMoved, kCGEventOtherMouseDown,
+    kCGEventOtherMouseDragged, kCGEventOtherMouseUp, kCGEventRightMouseDown,
+    kCGEventRightMouseDragged, kCGEventRightMouseUp, kCGEventScrollWheel, CGEventType,
+};
+use crate::cg_constants::{
+    kCGEventFlagMaskAlphaShift, kCGEventFlagMaskAlternate, kCGEventFlagMaskCommand,
+    kCGEventFlagMaskControl, kCGEventFlagMaskLeftAlternate, kCGEventFlagMaskLeftCommand,
+    kCGEventFlagMaskLeftControl, kCGEventFlagMaskLeftShift, kCGEventFlagMaskRightAlternate,
+    kCGEventFlagMaskRightCommand, kCGEventFlagMaskRightControl, kCGEventFlagMaskRightShift,
+    kCGEventFlagMaskSecondaryFn, kCGEventFlagMaskShift, CGEventFlags,
+};
+#[cfg(target_os = "macos")]
+use apple_sys::CoreGraphics::{
+    CGEventGetFlags, CGEventGetIntegerValueField, CGEventGetLocation, CGEventRef,
+};
+#[cfg(target_os = "macos")]
+use crate::cg_constants::{
+    kCGKeyboardEventAutorepeat, kCGKeyboardEventKeycode, kCGMouseEventButtonNumber,
+    kCGScrollWheelEventDeltaAxis1, kCGScrollWheelEventDeltaAxis2,
 };
 use serde::{Deserialize, Serialize};
 use std::time::SystemTime;
 use std::time::UNIX_EPOCH;
 
+// Event types handled by this app, and their names in `Event` and JS.
+const EVENT_TYPES: &[(CGEventType, &str)] = &[
+    (kCGEventKeyDown, "keyDown"),
+    (kCGEventKeyUp, "keyUp"),
+    (kCGEventFlagsChanged, "flagsChanged"),
+    (kCGEventLeftMouseDown, "leftMouseDown"),
+    (kCGEventLeftMouseUp, "leftMouseUp"),
+    (kCGEventRightMouseDown, "rightMouseDown"),
+    (kCGEventRightMouseUp, "rightMouseUp"),
+    (kCGEventOtherMouseDown, "otherMouseDown"),
+    (kCGEventOtherMouseUp, "otherMouseUp"),
+    (kCGEventMouseMoved, "mouseMoved"),
+    (kCGEventLeftMouseDragged, "leftMouseDragged"),
+    (kCGEventRightMouseDragged, "rightMouseDragged"),
+    (kCGEventOtherMouseDragged, "otherMouseDragged"),
+    (kCGEventScrollWheel, "scrollWheel"),
+];
+
 pub fn event_type(cg_event_type: CGEventType) -> &'static str {
-    #[allow(non_upper_case_globals)]
-    match cg_event_type {
-        kCGEventKeyDown => "keyDown",
-        kCGEventKeyUp => "keyUp",
-        kCGEventFlagsChanged => "flagsChanged",
-        _ => "unknown",
+    EVENT_TYPES
+        .iter()
+        .find(|(t, _)| *t == cg_event_type)
+        .map(|(_, name)| *name)
+        .unwrap_or("unknown")
+}
+
+pub fn cg_event_type(event_type: &str) -> Option<CGEventType> {
+    EVENT_TYPES
+        .iter()
+        .find(|(_, name)| *name == event_type)
+        .map(|(t, _)| *t)
+}
+
+/// Every CGEventType the tap subscribes to, except the motion events.
+pub fn cg_event_types() -> impl Iterator<Item = CGEventType> {
+    EVENT_TYPES
+        .iter()
+        .map(|(t, _)| *t)
+        .filter(|t| !is_motion_event(*t))
+}
+
+/// The pointer movements. They come at the polling rate of the pointer device, so the tap
+/// subscribes to them only while a plugin asks for them.
+pub fn motion_event_types() -> impl Iterator<Item = CGEventType> {
+    EVENT_TYPES
+        .iter()
+        .map(|(t, _)| *t)
+        .filter(|t| is_motion_event(*t))
+}
+
+pub fn is_motion_event(cg_event_type: CGEventType) -> bool {
+    matches!(
+        event_type(cg_event_type),
+        "mouseMoved" | "leftMouseDragged" | "rightMouseDragged" | "otherMouseDragged"
+    )
+}
+
+pub fn is_mouse_event(cg_event_type: CGEventType) -> bool {
+    !matches!(
+        event_type(cg_event_type),
+        "keyDown" | "keyUp" | "flagsChanged" | "unknown"
+    )
+}
+
+/// Mouse specific part of the event.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Mouse {
+    /// Cursor position in global display coordinates.
+    pub x: f64,
+    pub y: f64,
+    /// 0 = left, 1 = right, 2 = center, and so on.
+    pub button: i64,
+    /// Scroll amount in lines. Only set on scrollWheel events.
+    pub delta_x: i64,
+    pub delta_y: i64,
+}
+
+/// Modifier state decoded from `CGEventFlags`. The `left_*`/`right_*` fields are only set when the
+/// backend reports the device dependent flags; synthesized events usually don't have them.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub cmd: bool,
+    #[serde(rename = "fn")]
+    pub fn_key: bool,
+    pub caps_lock: bool,
+    pub left_shift: bool,
+    pub right_shift: bool,
+    pub left_ctrl: bool,
+    pub right_ctrl: bool,
+    pub left_alt: bool,
+    pub right_alt: bool,
+    pub left_cmd: bool,
+    pub right_cmd: bool,
+}
+
+impl Modifiers {
+    pub fn from_flags(flags: CGEventFlags) -> Self {
+        let has = |mask: CGEventFlags| flags & mask != 0;
+        Modifiers {
+            shift: has(kCGEventFlagMaskShift),
+            ctrl: has(kCGEventFlagMaskControl),
+            alt: has(kCGEventFlagMaskAlternate),
+            cmd: has(kCGEventFlagMaskCommand),
+            fn_key: has(kCGEventFlagMaskSecondaryFn),
+            caps_lock: has(kCGEventFlagMaskAlphaShift),
+            left_shift: has(kCGEventFlagMaskLeftShift),
+            right_shift: has(kCGEventFlagMaskRightShift),
+            left_ctrl: has(kCGEventFlagMaskLeftControl),
+            right_ctrl: has(kCGEventFlagMaskRightControl),
+            left_alt: has(kCGEventFlagMaskLeftAlternate),
+            right_alt: has(kCGEventFlagMaskRightAlternate),
+            left_cmd: has(kCGEventFlagMaskLeftCommand),
+            right_cmd: has(kCGEventFlagMaskRightCommand),
+        }
+    }
+
+    /// Pairs of the property name exposed to JS and its value.
+    pub fn entries(&self) -> [(&'static str, bool); 14] {
+        [
+            ("shift", self.shift),
+            ("ctrl", self.ctrl),
+            ("alt", self.alt),
+            ("cmd", self.cmd),
+            ("fn", self.fn_key),
+            ("capsLock", self.caps_lock),
+            ("leftShift", self.left_shift),
+            ("rightShift", self.right_shift),
+            ("leftCtrl", self.left_ctrl),
+            ("rightCtrl", self.right_ctrl),
+            ("leftAlt", self.left_alt),
+            ("rightAlt", self.right_alt),
+            ("leftCmd", self.left_cmd),
+            ("rightCmd", self.right_cmd),
+        ]
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Event {
     pub event_type: String,
     pub keycode: i64,
+    /// Modifier flags at the time of the event. Set on every event type, not only flagsChanged.
     pub flags: u64,
+    // Traces recorded before these fields existed don't have them.
+    #[serde(default)]
+    pub modifiers: Modifiers,
+    #[serde(default)]
+    pub autorepeat: bool,
+    /// Set on mouse events only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mouse: Option<Mouse>,
+    /// Unicode string attached to an injected keyboard event by `sendText`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// Milliseconds since the UNIX epoch.
     pub timestamp: u64,
 }
 
-#[allow(clippy::not_unsafe_ptr_arg_deref)]
 impl Event {
+    pub fn new(cg_event_type: CGEventType, keycode: i64, flags: u64) -> Self {
+        let timestamp = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(n) => n.as_millis() as u64,
+            Err(_) => 0,
+        };
+
+        Event {
+            timestamp,
+            event_type: event_type(cg_event_type).to_string(),
+            keycode,
+            flags,
+            modifiers: Modifiers::from_flags(flags),
+            autorepeat: false,
+            mouse: None,
+            text: None,
+        }
+    }
+
+    pub fn new_mouse(cg_event_type: CGEventType, mouse: Mouse, flags: u64) -> Self {
+        Event {
+            mouse: Some(mouse),
+            ..Event::new(cg_event_type, 0, flags)
+        }
+    }
+
+    pub fn with_autorepeat(mut self, autorepeat: bool) -> Self {
+        self.autorepeat = autorepeat;
+        self
+    }
+
+    pub fn with_text(mut self, text: &str) -> Self {
+        self.text = Some(text.to_string());
+        self
+    }
+
+    /// Returns a copy with the type, keycode and flags replaced. Timestamp and autorepeat are kept.
+    pub fn rewrite(&self, cg_event_type: CGEventType, keycode: i64, flags: u64) -> Self {
+        Event {
+            event_type: event_type(cg_event_type).to_string(),
+            keycode,
+            flags,
+            modifiers: Modifiers::from_flags(flags),
+            ..self.clone()
+        }
+    }
+
+    /// true if both events have the same type, keycode, flags, mouse state and text. Timestamps
+    /// are ignored.
+    pub fn same_input(&self, other: &Event) -> bool {
+        self.event_type == other.event_type
+            && self.keycode == other.keycode
+            && self.flags == other.flags
+            && self.mouse == other.mouse
+            && self.text == other.text
+    }
+
+    #[cfg(target_os = "macos")]
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
     pub fn from_cf(cg_event_type: CGEventType, cg_event_ref: CGEventRef) -> Self {
         unsafe {
             let keycode =
                 CGEventGetIntegerValueField(cg_event_ref, kCGKeyboardEventKeycode);
+            let autorepeat =
+                CGEventGetIntegerValueField(cg_event_ref, kCGKeyboardEventAutorepeat) != 0;
             let flags = CGEventGetFlags(cg_event_ref);
-
-            let timestamp = match SystemTime::now().duration_since(UNIX_EPOCH) {
-                Ok(n) => n.as_secs(),
-                Err(_) => 0,
-            };
-
-            Event {
-                timestamp,
-                event_type: event_type(cg_event_type).to_string(),
-                keycode,
-                flags,
+            if is_mouse_event(cg_event_type) {
+                let location = CGEventGetLocation(cg_event_ref);
+                let mouse = Mouse {
+                    x: location.x,
+                    y: location.y,
+                    button: CGEventGetIntegerValueField(cg_event_ref, kCGMouseEventButtonNumber),
+                    delta_x: CGEventGetIntegerValueField(
+                        cg_event_ref,
+                        kCGScrollWheelEventDeltaAxis2,
+                    ),
+                    delta_y: CGEventGetIntegerValueField(
+                        cg_event_ref,
+                        kCGScrollWheelEventDeltaAxis1,
+                    ),
+                };
+                return Event::new_mouse(cg_event_type, mouse, flags);
             }
+            Event::new(cg_event_type, keycode, flags).with_autorepeat(autorepeat)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_modifiers() {
+        let event = Event::new(
+            kCGEventKeyDown,
+            0,
+            kCGEventFlagMaskShift | kCGEventFlagMaskRightShift | kCGEventFlagMaskSecondaryFn,
+        );
+        assert!(event.modifiers.shift);
+        assert!(event.modifiers.right_shift);
+        assert!(!event.modifiers.left_shift);
+        assert!(event.modifiers.fn_key);
+        assert!(!event.modifiers.ctrl);
+        assert!(!event.autorepeat);
+
+        let json = serde_json::to_value(&event.modifiers).unwrap();
+        assert_eq!(json["fn"], true);
+        assert_eq!(json["rightShift"], true);
+        assert_eq!(json["capsLock"], false);
+    }
+
+    #[test]
+    fn test_event_types() {
+        assert_eq!(event_type(kCGEventOtherMouseDown), "otherMouseDown");
+        assert_eq!(cg_event_type("scrollWheel"), Some(kCGEventScrollWheel));
+        assert_eq!(cg_event_type("unknown"), None);
+        assert!(is_mouse_event(kCGEventMouseMoved));
+        assert!(is_motion_event(kCGEventMouseMoved));
+        assert!(!is_motion_event(kCGEventLeftMouseDown));
+        assert_eq!(cg_event_types().count() + motion_event_types().count(), EVENT_TYPES.len());
+        assert!(!is_mouse_event(kCGEventKeyDown));
+        assert!(!is_mouse_event(0));
+    }
+
+    #[test]
+    fn test_deserialize_without_modifiers() -> anyhow::Result<()> {
+        let event: Event = serde_json::from_str(
+            r#"{"event_type":"keyDown","keycode":0,"flags":0,"timestamp":0}"#,
+        )?;
+        assert_eq!(event.modifiers, Modifiers::default());
+        assert!(!event.autorepeat);
+        Ok(())
+    }
+}
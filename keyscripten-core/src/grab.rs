@@ -1,19 +1,32 @@
 #![allow(improper_ctypes_definitions)]
 
+use crate::backend::{InputBackend, USER_DATA_FROM_THIS_APP};
+use crate::cg_constants::{CGEventFlags, CGKeyCode};
+use crate::event::{
+    cg_event_type, cg_event_types, is_motion_event, motion_event_types, Event,
+};
 use crate::js::JS;
-use crate::send::USER_DATA_FROM_THIS_APP;
+use crate::send::{
+    send_flags_changed_event, send_keyboard_event, send_mouse_event, send_scroll_event, send_text,
+};
 use anyhow::anyhow;
 use apple_sys::CoreFoundation::{
-    kCFAllocatorDefault, kCFRunLoopCommonModes, CFMachPortCreateRunLoopSource, CFRunLoopAddSource,
-    CFRunLoopGetCurrent, CFRunLoopRun,
+    kCFAllocatorDefault, kCFRunLoopCommonModes, CFAbsoluteTimeGetCurrent,
+    CFMachPortCreateRunLoopSource, CFMachPortRef, CFRunLoopAddSource, CFRunLoopAddTimer, CFRunLoopGetCurrent,
+    CFRunLoopRef, CFRunLoopRun, CFRunLoopSourceContext, CFRunLoopSourceCreate, CFRunLoopSourceRef,
+    CFRunLoopSourceSignal, CFRunLoopTimerContext, CFRunLoopTimerCreate, CFRunLoopTimerRef,
+    CFRunLoopTimerSetNextFireDate, CFRunLoopWakeUp, CGPoint,
 };
+use std::ptr::null_mut;
 use apple_sys::CoreGraphics::{
-    CGEventGetIntegerValueField, CGEventMask, CGEventRef, CGEventSetType, CGEventTapCreate,
+    CGEventGetIntegerValueField, CGEventMask, CGEventRef, CGEventSetFlags,
+    CGEventSetIntegerValueField, CGEventSetLocation, CGEventSetType, CGEventTapCreate,
     CGEventTapEnable, CGEventTapProxy, CGEventType,
 };
 use crate::cg_constants::{
-    kCGEventFlagsChanged, kCGEventKeyDown, kCGEventKeyUp, kCGEventNull, kCGEventSourceUserData,
-    kCGEventTapOptionDefault, kCGHIDEventTap, kCGHeadInsertEventTap,
+    kCGEventNull, kCGEventSourceUserData, kCGEventTapOptionDefault, kCGHIDEventTap,
+    kCGHeadInsertEventTap, kCGKeyboardEventKeycode, kCGMouseEventButtonNumber,
+    kCGScrollWheelEventDeltaAxis1, kCGScrollWheelEventDeltaAxis2,
 };
 use cocoa::base::nil;
 use cocoa::foundation::NSAutoreleasePool;
@@ -27,6 +40,77 @@ unsafe fn is_sent_from_this_app(cg_event: CGEventRef) -> bool {
     user_data == USER_DATA_FROM_THIS_APP
 }
 
+// Fire date of the timer while no JS timer is pending. About 30 years from now.
+const TIMER_IDLE_SECONDS: f64 = 1.0e9;
+
+// Shared by the event tap and the run loop timer. Both callbacks run on the run loop thread.
+struct TapState {
+    js: JS,
+    timer: CFRunLoopTimerRef,
+    // The tap of the pointer movements. Enabled only while a plugin asks for them.
+    motion_tap: CFMachPortRef,
+}
+
+// Runs the due JS timers, and moves the run loop timer to the next one.
+unsafe fn schedule_timers(state: &mut TapState) {
+    let delay = match state.js.run_timers() {
+        Ok(Some(delay_ms)) => delay_ms as f64 / 1000.0,
+        Ok(None) => TIMER_IDLE_SECONDS,
+        Err(err) => {
+            log::error!("Cannot run JS timers: {:?}", err);
+            TIMER_IDLE_SECONDS
+        }
+    };
+    CFRunLoopTimerSetNextFireDate(state.timer, CFAbsoluteTimeGetCurrent() + delay);
+}
+
+// Every pointer movement runs the plugins on this thread, so listen to them only when needed.
+unsafe fn update_motion_tap(state: &mut TapState) {
+    let enabled = match state.js.wants_mouse_motion() {
+        Ok(enabled) => enabled,
+        Err(err) => {
+            log::error!("Cannot check the plugins for mouse motion: {:?}", err);
+            false
+        }
+    };
+    CGEventTapEnable(state.motion_tap, enabled);
+}
+
+unsafe extern "C" fn timer_callback(
+    _timer: CFRunLoopTimerRef,
+    user_info: *mut ::std::os::raw::c_void,
+) {
+    let state = &mut *(user_info as *mut TapState);
+    schedule_timers(state);
+}
+
+// Signalled by `JsOperationSender`, when the UI sends an operation.
+unsafe extern "C" fn js_operation_callback(user_info: *mut ::std::os::raw::c_void) {
+    let state = &mut *(user_info as *mut TapState);
+    state.js.process_js_operations();
+    // Reloaded plugins may have added a timer, or asked for the pointer movements.
+    schedule_timers(state);
+    update_motion_tap(state);
+}
+
+// The run loop source and its run loop, to signal them from the sender thread. Both are thread
+// safe, and live as long as the process.
+struct RunLoopSignal {
+    source: CFRunLoopSourceRef,
+    run_loop: CFRunLoopRef,
+}
+
+unsafe impl Send for RunLoopSignal {}
+
+impl RunLoopSignal {
+    fn signal(&self) {
+        unsafe {
+            CFRunLoopSourceSignal(self.source);
+            CFRunLoopWakeUp(self.run_loop);
+        }
+    }
+}
+
 unsafe extern "C" fn raw_callback(
     _proxy: CGEventTapProxy,
     event_type: CGEventType,
@@ -39,14 +123,25 @@ unsafe extern "C" fn raw_callback(
         return cg_event;
     }
 
-    let js = &mut *(user_info as *mut JS);
-    match js.send_event(event_type, cg_event) {
-        Ok(b) => {
-            if !b {
-                log::debug!("Don't send keyboard event to the destination.");
-                CGEventSetType(cg_event, kCGEventNull);
+    let state = &mut *(user_info as *mut TapState);
+    let event = Event::from_cf(event_type, cg_event);
+    let result = state.js.send_event(event.clone());
+    // The plugins may have added a timer.
+    schedule_timers(state);
+    if is_motion_event(event_type) {
+        // The plugin which asked for them may have been disabled.
+        update_motion_tap(state);
+    }
+    match result {
+        Ok(Some(delivered)) => {
+            if !delivered.same_input(&event) {
+                rewrite_event(cg_event, &delivered);
             }
         }
+        Ok(None) => {
+            log::debug!("Don't send keyboard event to the destination.");
+            CGEventSetType(cg_event, kCGEventNull);
+        }
         Err(err) => {
             log::error!("Cannot call JS callback: {:?}", err);
         }
@@ -55,40 +150,183 @@ unsafe extern "C" fn raw_callback(
     cg_event
 }
 
+// Mutates the event in place, so that it keeps its position in the event stream.
+unsafe fn rewrite_event(cg_event: CGEventRef, event: &Event) {
+    log::debug!("Rewriting the event to {:?}", event);
+    if let Some(cg_event_type) = cg_event_type(&event.event_type) {
+        CGEventSetType(cg_event, cg_event_type);
+    }
+    match &event.mouse {
+        Some(mouse) => {
+            CGEventSetLocation(cg_event, CGPoint { x: mouse.x, y: mouse.y });
+            CGEventSetIntegerValueField(cg_event, kCGMouseEventButtonNumber, mouse.button);
+            CGEventSetIntegerValueField(cg_event, kCGScrollWheelEventDeltaAxis1, mouse.delta_y);
+            CGEventSetIntegerValueField(cg_event, kCGScrollWheelEventDeltaAxis2, mouse.delta_x);
+        }
+        None => {
+            CGEventSetIntegerValueField(cg_event, kCGKeyboardEventKeycode, event.keycode);
+        }
+    }
+    CGEventSetFlags(cg_event, event.flags);
+}
+
 pub fn grab_setup(js: JS) -> anyhow::Result<()> {
     unsafe {
         let _pool = NSAutoreleasePool::new(nil);
-        log::debug!("Calling CGEventTapCreate");
-        let tap = CGEventTapCreate(
-            kCGHIDEventTap, // HID, Session, AnnotatedSession,
-            kCGHeadInsertEventTap,
-            kCGEventTapOptionDefault,
-            (1 << kCGEventKeyDown as CGEventMask)
-                + (1 << kCGEventKeyUp as CGEventMask)
-                + (1 << kCGEventFlagsChanged as CGEventMask),
-            Some(raw_callback),
-            Box::into_raw(Box::new(js)) as *mut _,
-        );
-        if tap.is_null() {
-            return Err(anyhow!("Cannot create CGEventTapCreate"));
-        }
-        let _loop = CFMachPortCreateRunLoopSource(kCFAllocatorDefault, tap, 0);
-        if _loop.is_null() {
-            return Err(anyhow!("Error in CFMachPortCreateRunLoopSource"));
-        }
+        let state = Box::into_raw(Box::new(TapState {
+            js,
+            timer: null_mut(),
+            motion_tap: null_mut(),
+        }));
 
         let current_loop = CFRunLoopGetCurrent();
-        log::debug!("Calling CFRunLoopAddSource");
-        CFRunLoopAddSource(current_loop, _loop, kCFRunLoopCommonModes);
-
+        let tap = create_tap(current_loop, cg_event_types(), state)?;
         CGEventTapEnable(tap, true);
+        (*state).motion_tap = create_tap(current_loop, motion_event_types(), state)?;
+
+        // setTimeout/setInterval. The timer repeats, so that it's never invalidated; the fire date
+        // is moved by `schedule_timers` instead.
+        let mut timer_context = CFRunLoopTimerContext {
+            version: 0,
+            info: state as *mut _,
+            retain: None,
+            release: None,
+            copyDescription: None,
+        };
+        let timer = CFRunLoopTimerCreate(
+            kCFAllocatorDefault,
+            CFAbsoluteTimeGetCurrent() + TIMER_IDLE_SECONDS,
+            TIMER_IDLE_SECONDS,
+            0,
+            0,
+            Some(timer_callback),
+            &mut timer_context,
+        );
+        if timer.is_null() {
+            return Err(anyhow!("Cannot create CFRunLoopTimer"));
+        }
+        CFRunLoopAddTimer(current_loop, timer, kCFRunLoopCommonModes);
+        (*state).timer = timer;
+
+        let mut source_context = CFRunLoopSourceContext {
+            version: 0,
+            info: state as *mut _,
+            retain: None,
+            release: None,
+            copyDescription: None,
+            equal: None,
+            hash: None,
+            schedule: None,
+            cancel: None,
+            perform: Some(js_operation_callback),
+        };
+        let source = CFRunLoopSourceCreate(kCFAllocatorDefault, 0, &mut source_context);
+        if source.is_null() {
+            return Err(anyhow!("Cannot create CFRunLoopSource"));
+        }
+        CFRunLoopAddSource(current_loop, source, kCFRunLoopCommonModes);
+        let signal = RunLoopSignal {
+            source,
+            run_loop: current_loop,
+        };
+        (*state).js.set_js_operation_waker(move || signal.signal());
+        // Operations may have been sent while loading the plugins.
+        (*state).js.process_js_operations();
+        // Timers may be added while loading the plugins.
+        schedule_timers(&mut *state);
+        update_motion_tap(&mut *state);
     }
     Ok(())
 }
 
+// Creates a disabled tap of `event_types` on the run loop.
+unsafe fn create_tap(
+    run_loop: CFRunLoopRef,
+    event_types: impl Iterator<Item = CGEventType>,
+    state: *mut TapState,
+) -> anyhow::Result<CFMachPortRef> {
+    log::debug!("Calling CGEventTapCreate");
+    let tap = CGEventTapCreate(
+        kCGHIDEventTap, // HID, Session, AnnotatedSession,
+        kCGHeadInsertEventTap,
+        kCGEventTapOptionDefault,
+        event_types.fold(0, |mask, t| mask | (1 << t as CGEventMask)),
+        Some(raw_callback),
+        state as *mut _,
+    );
+    if tap.is_null() {
+        return Err(anyhow!("Cannot create CGEventTapCreate"));
+    }
+    CGEventTapEnable(tap, false);
+    let _loop = CFMachPortCreateRunLoopSource(kCFAllocatorDefault, tap, 0);
+    if _loop.is_null() {
+        return Err(anyhow!("Error in CFMachPortCreateRunLoopSource"));
+    }
+
+    log::debug!("Calling CFRunLoopAddSource");
+    CFRunLoopAddSource(run_loop, _loop, kCFRunLoopCommonModes);
+    Ok(tap)
+}
+
 pub fn grab_run() {
     log::info!("Running CFRunLoopRun");
     unsafe {
         CFRunLoopRun();
     }
 }
+
+/// The macOS backend, built on top of `CGEventTapCreate` and `CGEventPost`.
+#[derive(Default)]
+pub struct CoreGraphicsBackend {}
+
+impl CoreGraphicsBackend {
+    pub fn new() -> Self {
+        CoreGraphicsBackend {}
+    }
+}
+
+impl InputBackend for CoreGraphicsBackend {
+    fn grab_setup(&self, js: JS) -> anyhow::Result<()> {
+        grab_setup(js)
+    }
+
+    fn grab_run(&self) {
+        grab_run()
+    }
+
+    fn send_keyboard_event(
+        &self,
+        keycode: CGKeyCode,
+        flags: CGEventFlags,
+        keydown: bool,
+    ) -> anyhow::Result<()> {
+        send_keyboard_event(keycode, flags, keydown)
+    }
+
+    fn send_flags_changed_event(&self, flags: CGEventFlags) -> anyhow::Result<()> {
+        send_flags_changed_event(flags)
+    }
+
+    fn send_text(&self, text: &str) -> anyhow::Result<()> {
+        send_text(text)
+    }
+
+    fn send_mouse_event(
+        &self,
+        cg_event_type: CGEventType,
+        button: i64,
+        position: Option<(f64, f64)>,
+        flags: CGEventFlags,
+    ) -> anyhow::Result<()> {
+        send_mouse_event(cg_event_type, button, position, flags)
+    }
+
+    fn send_scroll_event(
+        &self,
+        delta_x: i64,
+        delta_y: i64,
+        flags: CGEventFlags,
+    ) -> anyhow::Result<()> {
+        send_scroll_event(delta_x, delta_y, flags)
+    }
+}
@@ -0,0 +1,808 @@
+use crate::app_config::AppConfig;
+use crate::cg_constants::{
+    kCGEventFlagsChanged, kCGEventKeyDown, kCGEventKeyUp, CGEventFlags, CGKeyCode,
+};
+use crate::event::{event_type, Event};
+use crate::hotkey::{notation_flags_mask, parse_keys};
+use crate::js::JS;
+use crate::manifest::PluginManifest;
+use crate::mock_backend::MockBackend;
+use std::sync::Arc;
+
+/// Runs scripted events through the plugins, without any OS event tap.
+///
+/// ```no_run
+/// # use keyscripten_core::harness::Harness;
+/// let mut harness = Harness::new()?;
+/// harness.type_keys("a b a b C-t")?;
+/// harness.assert_injected_keys("a b");
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct Harness {
+    js: JS,
+    backend: Arc<MockBackend>,
+    swallowed: Vec<Event>,
+    delivered: Vec<Event>,
+}
+
+impl Harness {
+    /// Creates a harness with driver.js and the bundled plugins loaded. The clock is virtual; it only
+    /// moves by `advance`.
+    pub fn new() -> anyhow::Result<Self> {
+        let backend = Arc::new(MockBackend::new());
+        let mut js =
+            JS::new_with_config(None, None, None, Some(backend.clone()), AppConfig::default())?;
+        js.use_virtual_clock();
+        js.use_storage(None);
+        Ok(Harness {
+            js,
+            backend,
+            swallowed: Vec::new(),
+            delivered: Vec::new(),
+        })
+    }
+
+    /// Evaluates the plugin source code in its own realm, as a plugin file is loaded. The header
+    /// manifest, if any, is applied.
+    pub fn load_plugin(&mut self, src: &str) -> anyhow::Result<()> {
+        let manifest = PluginManifest::from_header(src)?;
+        self.js.eval_plugin(src, manifest.as_ref())?;
+        Ok(())
+    }
+
+    /// Passes the event to the plugins. Returns the event delivered to the application, or None if
+    /// the plugins swallowed it.
+    pub fn send(&mut self, event: Event) -> anyhow::Result<Option<Event>> {
+        let result = self.js.send_event(event.clone())?;
+        match &result {
+            Some(delivered) => self.delivered.push(delivered.clone()),
+            None => self.swallowed.push(event),
+        }
+        // Same as the backends, due timers run right after the event.
+        self.js.run_timers()?;
+        Ok(result)
+    }
+
+    /// Moves the clock forward by `ms`, and fires the timers which become due.
+    pub fn advance(&mut self, ms: u64) -> anyhow::Result<()> {
+        let until = self.js.now_ms() + ms;
+        self.js.run_timers_until(until)
+    }
+
+    pub fn run(&mut self, events: Vec<Event>) -> anyhow::Result<()> {
+        for event in events {
+            self.send(event)?;
+        }
+        Ok(())
+    }
+
+    /// Types the whitespace separated hotkey notations, e.g. `"a b C-t"`.
+    pub fn type_keys(&mut self, keys: &str) -> anyhow::Result<()> {
+        self.run(keystrokes(keys)?)
+    }
+
+    pub fn injected(&self) -> Vec<Event> {
+        self.backend.injected()
+    }
+
+    pub fn swallowed(&self) -> &[Event] {
+        &self.swallowed
+    }
+
+    /// Events which reach the application, after the plugins rewrote them.
+    pub fn delivered(&self) -> &[Event] {
+        &self.delivered
+    }
+
+    /// How many callbacks of the plugin the watchdog aborted.
+    pub fn aborted(&mut self, plugin_id: &str) -> anyhow::Result<u32> {
+        let stats = self.js.stats()?;
+        let plugin = stats.plugins.iter().find(|plugin| plugin.id == plugin_id);
+        Ok(plugin.map(|plugin| plugin.aborted).unwrap_or_default())
+    }
+
+    /// Forgets the injected, swallowed and delivered events. The plugin state is kept as is.
+    pub fn clear(&mut self) {
+        self.backend.clear();
+        self.swallowed.clear();
+        self.delivered.clear();
+    }
+
+    /// Asserts that the keyDown events injected by the plugins are exactly `expected`.
+    pub fn assert_injected_keys(&self, expected: &str) {
+        assert_eq!(
+            key_downs(&self.injected()),
+            key_downs(&keystrokes(expected).unwrap()),
+            "injected keys don't match `{}`: {:?}",
+            expected,
+            self.injected()
+        );
+    }
+
+    /// Concatenated text typed by `sendText` so far.
+    pub fn injected_text(&self) -> String {
+        self.injected()
+            .iter()
+            .filter(|event| event.event_type == event_type(kCGEventKeyDown))
+            .filter_map(|event| event.text.clone())
+            .collect()
+    }
+
+    /// Asserts that the keyDown events delivered to the application are exactly `expected`.
+    pub fn assert_delivered_keys(&self, expected: &str) {
+        assert_eq!(
+            key_downs(&self.delivered),
+            key_downs(&keystrokes(expected).unwrap()),
+            "delivered keys don't match `{}`: {:?}",
+            expected,
+            self.delivered
+        );
+    }
+
+    /// Asserts that the keyDown events swallowed by the plugins are exactly `expected`.
+    pub fn assert_swallowed_keys(&self, expected: &str) {
+        assert_eq!(
+            key_downs(&self.swallowed),
+            key_downs(&keystrokes(expected).unwrap()),
+            "swallowed keys don't match `{}`: {:?}",
+            expected,
+            self.swallowed
+        );
+    }
+}
+
+/// Expands the whitespace separated hotkey notations into the events a real keyboard sends.
+/// Modifiers are pressed with a flagsChanged event before the key, and released after it.
+pub fn keystrokes(keys: &str) -> anyhow::Result<Vec<Event>> {
+    let mut events = Vec::new();
+    for hotkey in parse_keys(keys)? {
+        let keycode = hotkey.keycode as i64;
+        let flags = hotkey.flags | hotkey.device_flags;
+        if flags != 0 {
+            events.push(Event::new(kCGEventFlagsChanged, 0, flags));
+        }
+        events.push(Event::new(kCGEventKeyDown, keycode, flags));
+        events.push(Event::new(kCGEventKeyUp, keycode, flags));
+        if flags != 0 {
+            events.push(Event::new(kCGEventFlagsChanged, 0, 0));
+        }
+    }
+    Ok(events)
+}
+
+fn key_downs(events: &[Event]) -> Vec<(CGKeyCode, CGEventFlags)> {
+    let modifiers = notation_flags_mask();
+    events
+        .iter()
+        .filter(|event| event.event_type == event_type(kCGEventKeyDown))
+        .map(|event| (event.keycode as CGKeyCode, event.flags & modifiers))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::watchdog::MAX_OFFENCES;
+    use std::time::Duration;
+    use crate::cg_constants::{
+        kCGEventFlagMaskCommand, kCGEventFlagMaskControl,
+        kCGEventFlagMaskSecondaryFn, kCGEventScrollWheel,
+    };
+    use crate::event::Mouse;
+
+    #[test]
+    fn test_keystrokes() -> anyhow::Result<()> {
+        let events = keystrokes("a C-t")?;
+        let types: Vec<&str> = events.iter().map(|e| e.event_type.as_str()).collect();
+        assert_eq!(
+            types,
+            vec!["keyDown", "keyUp", "flagsChanged", "keyDown", "keyUp", "flagsChanged"]
+        );
+        assert_eq!(events[3].flags, kCGEventFlagMaskControl);
+        assert_eq!(events[5].flags, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dynamic_macro_repeat() -> anyhow::Result<()> {
+        let mut harness = Harness::new()?;
+        harness.type_keys("a b a b C-t")?;
+        harness.assert_injected_keys("a b");
+        harness.assert_swallowed_keys("C-t");
+        Ok(())
+    }
+
+    #[test]
+    fn test_dynamic_macro_predict() -> anyhow::Result<()> {
+        let mut harness = Harness::new()?;
+        harness.type_keys("a b c a C-t")?;
+        harness.assert_injected_keys("b c");
+        Ok(())
+    }
+
+    #[test]
+    fn test_dynamic_macro_no_pattern() -> anyhow::Result<()> {
+        let mut harness = Harness::new()?;
+        harness.type_keys("a b C-t")?;
+        harness.assert_injected_keys("");
+        // hotkey is passed to the application if there's nothing to repeat.
+        harness.assert_swallowed_keys("");
+        Ok(())
+    }
+
+    #[test]
+    fn test_plugin_swallows_event() -> anyhow::Result<()> {
+        let mut harness = Harness::new()?;
+        harness.load_plugin(
+            r#"
+            registerPlugin("test.swallow", "Swallow", "", (event, config) => {
+                return !(event.type === "keyDown" && event.keycode === Key.X);
+            }, []);
+            "#,
+        )?;
+        harness.type_keys("a x b")?;
+        harness.assert_swallowed_keys("x");
+        harness.assert_injected_keys("");
+        Ok(())
+    }
+
+    #[test]
+    fn test_plugin_rewrites_event() -> anyhow::Result<()> {
+        let mut harness = Harness::new()?;
+        harness.load_plugin(
+            r#"
+            registerPlugin("test.rewrite", "Rewrite", "", (event, config) => {
+                if (event.keycode === Key.X) {
+                    return { ...event, keycode: Key.Y, flags: kCGEventFlagMaskShift };
+                }
+                return true;
+            }, []);
+            "#,
+        )?;
+        harness.type_keys("a x b")?;
+        harness.assert_delivered_keys("a S-y b");
+        harness.assert_swallowed_keys("");
+        harness.assert_injected_keys("");
+        Ok(())
+    }
+
+    #[test]
+    fn test_mouse_events() -> anyhow::Result<()> {
+        let mut harness = Harness::new()?;
+        harness.load_plugin(
+            r#"
+            registerPlugin("test.mouse", "Mouse", "", (event, config) => {
+                // Fn + scroll => Cmd + scroll
+                if (event.type === "scrollWheel" && event.fn) {
+                    sendScroll(event.deltaY, event.deltaX, kCGEventFlagMaskCommand);
+                    return false;
+                }
+                // M-m => middle click at the current position
+                if (event.type === "keyDown" && event.cmd && event.keycode === Key.M) {
+                    sendMouseEvent(kCGEventOtherMouseDown);
+                    sendMouseEvent(kCGEventOtherMouseUp, kCGMouseButtonCenter);
+                    return false;
+                }
+                return true;
+            }, []);
+            "#,
+        )?;
+
+        let scroll = |flags| {
+            let mouse = Mouse {
+                x: 10.0,
+                y: 20.0,
+                delta_y: -3,
+                ..Mouse::default()
+            };
+            Event::new_mouse(kCGEventScrollWheel, mouse, flags)
+        };
+        assert!(harness.send(scroll(0))?.is_some());
+        assert!(harness.send(scroll(kCGEventFlagMaskSecondaryFn))?.is_none());
+        harness.type_keys("M-m")?;
+
+        let injected: Vec<(String, i64, i64, u64)> = harness
+            .injected()
+            .iter()
+            .map(|e| {
+                let mouse = e.mouse.clone().unwrap();
+                (e.event_type.clone(), mouse.button, mouse.delta_y, e.flags)
+            })
+            .collect();
+        assert_eq!(
+            injected,
+            vec![
+                ("scrollWheel".to_string(), 0, -3, kCGEventFlagMaskCommand),
+                ("otherMouseDown".to_string(), 2, 0, 0),
+                ("otherMouseUp".to_string(), 2, 0, 0),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_timers() -> anyhow::Result<()> {
+        let mut harness = Harness::new()?;
+        harness.load_plugin(
+            r#"
+            let count = 0;
+            let intervalId;
+            registerPlugin("test.timer", "Timer", "", (event, config) => {
+                if (event.type === "keyDown" && event.keycode === Key.A) {
+                    setTimeout((keycode) => {
+                        sendKeyboardEvent(keycode, 0, true);
+                    }, 300, Key.B);
+                    const cancelled = setTimeout(() => sendKeyboardEvent(Key.X, 0, true), 100);
+                    clearTimeout(cancelled);
+                    intervalId = setInterval(() => {
+                        sendKeyboardEvent(Key.C, 0, true);
+                        if (++count === 3) {
+                            clearInterval(intervalId);
+                        }
+                    }, 100);
+                }
+                return true;
+            }, []);
+            "#,
+        )?;
+
+        harness.type_keys("a")?;
+        harness.assert_injected_keys("");
+        harness.advance(250)?;
+        harness.assert_injected_keys("c c");
+        harness.advance(50)?;
+        // The timeout and the interval are due at the same time. The older one runs first.
+        harness.assert_injected_keys("c c b c");
+        harness.advance(1000)?;
+        harness.assert_injected_keys("c c b c");
+        Ok(())
+    }
+
+    #[test]
+    fn test_async_callback() -> anyhow::Result<()> {
+        let mut harness = Harness::new()?;
+        harness.load_plugin(
+            r#"
+            const sleep = (ms) => new Promise((resolve) => setTimeout(resolve, ms));
+            registerPlugin("test.async", "Async", "", async (event, config) => {
+                if (event.type === "keyDown" && event.keycode === Key.A) {
+                    await null;
+                    sendKeyboardEvent(Key.B, 0, true);
+                    await sleep(100);
+                    sendKeyboardEvent(Key.C, 0, true);
+                    await sleep(100);
+                    sendKeyboardEvent(Key.D, 0, true);
+                }
+                // Too late to swallow the event.
+                return false;
+            }, []);
+            "#,
+        )?;
+
+        harness.type_keys("a x")?;
+        harness.assert_delivered_keys("a x");
+        harness.assert_injected_keys("b");
+        harness.advance(100)?;
+        harness.assert_injected_keys("b c");
+        harness.advance(100)?;
+        harness.assert_injected_keys("b c d");
+        Ok(())
+    }
+
+    #[test]
+    fn test_sequence_hotkeys() -> anyhow::Result<()> {
+        let mut harness = Harness::new()?;
+        harness.load_plugin(
+            r#"
+            const save = new HotKey("C-x C-s", { timeout: 500 });
+            const escape = new HotKey("j+k");
+            registerPlugin("test.sequence", "Sequence", "", (event, config) => {
+                for (const [hotkey, key] of [[save, Key.A], [escape, Key.B]]) {
+                    const state = hotkey.feed(event);
+                    for (const { keycode, flags } of hotkey.takeCancelled()) {
+                        sendKeyboardEvent(keycode, flags, true);
+                        sendKeyboardEvent(keycode, flags, false);
+                    }
+                    if (state === "match") {
+                        sendKeyboardEvent(key, 0, true);
+                        return false;
+                    }
+                    if (state === "partial") {
+                        return false;
+                    }
+                }
+                return true;
+            }, []);
+            "#,
+        )?;
+
+        // The matching itself is tested with `KeySequence`. This checks `feed`, `takeCancelled`
+        // and the `timeout` option from JS, on the clock of the harness.
+        harness.type_keys("C-x C-s")?;
+        harness.assert_injected_keys("a");
+        harness.assert_delivered_keys("");
+
+        // The timed out `C-x` is sent again.
+        harness.type_keys("C-x")?;
+        harness.advance(600)?;
+        harness.type_keys("C-s")?;
+        harness.assert_injected_keys("a C-x");
+        harness.assert_delivered_keys("C-s");
+
+        harness.type_keys("k j")?;
+        harness.assert_injected_keys("a C-x b");
+        Ok(())
+    }
+
+    #[test]
+    fn test_side_modifiers() -> anyhow::Result<()> {
+        let mut harness = Harness::new()?;
+        harness.load_plugin(
+            r#"
+            const hotkey = new HotKey("RM-j");
+            registerPlugin("test.side", "Side", "", (event, config) => {
+                if (event.type === "keyDown" && hotkey.matches(event.flags, event.keycode)) {
+                    sendKeyboardEvent(Key.F, 0, true);
+                    return false;
+                }
+                return true;
+            }, []);
+            "#,
+        )?;
+
+        // The sides are matched by `HotKey::matches`. This checks that the events carry them.
+        harness.type_keys("LM-j RM-j")?;
+        harness.assert_delivered_keys("LM-j");
+        harness.assert_injected_keys("f");
+        Ok(())
+    }
+
+    #[test]
+    fn test_send_text() -> anyhow::Result<()> {
+        let mut harness = Harness::new()?;
+        harness.load_plugin(
+            r#"
+            registerPlugin("test.text", "Text", "", (event, config) => {
+                if (event.type === "keyDown" && event.keycode === Key.T) {
+                    sendText("こんにちは, ");
+                    sendText("world 👋");
+                    return false;
+                }
+                return true;
+            }, []);
+            "#,
+        )?;
+
+        harness.type_keys("t")?;
+        assert_eq!(harness.injected_text(), "こんにちは, world 👋");
+        Ok(())
+    }
+
+    #[test]
+    fn test_send_keys() -> anyhow::Result<()> {
+        let mut harness = Harness::new()?;
+        harness.load_plugin(
+            r#"
+            registerPlugin("test.keys", "Keys", "", (event, config) => {
+                if (event.type === "keyDown" && event.keycode === Key.T) {
+                    sendKeys("C-a C-k  M-v RET", event.flags);
+                    return false;
+                }
+                return true;
+            }, []);
+            "#,
+        )?;
+
+        // The events are tested with `key_events`. This checks that they reach the backend, and
+        // that the flags passed from JS are restored.
+        harness.type_keys("C-t")?;
+        harness.assert_injected_keys("C-a C-k M-v RET");
+        assert_eq!(harness.injected().last().unwrap().flags, kCGEventFlagMaskControl);
+
+        harness.clear();
+        assert!(harness.js.eval(r#"sendKeys("C-a unknown")"#.to_string()).is_err());
+        harness.assert_injected_keys("");
+        Ok(())
+    }
+
+    #[test]
+    fn test_plugin_isolation() -> anyhow::Result<()> {
+        let mut harness = Harness::new()?;
+        let counter = r#"
+            const pressed = [];
+            registerPlugin("test.counter", "Counter", "", (event, config) => {
+                if (event.type === "keyDown") {
+                    pressed.push(event.keycode);
+                    if (pressed.length === 2) {
+                        return false;
+                    }
+                }
+                return true;
+            }, []);
+            "#;
+        harness.load_plugin(counter)?;
+        // The same top-level names in another file don't clash.
+        harness.load_plugin(
+            r#"
+            const pressed = "mine";
+            registerPlugin("test.other", "Other", "", (event, config) => pressed === "mine", []);
+            "#,
+        )?;
+
+        // The driver and the injected API can't be tampered with.
+        for src in [
+            "registerPlugin = () => {};",
+            "globalThis.registerPlugin = () => {};",
+            "$$CALLBACKS['test.counter'] = () => false;",
+            "Key.A = 1;",
+            "Object.prototype.type = 'keyUp';",
+            "Object.getPrototypeOf(registerPlugin).call = null;",
+        ] {
+            let _ = harness.load_plugin(src);
+        }
+        harness.type_keys("a b c")?;
+        assert_eq!(harness.swallowed.len(), 1);
+        assert_eq!(harness.delivered.len(), 5);
+
+        // Reloading a file with top-level `const` works.
+        harness.load_plugin(counter)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_realm_escape() -> anyhow::Result<()> {
+        let mut harness = Harness::new()?;
+        // `Function` of the driver realm would evaluate code with the globals of the driver. The
+        // one of the plugin realm throws.
+        harness.load_plugin(
+            r#"
+            const probes = [];
+            const probe = (value) => {
+                try {
+                    probes.push(value.constructor.constructor("return typeof $$IDS")());
+                } catch (e) {
+                    probes.push(e.name);
+                }
+            };
+            probe(registerPlugin);
+            probe(storage);
+            try {
+                setTimeout(null, 0);
+            } catch (e) {
+                probe(e);
+            }
+            registerPlugin("test.escape", "Escape", "", (event, config) => {
+                if (probes.length === 3) {
+                    probe(event);
+                    probe(config);
+                    probe(config.key);
+                    setTimeout((...args) => {
+                        probes.push(...args);
+                        sendText(probes.join(","));
+                    }, 0, "timer");
+                }
+                return true;
+            }, [{ name: "key", type: "hotkey", default: "C-a" }]);
+            "#,
+        )?;
+        harness.type_keys("a")?;
+        assert_eq!(
+            harness.injected_text(),
+            "EvalError,EvalError,EvalError,EvalError,EvalError,EvalError,timer"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_plugin_priority() -> anyhow::Result<()> {
+        let mut harness = Harness::new()?;
+        // Rewrites `a` to `b`.
+        harness.load_plugin(
+            r#"
+            registerPlugin("test.remap", "Remap", "", (event, config) => {
+                if (event.keycode === Key.A) {
+                    return { ...event, keycode: Key.B };
+                }
+                return true;
+            }, []);
+            "#,
+        )?;
+        // Swallows `b`.
+        harness.load_plugin(
+            r#"
+            registerPlugin("test.swallow", "Swallow", "", (event, config) => event.keycode !== Key.B, []);
+            "#,
+        )?;
+
+        // In the loading order, the remapped `a` is swallowed.
+        harness.type_keys("a")?;
+        harness.assert_swallowed_keys("a");
+        harness.assert_delivered_keys("");
+
+        // With a higher priority, the swallowing plugin sees the original `a` first.
+        harness.clear();
+        harness
+            .js
+            .eval(r#"$$setPluginPriority("test.swallow", 1)"#.to_string())?;
+        harness.type_keys("a")?;
+        harness.assert_swallowed_keys("");
+        harness.assert_delivered_keys("b");
+
+        let order = harness.js.get_config_schema()?.plugins;
+        assert_eq!(order[0].id, "test.swallow");
+        Ok(())
+    }
+
+    #[test]
+    fn test_manifest_permissions() -> anyhow::Result<()> {
+        let mut harness = Harness::new()?;
+        harness.load_plugin(
+            r#"
+            // ==KeyScripten==
+            // @id test.permissions
+            // @permission storage
+            // ==/KeyScripten==
+            registerPlugin("test.permissions", "Permissions", "", (event, config) => {
+                if (event.type === "keyDown" && event.keycode === Key.S) {
+                    storage.set("pressed", true);
+                    return false;
+                }
+                if (event.type === "keyDown" && event.keycode === Key.T) {
+                    sendText("not allowed");
+                    return false;
+                }
+                return true;
+            }, []);
+            "#,
+        )?;
+
+        harness.type_keys("s")?;
+        assert_eq!(harness.swallowed.len(), 1);
+        // `sendText` needs "sendEvents". The callback throws, and the event passes through.
+        harness.type_keys("t")?;
+        assert_eq!(harness.swallowed.len(), 1);
+        assert_eq!(harness.injected_text(), "");
+        Ok(())
+    }
+
+    #[test]
+    fn test_watchdog_disables_plugin() -> anyhow::Result<()> {
+        let mut harness = Harness::new()?;
+        harness.js.set_callback_budget(Duration::from_millis(20));
+        harness.load_plugin(
+            r#"
+            registerPlugin("test.loop", "Loop", "", (event, config) => {
+                if (event.type === "keyDown" && event.keycode === Key.L) {
+                    try {
+                        // Each call is short, but the callback isn't.
+                        for (;;) {
+                            (() => {
+                                for (let i = 0; i < 900000; i++) {}
+                            })();
+                        }
+                    } catch (e) {
+                        // charged all the same
+                    }
+                }
+                return false;
+            }, []);
+            "#,
+        )?;
+
+        // Aborted callbacks pass the event.
+        harness.type_keys("l")?;
+        assert_eq!(harness.delivered.len(), 1);
+        assert_eq!(harness.swallowed.len(), 1);
+        harness.type_keys("a")?;
+        assert_eq!(harness.swallowed.len(), 3);
+
+        // The plugin is disabled at the MAX_OFFENCES-th abort.
+        for _ in 1..MAX_OFFENCES {
+            harness.type_keys("l")?;
+        }
+        let swallowed = harness.swallowed.len();
+        harness.type_keys("a")?;
+        assert_eq!(harness.swallowed.len(), swallowed);
+        Ok(())
+    }
+
+    #[test]
+    fn test_watchdog_charges_timers_and_jobs() -> anyhow::Result<()> {
+        let mut harness = Harness::new()?;
+        harness.js.set_callback_budget(Duration::from_millis(20));
+        harness.load_plugin(
+            r#"
+            setInterval(() => {
+                while (true) {}
+            }, 100);
+            registerPlugin("test.loop", "Loop", "", (event, config) => {
+                if (event.type === "keyDown" && event.keycode === Key.L) {
+                    Promise.resolve().then(() => {
+                        while (true) {}
+                    });
+                }
+                if (event.type === "keyDown" && event.keycode === Key.K) {
+                    (async () => {
+                        while (true) await null;
+                    })();
+                }
+                return false;
+            }, []);
+            "#,
+        )?;
+        harness.load_plugin(
+            r#"
+            setInterval(() => sendKeyboardEvent(Key.C, 0, true), 100);
+            registerPlugin("test.ticker", "Ticker", "", (event, config) => true, []);
+            "#,
+        )?;
+
+        harness.type_keys("l")?;
+        assert_eq!(harness.aborted("test.loop")?, 1);
+        // The timer of the other plugin, due at the same time, still runs.
+        harness.advance(100)?;
+        assert_eq!(harness.aborted("test.loop")?, 2);
+        harness.assert_injected_keys("c");
+
+        // Each await is short, but they never end. Disabled at the MAX_OFFENCES-th abort.
+        assert_eq!(MAX_OFFENCES, 3);
+        harness.type_keys("k")?;
+        assert_eq!(harness.aborted("test.loop")?, 0);
+        harness.type_keys("a")?;
+        harness.assert_delivered_keys("a");
+        harness.advance(100)?;
+        harness.assert_injected_keys("c c");
+        assert_eq!(harness.aborted("test.loop")?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_plugin_storage() -> anyhow::Result<()> {
+        let mut harness = Harness::new()?;
+        let src = r#"
+            registerPlugin("test.storage", "Storage", "", (event, config) => {
+                if (event.type === "keyDown") {
+                    storage.set("count", storage.get("count", 0) + 1);
+                }
+                return true;
+            }, []);
+            "#;
+        harness.load_plugin(src)?;
+        harness.type_keys("a b c")?;
+
+        // Reloading the plugin keeps the storage.
+        harness.load_plugin(src)?;
+        harness.type_keys("d")?;
+        // Without registering a plugin, the namespace is the id in the manifest.
+        let other = r#"
+            // ==KeyScripten==
+            // @id test.other
+            // @permission storage
+            // @permission sendEvents
+            // ==/KeyScripten==
+            "#;
+        harness.load_plugin(&format!(
+            r#"{}
+            storage.set("count", 100);
+            storage.set("words", ["x", "y"]);
+            storage.delete("count");
+            sendText(JSON.stringify([storage.keys(), storage.get("count", null)]));
+            "#,
+            other
+        ))?;
+        harness.load_plugin(&format!(
+            r#"{}
+            sendText(String(storage.get("count")));
+            "#,
+            other.replace("test.other", "test.storage")
+        ))?;
+        assert_eq!(harness.injected_text(), r#"[["words"],null]4"#);
+
+        // A file without a manifest gets a namespace by registering a plugin.
+        assert!(harness.load_plugin(r#"storage.get("count")"#).is_err());
+        assert!(harness
+            .load_plugin(r#"registerPlugin("test.f", "F", "", () => true, []); storage.set("f", () => 1)"#)
+            .is_err());
+        Ok(())
+    }
+}
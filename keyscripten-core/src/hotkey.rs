@@ -1,41 +1,78 @@
 use crate::keycode;
 use anyhow::anyhow;
-use apple_sys::CoreGraphics::{CGEventFlags, CGKeyCode};
+use crate::cg_constants::{CGEventFlags, CGKeyCode};
 use crate::cg_constants::{
     kCGEventFlagMaskAlternate, kCGEventFlagMaskCommand, kCGEventFlagMaskControl,
-    kCGEventFlagMaskShift,
+    kCGEventFlagMaskLeftAlternate, kCGEventFlagMaskLeftCommand, kCGEventFlagMaskLeftControl,
+    kCGEventFlagMaskLeftShift, kCGEventFlagMaskNonCoalesced, kCGEventFlagMaskRightAlternate,
+    kCGEventFlagMaskRightCommand, kCGEventFlagMaskRightControl, kCGEventFlagMaskRightShift,
+    kCGEventFlagMaskSecondaryFn, kCGEventFlagMaskShift, kCGEventFlagsChanged, kCGEventKeyDown,
+    kCGEventKeyUp,
 };
+use crate::event::Event;
 use boa_gc::{Finalize, Trace};
 use std::collections::HashMap;
+use std::fmt;
+
+// Modifier prefixes, and the flags and device dependent flags they require.
+const MODIFIER_PREFIXES: &[(&str, CGEventFlags, CGEventFlags)] = &[
+    ("C-", kCGEventFlagMaskControl, 0),
+    ("S-", kCGEventFlagMaskShift, 0),
+    ("M-", kCGEventFlagMaskCommand, 0),
+    ("A-", kCGEventFlagMaskAlternate, 0),
+    ("Fn-", kCGEventFlagMaskSecondaryFn, 0),
+    ("LC-", kCGEventFlagMaskControl, kCGEventFlagMaskLeftControl),
+    ("RC-", kCGEventFlagMaskControl, kCGEventFlagMaskRightControl),
+    ("LS-", kCGEventFlagMaskShift, kCGEventFlagMaskLeftShift),
+    ("RS-", kCGEventFlagMaskShift, kCGEventFlagMaskRightShift),
+    ("LM-", kCGEventFlagMaskCommand, kCGEventFlagMaskLeftCommand),
+    ("RM-", kCGEventFlagMaskCommand, kCGEventFlagMaskRightCommand),
+    ("LA-", kCGEventFlagMaskAlternate, kCGEventFlagMaskLeftAlternate),
+    ("RA-", kCGEventFlagMaskAlternate, kCGEventFlagMaskRightAlternate),
+];
+
+// Modifiers in the order of the canonical notation, with their left and right flags.
+const CANONICAL_ORDER: &[(&str, CGEventFlags, CGEventFlags, CGEventFlags)] = &[
+    ("C", kCGEventFlagMaskControl, kCGEventFlagMaskLeftControl, kCGEventFlagMaskRightControl),
+    ("A", kCGEventFlagMaskAlternate, kCGEventFlagMaskLeftAlternate, kCGEventFlagMaskRightAlternate),
+    ("M", kCGEventFlagMaskCommand, kCGEventFlagMaskLeftCommand, kCGEventFlagMaskRightCommand),
+    ("S", kCGEventFlagMaskShift, kCGEventFlagMaskLeftShift, kCGEventFlagMaskRightShift),
+];
+
+/// Every flag which can be written in the notation.
+pub fn notation_flags_mask() -> CGEventFlags {
+    MODIFIER_PREFIXES
+        .iter()
+        .fold(0, |mask, (_, flags, device_flags)| mask | flags | device_flags)
+}
 
 #[derive(Debug, PartialEq, Trace, Finalize)]
 pub struct HotKey {
     pub flags: CGEventFlags,
+    /// Left/right specific bits which must be set in addition to `flags`, e.g.
+    /// `kCGEventFlagMaskRightCommand` for `RM-`.
+    pub device_flags: CGEventFlags,
     pub keycode: CGKeyCode,
 }
 
 impl HotKey {
     pub fn from_str(s: &str) -> anyhow::Result<HotKey> {
         let mut map = HashMap::new();
-        map.insert("C-", kCGEventFlagMaskControl);
-        map.insert("S-", kCGEventFlagMaskShift);
-        map.insert("M-", kCGEventFlagMaskCommand);
-        map.insert("A-", kCGEventFlagMaskAlternate);
+        for (prefix, flags, device_flags) in MODIFIER_PREFIXES {
+            map.insert(*prefix, (*flags, *device_flags));
+        }
 
-        let mut chars = s.chars().peekable();
         let mut flags = 0;
+        let mut device_flags = 0;
 
         let mut part = String::new();
 
-        loop {
-            if let Some(c) = chars.next() {
-                part.push(c);
-            } else {
-                break;
-            }
+        for c in s.chars() {
+            part.push(c);
 
-            if let Some(code) = map.get(&part[..]) {
+            if let Some((code, device_code)) = map.get(&part[..]) {
                 flags |= *code;
+                device_flags |= *device_code;
                 part.clear();
             }
         }
@@ -45,7 +82,11 @@ impl HotKey {
         }
 
         match keycode::get_keycode(&part) {
-            Some(keycode) => Ok(HotKey { flags, keycode }),
+            Some(keycode) => Ok(HotKey {
+                flags,
+                device_flags,
+                keycode,
+            }),
             None => Err(anyhow!("Unknown key: `{:?}`", s)),
         }
     }
@@ -63,18 +104,320 @@ impl HotKey {
         );
 
         // 全てのキー修飾フラグを取得
-        let all_modifiers = kCGEventFlagMaskControl
+        // macOS sets the Fn flag on the arrow and function keys by itself, so Fn is only checked
+        // when the hotkey requires it.
+        let mut all_modifiers = kCGEventFlagMaskControl
             | kCGEventFlagMaskAlternate
             | kCGEventFlagMaskShift
             | kCGEventFlagMaskCommand;
+        all_modifiers |= expected_flags & kCGEventFlagMaskSecondaryFn;
 
         // 期待するフラグだけが押されていて、それ以外のフラグは押されていないことをチェック
         let is_correct_flags_pressed = flags & all_modifiers == expected_flags;
 
+        // 左右の指定があれば、そちら側のキーが押されていることをチェック
+        let is_correct_side_pressed = flags & self.device_flags == self.device_flags;
+
         // キーコードが期待通りであることをチェック
         let is_correct_keycode = code == expected_code;
 
-        is_correct_flags_pressed && is_correct_keycode
+        is_correct_flags_pressed && is_correct_side_pressed && is_correct_keycode
+    }
+}
+
+/// Prints the canonical notation, e.g. `C-M-t` for `M-C-t`.
+impl fmt::Display for HotKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, flag, left, right) in CANONICAL_ORDER {
+            if self.flags & flag == 0 {
+                continue;
+            }
+            let is_left = self.device_flags & left != 0;
+            let is_right = self.device_flags & right != 0;
+            if is_left {
+                write!(f, "L{}-", name)?;
+            }
+            if is_right {
+                write!(f, "R{}-", name)?;
+            }
+            if !is_left && !is_right {
+                write!(f, "{}-", name)?;
+            }
+        }
+        if self.flags & kCGEventFlagMaskSecondaryFn != 0 {
+            write!(f, "Fn-")?;
+        }
+        write!(f, "{}", keycode::get_keyname(self.keycode).unwrap_or("unknown"))
+    }
+}
+
+/// Parses whitespace separated hotkeys like `C-a C-k M-v RET`. Chords aren't allowed.
+pub fn parse_keys(keys: &str) -> anyhow::Result<Vec<HotKey>> {
+    keys.split_whitespace().map(HotKey::from_str).collect()
+}
+
+/// The events which type `hotkeys` the same way as a real keyboard: press the modifiers, the key,
+/// then release them. The modifiers held before are released first, and `restore_flags` is set
+/// at the end if it's given.
+pub fn key_events(hotkeys: &[HotKey], restore_flags: Option<CGEventFlags>) -> Vec<Event> {
+    let mut events = vec![Event::new(kCGEventFlagsChanged, 0, kCGEventFlagMaskNonCoalesced)];
+    for hotkey in hotkeys {
+        let flags = hotkey.flags | hotkey.device_flags;
+        let keycode = hotkey.keycode as i64;
+        if flags != 0 {
+            events.push(Event::new(kCGEventFlagsChanged, 0, flags));
+        }
+        events.push(Event::new(kCGEventKeyDown, keycode, flags));
+        events.push(Event::new(kCGEventKeyUp, keycode, flags));
+        if flags != 0 {
+            events.push(Event::new(kCGEventFlagsChanged, 0, kCGEventFlagMaskNonCoalesced));
+        }
+    }
+    if let Some(flags) = restore_flags {
+        events.push(Event::new(kCGEventFlagsChanged, 0, flags));
+    }
+    events
+}
+
+/// Default for the time allowed between the strokes of a sequence, e.g. `C-x` and `C-s`.
+pub const DEFAULT_SEQUENCE_TIMEOUT_MS: u64 = 1000;
+/// Default for the time allowed between the first and the last key of a chord, e.g. `j+k`.
+pub const DEFAULT_CHORD_TIMEOUT_MS: u64 = 50;
+
+/// One step of a `KeySequence`.
+#[derive(Debug, PartialEq, Trace, Finalize)]
+pub enum Stroke {
+    Key(HotKey),
+    /// Keys pressed together in any order, e.g. `j+k`.
+    Chord(Vec<HotKey>),
+}
+
+impl Stroke {
+    pub fn from_str(s: &str) -> anyhow::Result<Stroke> {
+        if s.len() > 1 && s.contains('+') {
+            let keys = s
+                .split('+')
+                .map(HotKey::from_str)
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            return Ok(Stroke::Chord(keys));
+        }
+        Ok(Stroke::Key(HotKey::from_str(s)?))
+    }
+}
+
+impl fmt::Display for Stroke {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stroke::Key(hotkey) => write!(f, "{}", hotkey),
+            Stroke::Chord(keys) => {
+                let keys: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+                write!(f, "{}", keys.join("+"))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchState {
+    None,
+    /// The keyDown events so far are a prefix of the sequence.
+    Partial,
+    Match,
+}
+
+impl MatchState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MatchState::None => "none",
+            MatchState::Partial => "partial",
+            MatchState::Match => "match",
+        }
+    }
+}
+
+/// A keyDown event fed to a `KeySequence`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyPress {
+    pub flags: CGEventFlags,
+    pub keycode: CGKeyCode,
+    pub ms: u64,
+}
+
+/// Whitespace separated strokes in Emacs notation, e.g. `C-x C-s` or `j+k`, and the progress of
+/// matching them against the keyDown events.
+#[derive(Debug, Trace, Finalize)]
+pub struct KeySequence {
+    pub strokes: Vec<Stroke>,
+    pub timeout_ms: u64,
+    pub chord_timeout_ms: u64,
+    // Number of strokes matched so far.
+    position: usize,
+    // Indexes of the keys pressed so far in the chord at `position`.
+    chord_pressed: Vec<usize>,
+    // When the last stroke, or the first key of the current chord, was pressed.
+    last_ms: u64,
+    // The keys of the partial match, which the caller swallowed.
+    #[unsafe_ignore_trace]
+    matched: Vec<KeyPress>,
+    // The keys of the cancelled partial matches, for the caller to send again.
+    #[unsafe_ignore_trace]
+    cancelled: Vec<KeyPress>,
+}
+
+impl KeySequence {
+    pub fn from_str(s: &str) -> anyhow::Result<KeySequence> {
+        let strokes = s
+            .split_whitespace()
+            .map(Stroke::from_str)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if strokes.is_empty() {
+            return Err(anyhow!("Cannot parse shortcut: `{:?}`", s));
+        }
+        Ok(KeySequence {
+            strokes,
+            timeout_ms: DEFAULT_SEQUENCE_TIMEOUT_MS,
+            chord_timeout_ms: DEFAULT_CHORD_TIMEOUT_MS,
+            position: 0,
+            chord_pressed: Vec::new(),
+            last_ms: 0,
+            matched: Vec::new(),
+            cancelled: Vec::new(),
+        })
+    }
+
+    /// Returns the hotkey if this is a plain single stroke like `C-t`.
+    pub fn single(&self) -> Option<&HotKey> {
+        match self.strokes.as_slice() {
+            [Stroke::Key(hotkey)] => Some(hotkey),
+            _ => None,
+        }
+    }
+
+    /// Advances the matching with a keyDown event.
+    ///
+    /// A key which doesn't continue the sequence cancels the partial match. The keys matched so
+    /// far are given up from the first one until the rest and the key match again, so `C-x C-x
+    /// C-s` matches `C-x C-s`. The given up keys, and those of a timed out match, are kept for
+    /// `take_cancelled`. Autorepeat events of a started sequence are ignored and return `Partial`.
+    pub fn feed(
+        &mut self,
+        flags: CGEventFlags,
+        keycode: CGKeyCode,
+        autorepeat: bool,
+        now_ms: u64,
+    ) -> MatchState {
+        if !self.is_pending(now_ms) {
+            self.reset();
+        }
+        if autorepeat && self.is_started() {
+            return MatchState::Partial;
+        }
+
+        let press = KeyPress {
+            flags,
+            keycode,
+            ms: now_ms,
+        };
+        if self.advance(press) {
+            return self.matched(press);
+        }
+        let mut kept = std::mem::take(&mut self.matched);
+        while !kept.is_empty() {
+            self.cancelled.push(kept.remove(0));
+            self.clear_progress();
+            if kept.iter().all(|press| self.advance(*press)) && self.advance(press) {
+                self.matched = kept;
+                return self.matched(press);
+            }
+        }
+        self.clear_progress();
+        MatchState::None
+    }
+
+    // Records the key which advanced the matching.
+    fn matched(&mut self, press: KeyPress) -> MatchState {
+        if self.position == self.strokes.len() {
+            self.clear_progress();
+            self.matched.clear();
+            MatchState::Match
+        } else {
+            self.matched.push(press);
+            MatchState::Partial
+        }
+    }
+
+    // Matches the key against the next stroke. Returns false, leaving the progress as it is, if
+    // it doesn't match or comes too late.
+    fn advance(&mut self, press: KeyPress) -> bool {
+        if self.is_started() && !self.is_pending(press.ms) {
+            return false;
+        }
+        match &self.strokes[self.position] {
+            Stroke::Key(hotkey) => {
+                let matched = hotkey.matches(press.flags, press.keycode);
+                if matched {
+                    self.position += 1;
+                    self.last_ms = press.ms;
+                }
+                matched
+            }
+            Stroke::Chord(keys) => {
+                let found = keys.iter().enumerate().position(|(i, key)| {
+                    !self.chord_pressed.contains(&i) && key.matches(press.flags, press.keycode)
+                });
+                if let Some(i) = found {
+                    if self.chord_pressed.is_empty() {
+                        self.last_ms = press.ms;
+                    }
+                    self.chord_pressed.push(i);
+                    if self.chord_pressed.len() == keys.len() {
+                        self.chord_pressed.clear();
+                        self.position += 1;
+                        self.last_ms = press.ms;
+                    }
+                }
+                found.is_some()
+            }
+        }
+    }
+
+    fn is_started(&self) -> bool {
+        self.position > 0 || !self.chord_pressed.is_empty()
+    }
+
+    /// true if a part of the sequence is matched and it hasn't timed out at `now_ms`.
+    pub fn is_pending(&self, now_ms: u64) -> bool {
+        let limit = if !self.chord_pressed.is_empty() {
+            self.chord_timeout_ms
+        } else if self.position > 0 {
+            self.timeout_ms
+        } else {
+            return false;
+        };
+        now_ms.saturating_sub(self.last_ms) <= limit
+    }
+
+    /// Cancels the partial match. Its keys are kept for `take_cancelled`.
+    pub fn reset(&mut self) {
+        self.clear_progress();
+        self.cancelled.append(&mut self.matched);
+    }
+
+    fn clear_progress(&mut self) {
+        self.position = 0;
+        self.chord_pressed.clear();
+    }
+
+    /// Returns the keys of the partial matches cancelled so far, oldest first, and forgets them.
+    pub fn take_cancelled(&mut self) -> Vec<KeyPress> {
+        std::mem::take(&mut self.cancelled)
+    }
+}
+
+impl fmt::Display for KeySequence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let strokes: Vec<String> = self.strokes.iter().map(|stroke| stroke.to_string()).collect();
+        write!(f, "{}", strokes.join(" "))
     }
 }
 
@@ -105,4 +448,210 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_side_and_fn_modifiers() -> anyhow::Result<()> {
+        let right_cmd_t = HotKey::from_str("RM-t")?;
+        assert_eq!(right_cmd_t.flags, kCGEventFlagMaskCommand);
+        assert_eq!(right_cmd_t.device_flags, kCGEventFlagMaskRightCommand);
+        assert!(right_cmd_t.matches(
+            kCGEventFlagMaskCommand | kCGEventFlagMaskRightCommand,
+            KEY_CODE_KEY_T
+        ));
+        assert!(!right_cmd_t.matches(
+            kCGEventFlagMaskCommand | kCGEventFlagMaskLeftCommand,
+            KEY_CODE_KEY_T
+        ));
+        // `M-` matches either side.
+        assert!(HotKey::from_str("M-t")?.matches(
+            kCGEventFlagMaskCommand | kCGEventFlagMaskLeftCommand,
+            KEY_CODE_KEY_T
+        ));
+
+        let left_ctrl_t = HotKey::from_str("LC-S-t")?;
+        assert_eq!(left_ctrl_t.flags, kCGEventFlagMaskControl | kCGEventFlagMaskShift);
+        assert_eq!(left_ctrl_t.device_flags, kCGEventFlagMaskLeftControl);
+
+        let j = keycode::get_keycode("j").unwrap();
+        let right_cmd_j = HotKey::from_str("RM-j")?;
+        assert!(right_cmd_j.matches(kCGEventFlagMaskCommand | kCGEventFlagMaskRightCommand, j));
+        assert!(!right_cmd_j.matches(kCGEventFlagMaskCommand | kCGEventFlagMaskLeftCommand, j));
+        // The side flag alone isn't the modifier.
+        assert!(!right_cmd_j.matches(kCGEventFlagMaskRightCommand, j));
+        let cmd_j = HotKey::from_str("M-j")?;
+        assert!(cmd_j.matches(kCGEventFlagMaskCommand | kCGEventFlagMaskLeftCommand, j));
+        assert!(cmd_j.matches(kCGEventFlagMaskCommand | kCGEventFlagMaskRightCommand, j));
+        assert!(cmd_j.matches(
+            kCGEventFlagMaskCommand | kCGEventFlagMaskLeftCommand | kCGEventFlagMaskRightCommand,
+            j
+        ));
+        let fn_j = HotKey::from_str("Fn-j")?;
+        assert!(fn_j.matches(kCGEventFlagMaskSecondaryFn, j));
+        assert!(!fn_j.matches(kCGEventFlagMaskSecondaryFn | kCGEventFlagMaskCommand, j));
+
+        let fn_t = HotKey::from_str("Fn-t")?;
+        assert!(fn_t.matches(kCGEventFlagMaskSecondaryFn, KEY_CODE_KEY_T));
+        assert!(!fn_t.matches(0, KEY_CODE_KEY_T));
+        // Fn is ignored unless the hotkey asks for it.
+        assert!(HotKey::from_str("t")?.matches(kCGEventFlagMaskSecondaryFn, KEY_CODE_KEY_T));
+        assert!(HotKey::from_str("RM-").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_sequence() -> anyhow::Result<()> {
+        let sequence = KeySequence::from_str("C-x  j+k")?;
+        assert_eq!(sequence.strokes.len(), 2);
+        assert_eq!(sequence.strokes[0], Stroke::Key(HotKey::from_str("C-x")?));
+        assert_eq!(
+            sequence.strokes[1],
+            Stroke::Chord(vec![HotKey::from_str("j")?, HotKey::from_str("k")?])
+        );
+        assert!(sequence.single().is_none());
+        assert!(KeySequence::from_str("C-t")?.single().is_some());
+
+        assert!(KeySequence::from_str("").is_err());
+        assert!(KeySequence::from_str("C-x unknown").is_err());
+        assert!(KeySequence::from_str("j+").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_feed_sequence() -> anyhow::Result<()> {
+        let x = keycode::get_keycode("x").unwrap();
+        let s = keycode::get_keycode("s").unwrap();
+        let mut sequence = KeySequence::from_str("C-x C-s")?;
+
+        assert_eq!(sequence.feed(kCGEventFlagMaskControl, x, false, 0), MatchState::Partial);
+        assert_eq!(sequence.feed(kCGEventFlagMaskControl, x, true, 10), MatchState::Partial);
+        assert_eq!(sequence.feed(kCGEventFlagMaskControl, s, false, 500), MatchState::Match);
+
+        assert_eq!(sequence.take_cancelled(), vec![]);
+
+        // A wrong key cancels the sequence, and the swallowed keys are given back.
+        assert_eq!(sequence.feed(kCGEventFlagMaskControl, x, false, 1000), MatchState::Partial);
+        assert_eq!(sequence.feed(0, s, false, 1010), MatchState::None);
+        assert_eq!(sequence.feed(kCGEventFlagMaskControl, s, false, 1020), MatchState::None);
+        let c_x = |ms| KeyPress {
+            flags: kCGEventFlagMaskControl,
+            keycode: x,
+            ms,
+        };
+        assert_eq!(sequence.take_cancelled(), vec![c_x(1000)]);
+
+        // So does the timeout.
+        assert_eq!(sequence.feed(kCGEventFlagMaskControl, x, false, 2000), MatchState::Partial);
+        assert!(sequence.is_pending(3000));
+        assert!(!sequence.is_pending(3001));
+        assert_eq!(sequence.feed(kCGEventFlagMaskControl, s, false, 3001), MatchState::None);
+        assert_eq!(sequence.take_cancelled(), vec![c_x(2000)]);
+
+        // The matching restarts from the key which broke it.
+        assert_eq!(sequence.feed(kCGEventFlagMaskControl, x, false, 4000), MatchState::Partial);
+        assert_eq!(sequence.feed(kCGEventFlagMaskControl, x, false, 4010), MatchState::Partial);
+        assert_eq!(sequence.feed(kCGEventFlagMaskControl, s, false, 4020), MatchState::Match);
+        assert_eq!(sequence.take_cancelled(), vec![c_x(4000)]);
+
+        // `timeout_ms` is set by the `timeout` option of `new HotKey`.
+        sequence.timeout_ms = 500;
+        assert_eq!(sequence.feed(kCGEventFlagMaskControl, x, false, 5000), MatchState::Partial);
+        assert_eq!(sequence.feed(kCGEventFlagMaskControl, s, false, 5501), MatchState::None);
+        assert_eq!(sequence.take_cancelled(), vec![c_x(5000)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_feed_chord() -> anyhow::Result<()> {
+        let j = keycode::get_keycode("j").unwrap();
+        let k = keycode::get_keycode("k").unwrap();
+        let mut chord = KeySequence::from_str("j+k")?;
+
+        assert_eq!(chord.feed(0, k, false, 0), MatchState::Partial);
+        assert_eq!(chord.feed(0, j, false, 30), MatchState::Match);
+
+        // The second `j` starts a new chord.
+        assert_eq!(chord.feed(0, j, false, 100), MatchState::Partial);
+        assert_eq!(chord.feed(0, j, false, 110), MatchState::Partial);
+        assert_eq!(chord.feed(0, k, false, 120), MatchState::Match);
+
+        assert_eq!(chord.feed(0, j, false, 200), MatchState::Partial);
+        // The expired `j` is given back, and `k` starts a new chord.
+        assert_eq!(chord.feed(0, k, false, 251), MatchState::Partial);
+        let cancelled: Vec<CGKeyCode> = chord.take_cancelled().iter().map(|p| p.keycode).collect();
+        assert_eq!(cancelled, vec![j, j]);
+
+        // A key out of the chord gives back the expired `j`, and doesn't match.
+        let x = keycode::get_keycode("x").unwrap();
+        chord.reset();
+        chord.take_cancelled();
+        assert_eq!(chord.feed(0, j, false, 300), MatchState::Partial);
+        assert_eq!(chord.feed(0, x, false, 400), MatchState::None);
+        let cancelled: Vec<CGKeyCode> = chord.take_cancelled().iter().map(|p| p.keycode).collect();
+        assert_eq!(cancelled, vec![j]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_canonical_notation() -> anyhow::Result<()> {
+        assert_eq!(HotKey::from_str("M-C-t")?.to_string(), "C-M-t");
+        assert_eq!(HotKey::from_str("S-RM-LC-Fn-enter")?.to_string(), "LC-RM-S-Fn-return");
+        assert_eq!(HotKey::from_str("C--")?.to_string(), "C--");
+        assert_eq!(KeySequence::from_str("C-x   k+J")?.to_string(), "C-x k+j");
+        for notation in ["C-x C-s", "LA-RA-{", "j+k+l", "Fn-up"] {
+            let canonical = KeySequence::from_str(notation)?.to_string();
+            assert_eq!(KeySequence::from_str(&canonical)?.to_string(), canonical);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_keys() -> anyhow::Result<()> {
+        let keys = parse_keys("C-a  RET spc")?;
+        let keys: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+        assert_eq!(keys, vec!["C-a", "return", "space"]);
+        assert!(parse_keys("C-a j+k").is_err());
+        assert!(parse_keys("C-a unknown").is_err());
+        assert_eq!(parse_keys("  ")?, vec![]);
+
+        let keys = parse_keys("RM-v Fn-up")?;
+        assert_eq!(keys[0].device_flags, kCGEventFlagMaskRightCommand);
+        assert_eq!(keys[1].flags, kCGEventFlagMaskSecondaryFn);
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_events() -> anyhow::Result<()> {
+        let a = keycode::get_keycode("a").unwrap() as i64;
+        let ret = keycode::get_keycode("RET").unwrap() as i64;
+        let events = key_events(&parse_keys("C-a RET")?, Some(kCGEventFlagMaskShift));
+        let events: Vec<(&str, i64, u64)> = events
+            .iter()
+            .map(|event| (event.event_type.as_str(), event.keycode, event.flags))
+            .collect();
+        let control = kCGEventFlagMaskControl;
+        let released = kCGEventFlagMaskNonCoalesced;
+        assert_eq!(
+            events,
+            vec![
+                // The held modifiers are released first.
+                ("flagsChanged", 0, released),
+                ("flagsChanged", 0, control),
+                ("keyDown", a, control),
+                ("keyUp", a, control),
+                ("flagsChanged", 0, released),
+                ("keyDown", ret, 0),
+                ("keyUp", ret, 0),
+                // And restored at the end.
+                ("flagsChanged", 0, kCGEventFlagMaskShift),
+            ]
+        );
+
+        let events = key_events(&parse_keys("RM-v")?, None);
+        assert_eq!(events.len(), 5);
+        assert_eq!(
+            events[1].flags,
+            kCGEventFlagMaskCommand | kCGEventFlagMaskRightCommand
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,804 @@
+use anyhow::anyhow;
+use boa_engine::ast::expression::access::PropertyAccessField;
+use boa_engine::ast::expression::literal::LiteralKind;
+use boa_engine::ast::expression::Identifier;
+use boa_engine::ast::function::{ArrowFunction, AsyncArrowFunction, FunctionBody};
+use boa_engine::ast::property::PropertyName;
+use boa_engine::ast::scope::Scope;
+use boa_engine::ast::statement::{DoWhileLoop, ForLoop, WhileLoop, With};
+use boa_engine::ast::visitor::{VisitWith, Visitor};
+use boa_engine::ast::{Expression, Position, Span, Spanned, Statement, StatementListItem};
+use boa_engine::interner::{Interner, Sym};
+use boa_engine::parser::Parser;
+use boa_engine::Source;
+use std::convert::Infallible;
+use std::ops::{ControlFlow, Range};
+
+/// The native which checks the budget of the running callback. See `Budget::tick`.
+pub(crate) const TICK: &str = "$$tick";
+
+/// Inserts a `$$tick()` call at the start of every function body and into the condition of every
+/// loop, so that the budget of a callback also covers the functions it calls.
+///
+/// Nothing is inserted across lines, so the line numbers of the errors stay as they are.
+pub(crate) fn instrument(src: &str) -> anyhow::Result<String> {
+    let mut interner = Interner::default();
+    let script = Parser::new(Source::from_bytes(src))
+        .parse_script(&Scope::new_global(), &mut interner)
+        .map_err(|err| anyhow!("Syntax error: {}", err))?;
+
+    let lines = Lines::new(src);
+    let mut collector = Collector {
+        src,
+        lines: &lines,
+        tick: interner.get(TICK),
+        edits: Vec::new(),
+        regexps: Vec::new(),
+        arrows: Vec::new(),
+        loops: 0,
+        error: None,
+    };
+    let _ = script.visit_with(&mut collector);
+    if let Some(err) = collector.error {
+        return Err(err);
+    }
+
+    // The spans of the parser are off for some expressions, e.g. a call starts at its arguments,
+    // so the loop conditions and the concise bodies of arrow functions are found by the scanner.
+    // The parser tells how many there are.
+    let scan = scan(src, &collector.regexps, &collector.arrows);
+    if scan.loops != collector.loops || scan.arrows != collector.arrows.len() {
+        return Err(anyhow!(
+            "Cannot instrument the plugin: found {} of {} loops, and {} of {} arrow functions",
+            scan.loops,
+            collector.loops,
+            scan.arrows,
+            collector.arrows.len()
+        ));
+    }
+    let mut edits = collector.edits;
+    edits.extend(scan.edits);
+
+    let instrumented = apply(src, edits);
+    Parser::new(Source::from_bytes(&instrumented))
+        .parse_script(&Scope::new_global(), &mut Interner::default())
+        .map_err(|err| anyhow!("Cannot instrument the plugin: {}", err))?;
+    Ok(instrumented)
+}
+
+struct Edit {
+    offset: usize,
+    // Closing parentheses go before the text opened at the same offset.
+    close: bool,
+    text: &'static str,
+}
+
+impl Edit {
+    fn open(offset: usize, text: &'static str) -> Self {
+        Edit {
+            offset,
+            close: false,
+            text,
+        }
+    }
+
+    fn close(offset: usize) -> Self {
+        Edit {
+            offset,
+            close: true,
+            text: ")",
+        }
+    }
+}
+
+fn apply(src: &str, mut edits: Vec<Edit>) -> String {
+    edits.sort_by_key(|edit| (edit.offset, !edit.close));
+    let mut result = String::with_capacity(src.len() + edits.len() * 16);
+    let mut copied = 0;
+    for edit in edits {
+        result.push_str(&src[copied..edit.offset]);
+        result.push_str(edit.text);
+        copied = edit.offset;
+    }
+    result.push_str(&src[copied..]);
+    result
+}
+
+// Converts the positions of the parser, lines and columns counted in characters, to byte offsets.
+struct Lines {
+    starts: Vec<usize>,
+}
+
+impl Lines {
+    fn new(src: &str) -> Self {
+        let mut starts = vec![0];
+        let mut chars = src.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\r' if matches!(chars.peek(), Some((_, '\n'))) => {}
+                '\n' | '\r' | '\u{2028}' | '\u{2029}' => starts.push(i + c.len_utf8()),
+                _ => {}
+            }
+        }
+        Lines { starts }
+    }
+
+    fn offset(&self, src: &str, position: Position) -> Option<usize> {
+        let start = *self.starts.get((position.line_number() as usize).checked_sub(1)?)?;
+        let column = (position.column_number() as usize).checked_sub(1)?;
+        let mut chars = src[start..].char_indices().map(|(i, _)| start + i);
+        Some(chars.nth(column).unwrap_or(src.len()))
+    }
+}
+
+struct Collector<'a> {
+    src: &'a str,
+    lines: &'a Lines,
+    // None if the plugin doesn't mention `$$tick` at all.
+    tick: Option<Sym>,
+    edits: Vec<Edit>,
+    regexps: Vec<Range<usize>>,
+    arrows: Vec<ConciseArrow>,
+    loops: usize,
+    error: Option<anyhow::Error>,
+}
+
+// An arrow function with a concise body, `(a) => expression`, as the parser sees it.
+struct ConciseArrow {
+    range: Range<usize>,
+    // Where the body ends at the earliest.
+    body_end: usize,
+}
+
+impl Collector<'_> {
+    fn range(&mut self, span: Span) -> Option<Range<usize>> {
+        let start = self.lines.offset(self.src, span.start());
+        let end = self.lines.offset(self.src, span.end());
+        match (start, end) {
+            (Some(start), Some(end)) if start <= end => Some(start..end),
+            _ => {
+                self.error
+                    .get_or_insert_with(|| anyhow!("Cannot instrument the plugin: {:?}", span));
+                None
+            }
+        }
+    }
+
+    fn add_arrow(&mut self, span: Span, body: &FunctionBody) {
+        if !is_concise(body) {
+            return;
+        }
+        if let (Some(range), Some(body)) = (self.range(span), self.range(body.span())) {
+            self.arrows.push(ConciseArrow {
+                range,
+                body_end: body.end,
+            });
+        }
+    }
+}
+
+// The body of `() => expression` is a return statement spanning the expression.
+fn is_concise(body: &FunctionBody) -> bool {
+    match body.statements() {
+        [StatementListItem::Statement(statement)] => match statement.as_ref() {
+            Statement::Return(ret) => ret
+                .target()
+                .is_some_and(|target| target.span() == body.span()),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+impl<'ast> Visitor<'ast> for Collector<'_> {
+    type BreakTy = Infallible;
+
+    fn visit_function_body(&mut self, node: &'ast FunctionBody) -> ControlFlow<Self::BreakTy> {
+        if !is_concise(node) {
+            if let Some(range) = self.range(node.span()) {
+                // After the directives, e.g. "use strict", which must come first.
+                let mut offset = range.start + 1;
+                let mut text = "$$tick();";
+                for statement in node.statements() {
+                    let StatementListItem::Statement(statement) = statement else {
+                        break;
+                    };
+                    let Statement::Expression(Expression::Literal(literal)) = statement.as_ref()
+                    else {
+                        break;
+                    };
+                    if !matches!(literal.kind(), LiteralKind::String(_)) {
+                        break;
+                    }
+                    if let Some(range) = self.range(literal.span()) {
+                        offset = range.end;
+                        text = ";$$tick();";
+                    }
+                }
+                self.edits.push(Edit::open(offset, text));
+            }
+        }
+        node.visit_with(self)
+    }
+
+    fn visit_arrow_function(&mut self, node: &'ast ArrowFunction) -> ControlFlow<Self::BreakTy> {
+        self.add_arrow(node.span(), node.body());
+        node.visit_with(self)
+    }
+
+    fn visit_async_arrow_function(
+        &mut self,
+        node: &'ast AsyncArrowFunction,
+    ) -> ControlFlow<Self::BreakTy> {
+        self.add_arrow(node.span(), node.body());
+        node.visit_with(self)
+    }
+
+    fn visit_while_loop(&mut self, node: &'ast WhileLoop) -> ControlFlow<Self::BreakTy> {
+        self.loops += 1;
+        node.visit_with(self)
+    }
+
+    fn visit_do_while_loop(&mut self, node: &'ast DoWhileLoop) -> ControlFlow<Self::BreakTy> {
+        self.loops += 1;
+        node.visit_with(self)
+    }
+
+    fn visit_for_loop(&mut self, node: &'ast ForLoop) -> ControlFlow<Self::BreakTy> {
+        self.loops += 1;
+        node.visit_with(self)
+    }
+
+    fn visit_with(&mut self, _node: &'ast With) -> ControlFlow<Self::BreakTy> {
+        // Its object could shadow `$$tick`.
+        self.error
+            .get_or_insert_with(|| anyhow!("The `with` statement isn't supported in plugins"));
+        ControlFlow::Continue(())
+    }
+
+    // A binding or a reference could shadow or replace `$$tick`. Property names can't, and strings
+    // and comments aren't identifiers.
+    fn visit_identifier(&mut self, node: &'ast Identifier) -> ControlFlow<Self::BreakTy> {
+        if Some(node.sym()) == self.tick {
+            self.error
+                .get_or_insert_with(|| anyhow!("`{}` is reserved", TICK));
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn visit_property_access_field(
+        &mut self,
+        node: &'ast PropertyAccessField,
+    ) -> ControlFlow<Self::BreakTy> {
+        match node {
+            PropertyAccessField::Const(_) => ControlFlow::Continue(()),
+            PropertyAccessField::Expr(_) => node.visit_with(self),
+        }
+    }
+
+    fn visit_property_name(&mut self, node: &'ast PropertyName) -> ControlFlow<Self::BreakTy> {
+        match node {
+            PropertyName::Literal(_) => ControlFlow::Continue(()),
+            PropertyName::Computed(_) => node.visit_with(self),
+        }
+    }
+
+    fn visit_expression(&mut self, node: &'ast Expression) -> ControlFlow<Self::BreakTy> {
+        if let Expression::RegExpLiteral(regexp) = node {
+            if let Some(range) = self.range(regexp.span()) {
+                self.regexps.push(range);
+            }
+        }
+        node.visit_with(self)
+    }
+}
+
+struct Scan {
+    edits: Vec<Edit>,
+    // The loops and the concise arrow bodies found.
+    loops: usize,
+    arrows: usize,
+}
+
+// Wraps the conditions of the `while`, `do-while` and `for (init; condition; update)` loops, and
+// the concise bodies of the arrow functions. The regular expression literals, given by the
+// parser, are skipped since a lexer can't tell them from divisions.
+fn scan(src: &str, regexps: &[Range<usize>], arrows: &[ConciseArrow]) -> Scan {
+    let mut scanner = Scanner {
+        src: src.as_bytes(),
+        regexps,
+        arrows,
+        pos: 0,
+        last_end: 0,
+        line_break: false,
+        scan: Scan {
+            edits: Vec::new(),
+            loops: 0,
+            arrows: 0,
+        },
+    };
+    scanner.skip_hashbang();
+    scanner.code(false);
+    scanner.scan
+}
+
+// The brackets opened in the code, with the code found in the loop headers.
+enum Bracket {
+    Paren,
+    Other,
+    // The header of `for`: the offsets of its semicolons, and the code between them.
+    For {
+        semicolons: Vec<usize>,
+        condition: Option<Range<usize>>,
+    },
+    While {
+        condition: Option<Range<usize>>,
+    },
+}
+
+// The keyword before a `(`.
+#[derive(PartialEq)]
+enum Keyword {
+    None,
+    For,
+    While,
+}
+
+// A concise arrow body being scanned. It ends at a `,`, `;`, `:` or closing bracket of its own
+// depth, or at a line break after the end given by the parser.
+struct ArrowBody {
+    start: usize,
+    depth: usize,
+    body_end: usize,
+    // The `?` of the body, waiting for their `:`.
+    questions: usize,
+}
+
+struct Scanner<'a> {
+    src: &'a [u8],
+    regexps: &'a [Range<usize>],
+    arrows: &'a [ConciseArrow],
+    pos: usize,
+    // The end of the last token, and whether a line break follows it.
+    last_end: usize,
+    line_break: bool,
+    scan: Scan,
+}
+
+impl Scanner<'_> {
+    fn peek(&self, offset: usize) -> Option<u8> {
+        self.src.get(self.pos + offset).copied()
+    }
+
+    fn skip_hashbang(&mut self) {
+        if self.src.starts_with(b"#!") {
+            self.skip_line();
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.peek(0) {
+            if c == b'\n' || c == b'\r' {
+                break;
+            }
+            self.pos += 1;
+        }
+    }
+
+    // Skips whitespace and comments. Returns false at the end of the source.
+    fn skip_trivia(&mut self) -> bool {
+        self.line_break = false;
+        loop {
+            match (self.peek(0), self.peek(1)) {
+                (Some(b'/'), Some(b'/')) => self.skip_line(),
+                (Some(b'/'), Some(b'*')) => self.skip_block_comment(),
+                (Some(c), _) if c.is_ascii_whitespace() => {
+                    self.line_break |= c == b'\n' || c == b'\r';
+                    self.pos += 1;
+                }
+                // U+00A0, U+FEFF, U+2028 and the like.
+                (Some(c), _) if c >= 0x80 && self.non_ascii_whitespace() => {}
+                (Some(_), _) => return true,
+                (None, _) => return false,
+            }
+        }
+    }
+
+    fn non_ascii_whitespace(&mut self) -> bool {
+        let rest = &self.src[self.pos..];
+        let len = utf8_len(rest[0]).min(rest.len());
+        match std::str::from_utf8(&rest[..len]).ok().and_then(|s| s.chars().next()) {
+            Some(c) if c.is_whitespace() || c == '\u{FEFF}' => {
+                self.line_break |= c == '\u{2028}' || c == '\u{2029}';
+                self.pos += len;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn skip_block_comment(&mut self) {
+        self.pos += 2;
+        while self.pos < self.src.len() {
+            if self.src[self.pos..].starts_with(b"*/") {
+                self.pos += 2;
+                return;
+            }
+            if self.src[self.pos] >= 0x80 && self.non_ascii_whitespace() {
+                continue;
+            }
+            self.line_break |= matches!(self.src[self.pos], b'\n' | b'\r');
+            self.pos += 1;
+        }
+    }
+
+    fn skip_string(&mut self, quote: u8) {
+        self.pos += 1;
+        while let Some(c) = self.peek(0) {
+            self.pos += 1;
+            if c == b'\\' {
+                self.pos += 1;
+            } else if c == quote {
+                return;
+            }
+        }
+    }
+
+    fn skip_template(&mut self) {
+        self.pos += 1;
+        while let Some(c) = self.peek(0) {
+            match c {
+                b'\\' => self.pos += 2,
+                b'`' => {
+                    self.pos += 1;
+                    return;
+                }
+                b'$' if self.peek(1) == Some(b'{') => {
+                    self.pos += 2;
+                    self.code(true);
+                }
+                _ => self.pos += 1,
+            }
+        }
+    }
+
+    // Scans code up to the end, or past the `}` which closes a template substitution.
+    fn code(&mut self, substitution: bool) {
+        let mut brackets: Vec<Bracket> = Vec::new();
+        let mut bodies: Vec<ArrowBody> = Vec::new();
+        let mut keyword = Keyword::None;
+        while self.skip_trivia() {
+            if self.line_break {
+                self.end_bodies(&mut bodies, brackets.len(), |body, end| end >= body.body_end);
+            }
+            let c = self.src[self.pos];
+            if !matches!(c, b';' | b')' | b']' | b'}') {
+                self.mark_code(&mut brackets);
+            }
+            let start = self.pos;
+            if let Some(regexp) = self.regexps.iter().find(|regexp| regexp.start == self.pos) {
+                self.pos = regexp.end.max(self.pos + 1);
+                self.last_end = self.pos;
+                keyword = Keyword::None;
+                continue;
+            }
+            if c == b'(' && keyword != Keyword::None {
+                brackets.push(match keyword {
+                    Keyword::For => Bracket::For {
+                        semicolons: Vec::new(),
+                        condition: None,
+                    },
+                    _ => Bracket::While { condition: None },
+                });
+                keyword = Keyword::None;
+                self.pos += 1;
+                self.last_end = self.pos;
+                continue;
+            }
+            if is_identifier_byte(c) {
+                while self.peek(0).is_some_and(is_identifier_byte) {
+                    self.pos += 1;
+                }
+                let word = &self.src[start..self.pos];
+                let property = start > 0 && self.src[..start].ends_with(b".");
+                keyword = match word {
+                    _ if property => Keyword::None,
+                    b"for" => Keyword::For,
+                    b"while" => Keyword::While,
+                    // `for await (`
+                    b"await" if keyword == Keyword::For => Keyword::For,
+                    _ => Keyword::None,
+                };
+                self.last_end = self.pos;
+                continue;
+            }
+            keyword = Keyword::None;
+            match c {
+                b'\'' | b'"' => self.skip_string(c),
+                b'`' => self.skip_template(),
+                b'(' => {
+                    brackets.push(Bracket::Paren);
+                    self.pos += 1;
+                }
+                b'[' | b'{' => {
+                    brackets.push(Bracket::Other);
+                    self.pos += 1;
+                }
+                b')' | b']' | b'}' => {
+                    self.end_bodies(&mut bodies, brackets.len(), |_, _| true);
+                    match brackets.pop() {
+                        Some(Bracket::For {
+                            semicolons,
+                            condition,
+                        }) if semicolons.len() == 2 => {
+                            self.scan.loops += 1;
+                            match condition {
+                                Some(condition) => self.wrap_condition(condition.start, condition.end),
+                                None => self
+                                    .scan
+                                    .edits
+                                    .push(Edit::open(semicolons[0] + 1, "$$tick()")),
+                            }
+                        }
+                        // Or else a method named `while`.
+                        Some(Bracket::While {
+                            condition: Some(condition),
+                        }) => {
+                            self.scan.loops += 1;
+                            self.wrap_condition(condition.start, self.last_end);
+                        }
+                        None if substitution => {
+                            self.pos += 1;
+                            return;
+                        }
+                        _ => {}
+                    }
+                    self.pos += 1;
+                }
+                b',' | b';' => {
+                    self.end_bodies(&mut bodies, brackets.len(), |_, _| true);
+                    if c == b';' {
+                        if let Some(Bracket::For {
+                            semicolons,
+                            condition,
+                        }) = brackets.last_mut()
+                        {
+                            semicolons.push(self.pos);
+                            if let Some(condition) = condition {
+                                if semicolons.len() == 2 {
+                                    condition.end = self.last_end;
+                                }
+                            }
+                        }
+                    }
+                    self.pos += 1;
+                }
+                b':' => {
+                    let depth = brackets.len();
+                    self.end_bodies(&mut bodies, depth, |body, _| body.questions == 0);
+                    if let Some(body) = bodies.last_mut().filter(|body| body.depth == depth) {
+                        body.questions = body.questions.saturating_sub(1);
+                    }
+                    self.pos += 1;
+                }
+                // `??` and `?.`, but not `a ?.5 : b`.
+                b'?' if self.peek(1) == Some(b'?')
+                    || (self.peek(1) == Some(b'.')
+                        && !self.peek(2).is_some_and(|c| c.is_ascii_digit())) =>
+                {
+                    self.pos += 2;
+                }
+                b'?' => {
+                    let depth = brackets.len();
+                    if let Some(body) = bodies.last_mut().filter(|body| body.depth == depth) {
+                        body.questions += 1;
+                    }
+                    self.pos += 1;
+                }
+                b'=' if self.peek(1) == Some(b'>') => {
+                    let arrow = self.pos;
+                    self.pos += 2;
+                    self.last_end = self.pos;
+                    if self.skip_trivia() && self.src[self.pos] != b'{' {
+                        self.scan.arrows += 1;
+                        bodies.push(ArrowBody {
+                            start: self.pos,
+                            depth: brackets.len(),
+                            body_end: self.body_end(arrow),
+                            questions: 0,
+                        });
+                    }
+                    continue;
+                }
+                _ => self.pos += 1,
+            }
+            self.last_end = self.pos;
+        }
+        self.end_bodies(&mut bodies, 0, |_, _| true);
+    }
+
+    // Code starts in the innermost bracket.
+    fn mark_code(&self, brackets: &mut [Bracket]) {
+        match brackets.last_mut() {
+            Some(Bracket::For {
+                semicolons,
+                condition: condition @ None,
+            }) if semicolons.len() == 1 => *condition = Some(self.pos..self.pos),
+            Some(Bracket::While {
+                condition: condition @ None,
+            }) => *condition = Some(self.pos..self.pos),
+            _ => {}
+        }
+    }
+
+    // `$$tick() && (condition)`
+    fn wrap_condition(&mut self, start: usize, end: usize) {
+        self.scan.edits.push(Edit::open(start, "$$tick() && ("));
+        self.scan.edits.push(Edit::close(end));
+    }
+
+    // The end of the body given by the parser, for the arrow function of the `=>` at `arrow`.
+    fn body_end(&self, arrow: usize) -> usize {
+        self.arrows
+            .iter()
+            .filter(|candidate| candidate.range.contains(&arrow))
+            .max_by_key(|candidate| candidate.range.start)
+            .map_or(arrow, |candidate| candidate.body_end)
+    }
+
+    // Ends the innermost bodies of the given depth while `ends` holds, wrapping them into
+    // `($$tick(), body)`.
+    fn end_bodies(
+        &mut self,
+        bodies: &mut Vec<ArrowBody>,
+        depth: usize,
+        ends: impl Fn(&ArrowBody, usize) -> bool,
+    ) {
+        while let Some(body) = bodies.last() {
+            if body.depth < depth || !ends(body, self.last_end) {
+                break;
+            }
+            self.scan.edits.push(Edit::open(body.start, "($$tick(), "));
+            self.scan.edits.push(Edit::close(self.last_end));
+            bodies.pop();
+        }
+    }
+}
+
+fn is_identifier_byte(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c == b'$' || c == b'#' || c == b'\\' || c >= 0x80
+}
+
+fn utf8_len(first: u8) -> usize {
+    match first {
+        0xF0.. => 4,
+        0xE0.. => 3,
+        0xC0.. => 2,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_functions() -> anyhow::Result<()> {
+        assert_eq!(
+            instrument("function f(a) { return a; }")?,
+            "function f(a) {$$tick(); return a; }"
+        );
+        assert_eq!(
+            instrument("const f = (a) => a + 1, g = async () => ({ a: 1 });")?,
+            "const f = (a) => ($$tick(), a + 1), g = async () => ($$tick(), ({ a: 1 }));"
+        );
+        // The parser's spans of calls, tagged templates and computed members are off.
+        assert_eq!(
+            instrument("const f = (r) => setTimeout(r, 1), g = (t) => t`x`, h = (a) => a[0];")?,
+            "const f = (r) => ($$tick(), setTimeout(r, 1)), g = (t) => ($$tick(), t`x`), h = (a) => ($$tick(), a[0]);"
+        );
+        assert_eq!(
+            instrument("f(a => b => a ? b : c, d => d, `${e => e}`)")?,
+            "f(a => ($$tick(), b => ($$tick(), a ? b : c)), d => ($$tick(), d), `${e => ($$tick(), e)}`)"
+        );
+        // A line break ends the body only where the parser ends it.
+        assert_eq!(
+            instrument("const f = x => x\n  + 1\nconst g = y => y // y\ng()")?,
+            "const f = x => ($$tick(), x\n  + 1)\nconst g = y => ($$tick(), y) // y\ng()"
+        );
+        assert_eq!(
+            instrument("const f = (a) => { return a; };")?,
+            "const f = (a) => {$$tick(); return a; };"
+        );
+        assert_eq!(
+            instrument("class A { m() {} get x() { return 1; } static { init(); } }")?,
+            "class A { m() {$$tick();} get x() {$$tick(); return 1; } static {$$tick(); init(); } }"
+        );
+        // The directives stay first.
+        assert_eq!(
+            instrument("function f() {\n  'use strict'\n  g();\n}")?,
+            "function f() {\n  'use strict';$$tick();\n  g();\n}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_loops() -> anyhow::Result<()> {
+        assert_eq!(
+            instrument("while (i < n) i++; do { i--; } while (i, j)")?,
+            "while ($$tick() && (i < n)) i++; do { i--; } while ($$tick() && (i, j))"
+        );
+        assert_eq!(
+            instrument("for (let i = 0; i < 9; i++) {} for (;;) {} for (x of y) {}")?,
+            "for (let i = 0; $$tick() && (i < 9); i++) {} for (;$$tick();) {} for (x of y) {}"
+        );
+        // A loop calling a function which loops: both are instrumented.
+        assert_eq!(
+            instrument("for(;;){ (()=>{ for(let i=0;i<900000;i++){} })(); }")?,
+            "for(;$$tick();){ (()=>{$$tick(); for(let i=0;$$tick() && (i<900000);i++){} })(); }"
+        );
+        assert_eq!(
+            instrument("while (f(x)) {} while (a.b[0]) {} for (; g`t`; ) {} o = { while() {} };")?,
+            "while ($$tick() && (f(x))) {} while ($$tick() && (a.b[0])) {} for (; $$tick() && (g`t`); ) {} o = { while() {$$tick();} };"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_for_conditions() -> anyhow::Result<()> {
+        // Lookalikes of `for (;;)` in strings, comments, templates, regular expressions and
+        // property names.
+        let src = r#"
+            const s = "for (;;) {}", t = `for (;;) ${ "}" + `${ 1 }` }`;
+            // for (;;) {}
+            /* for (;;) {} */
+            const r = /for (;;)[/]/g, o = { for() {}, x: o.for };
+            for (let i = /;/.test(s) ? 0 : 1; /* ; */ ; i++) { for (; ; ) break; }
+            for (const a of [1, 2]) {}
+        "#;
+        let instrumented = instrument(src)?;
+        assert_eq!(instrumented.matches("$$tick()").count(), 3);
+        assert!(instrumented.contains("o = { for() {$$tick();}, x: o.for };"));
+        assert!(instrumented.contains("1;$$tick() /* ; */ ; i++) { for (;$$tick() ; ) break; }"));
+        assert!(instrumented.contains(r#""for (;;) {}""#));
+        assert!(instrumented.contains("/for (;;)[/]/g"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_positions() -> anyhow::Result<()> {
+        // Columns count characters, and lines end with any line terminator.
+        assert_eq!(
+            instrument("const s = \"日本語\"; /*\u{2028}*/\r\nwhile (s) {}\rdo {} while (s)")?,
+            "const s = \"日本語\"; /*\u{2028}*/\r\nwhile ($$tick() && (s)) {}\rdo {} while ($$tick() && (s))"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_tick_in_strings_and_names() -> anyhow::Result<()> {
+        for src in [
+            "const msg = \"uses $$tick\";",
+            "// $$tick is called by the budget\nf();",
+            "/* $$tick */ const t = `$$tick ${1}`;",
+            "o.$$tick; o = { $$tick: 1 }; o[\"$$tick\"];",
+        ] {
+            assert_eq!(instrument(src)?, src);
+        }
+        assert_eq!(
+            instrument("class C { $$tick() {} }")?,
+            "class C { $$tick() {$$tick();} }"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejected() {
+        assert!(instrument("const $$tick = () => true;").is_err());
+        assert!(instrument("function f($$tick) {}").is_err());
+        assert!(instrument("$$tick = null;").is_err());
+        assert!(instrument("const o = { $$tick };").is_err());
+        assert!(instrument("with (o) { f(); }").is_err());
+        assert!(instrument("while (").is_err());
+    }
+}
@@ -1,68 +1,213 @@
 use anyhow::anyhow;
-use apple_sys::CoreGraphics::{CGEventRef, CGEventType};
+use crate::app_config::{order_priorities, AppConfig, ConfigData};
+use crate::backend::{BackendData, InputBackend};
 use crate::cg_constants::{
     kCGEventFlagsChanged, kCGEventKeyDown, kCGEventKeyUp, kCGEventFlagMaskAlphaShift,
     kCGEventFlagMaskAlternate, kCGEventFlagMaskCommand, kCGEventFlagMaskControl,
     kCGEventFlagMaskHelp, kCGEventFlagMaskNonCoalesced, kCGEventFlagMaskNumericPad,
-    kCGEventFlagMaskSecondaryFn, kCGEventFlagMaskShift, kCGKeyboardEventKeycode,
+    kCGEventFlagMaskSecondaryFn, kCGEventFlagMaskShift, kCGEventLeftMouseDown,
+    kCGEventLeftMouseDragged, kCGEventLeftMouseUp, kCGEventMouseMoved, kCGEventOtherMouseDown,
+    kCGEventOtherMouseDragged, kCGEventOtherMouseUp, kCGEventRightMouseDown,
+    kCGEventRightMouseDragged, kCGEventRightMouseUp, kCGEventScrollWheel, kCGKeyboardEventKeycode,
+    kCGMouseButtonCenter, kCGMouseButtonLeft, kCGMouseButtonRight,
 };
-use boa_engine::{js_string, Context, JsObject, JsValue, NativeFunction, Source};
+use boa_engine::{
+    js_string, Context, JsObject, JsResult, JsValue, NativeFunction, Script, Source,
+};
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::Debug;
-use std::sync::mpsc::{Receiver, TryRecvError};
 use std::sync::{Arc, RwLock};
 
 use boa_engine::native_function::NativeFunctionPointer;
-use boa_engine::object::builtins::JsFunction;
+use boa_engine::object::builtins::{JsArray, JsFunction};
+use boa_engine::realm::Realm;
 use boa_engine::property::{Attribute, PropertyKey};
 use boa_engine::value::TryFromJs;
 
-use crate::event::Event;
+use crate::event::{cg_event_type, is_motion_event, is_mouse_event, Event};
 use crate::js_console::Console;
 use serde::{Deserialize, Serialize};
 
 use crate::js_builtin::JsBuiltin;
 use crate::js_hotkey::JsHotKey;
+use crate::js_job::{job_realm, run_job, JobQueue};
 use crate::js_keycode::build_keycode;
-use crate::js_operation::JsOperation;
+use crate::instrument::{instrument, TICK};
+use crate::js_realm::{DriverCall, PluginHooks, PluginRealm, STORAGE_API};
+use crate::js_operation::{
+    JsOperation, JsOperationOutput, JsOperationReceiver, PluginStats, RuntimeStats,
+    PROTOCOL_VERSION,
+};
+use crate::manifest::{parse_version, PluginManifest, APP_VERSION};
 use crate::plugin::Plugins;
+use crate::registry::SharedPluginRegistry;
+use crate::clock::{Clock, ClockData};
+use crate::storage::{PluginStorage, StorageData};
+use crate::trace::{default_trace_path, InjectedEvents, TraceWriter};
+use crate::watchdog::{
+    Budget, Overrun, Watchdog, DEFAULT_CALLBACK_BUDGET, LOAD_BUDGET, MAX_OFFENCES,
+};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+// Builtins available to the driver and the plugins.
+const PUBLIC_BUILTINS: &[(&str, NativeFunctionPointer)] = &[
+    ("sendFlagsChangedEvent", JsBuiltin::send_flags_changed_event),
+    ("sendKeyboardEvent", JsBuiltin::send_keyboard_event),
+    ("sendMouseEvent", JsBuiltin::send_mouse_event),
+    ("sendScroll", JsBuiltin::send_scroll),
+    ("sendText", JsBuiltin::send_text),
+    ("sendKeys", JsBuiltin::send_keys),
+];
+
+// Builtins only driver.js can use.
+const DRIVER_BUILTINS: &[(&str, NativeFunctionPointer)] = &[
+    ("$$storageFlush", JsBuiltin::storage_flush),
+    ("$$now", JsBuiltin::now),
+    ("$$loadAppConfigJson", JsBuiltin::load_app_config_json),
+    // For the instrumented snippets of `JsOperation::Eval` with `driver`.
+    (TICK, Budget::tick),
+];
+
+// Functions of the plugin realms which call driver.js: the global name, the function of driver.js,
+// and the native which calls it.
+const DRIVER_API: &[(&str, &str, DriverCall)] = &[
+    ("registerPlugin", "$$registerPlugin", PluginRealm::register_plugin),
+    ("setTimeout", "$$setTimeout", PluginRealm::call_driver),
+    ("setInterval", "$$setInterval", PluginRealm::call_driver),
+    ("clearTimeout", "$$clearTimer", PluginRealm::call_driver),
+    ("clearInterval", "$$clearTimer", PluginRealm::call_driver),
+];
+
+// Freezes the injected globals of a plugin realm and makes them read-only.
+const LOCK_GLOBALS: &str = r#"
+(names, denied) => {
+    for (const [name, permission] of denied) {
+        const deny = () => {
+            throw new Error(`${name} requires the "${permission}" permission in the plugin manifest`);
+        };
+        // Also for the methods, e.g. `storage.get`.
+        globalThis[name] = new Proxy(deny, { get: () => deny });
+    }
+    for (const name of names) {
+        Object.freeze(globalThis[name]);
+        Object.defineProperty(globalThis, name, { writable: false, configurable: false });
+    }
+    Object.freeze(HotKey.prototype);
+}
+"#;
 
 pub struct JS {
     context: Context,
-    js_operation_rx: Option<Receiver<JsOperation>>,
+    jobs: Rc<JobQueue>,
+    js_operation_rx: Option<JsOperationReceiver>,
     monitoring_queue: Option<Arc<RwLock<VecDeque<Event>>>>,
     plugins: Option<Plugins>,
     plugin_id2filename: HashMap<String, String>,
+    // plugin id -> why the manifest of its file can't be read. Such files don't run, but are kept
+    // in `plugin_id2filename`, keyed by the declared `@id` or else by the filename, so that they
+    // can be fixed or deleted.
+    manifest_errors: HashMap<String, String>,
+    trace: Option<TraceWriter>,
+    watchdog: Watchdog,
+    callback_budget: Duration,
+    // Compared with `minAppVersion` of the manifests.
+    app_version: String,
+    registry: Option<SharedPluginRegistry>,
+    // Whether the watchdog, `SetPluginEnabled` and `SetPluginOrder` write to config.json.
+    save_config: bool,
+    paused: bool,
+    // Events dispatched to the plugins, and the time spent on them.
+    events: u64,
+    event_micros: u64,
 }
 
 impl JS {
     pub fn new(
-        js_operation_rx: Option<Receiver<JsOperation>>,
+        js_operation_rx: Option<JsOperationReceiver>,
+        monitoring_queue: Option<Arc<RwLock<VecDeque<Event>>>>,
+        plugins: Option<Plugins>,
+        backend: Option<Arc<dyn InputBackend>>,
+    ) -> anyhow::Result<Self> {
+        Self::build(js_operation_rx, monitoring_queue, plugins, backend, None)
+    }
+
+    /// Runs the plugins with `config` instead of config.json, which is neither read nor written.
+    /// For tests, replays and checks, whose results must not depend on the user's configuration.
+    pub fn new_with_config(
+        js_operation_rx: Option<JsOperationReceiver>,
+        monitoring_queue: Option<Arc<RwLock<VecDeque<Event>>>>,
+        plugins: Option<Plugins>,
+        backend: Option<Arc<dyn InputBackend>>,
+        config: AppConfig,
+    ) -> anyhow::Result<Self> {
+        Self::build(js_operation_rx, monitoring_queue, plugins, backend, Some(config))
+    }
+
+    fn build(
+        js_operation_rx: Option<JsOperationReceiver>,
         monitoring_queue: Option<Arc<RwLock<VecDeque<Event>>>>,
         plugins: Option<Plugins>,
+        backend: Option<Arc<dyn InputBackend>>,
+        config: Option<AppConfig>,
     ) -> anyhow::Result<Self> {
-        let context = Context::default();
+        let save_config = config.is_none();
+        let jobs = Rc::new(JobQueue::default());
+        let mut context = Context::builder()
+            .job_executor(jobs.clone())
+            .host_hooks(Rc::new(PluginHooks))
+            .build()
+            .map_err(|err| anyhow!("Cannot create the context: {:?}", err))?;
+        context.insert_data(ClockData::default());
+        context.insert_data(Budget::default());
+        context.insert_data(ConfigData { config });
+        let storage_path = match PluginStorage::default_path() {
+            Ok(path) => Some(path),
+            Err(err) => {
+                log::error!("Plugin storage is not persisted: {:?}", err);
+                None
+            }
+        };
+        context.insert_data(StorageData {
+            storage: RefCell::new(PluginStorage::new(storage_path)),
+        });
+        if let Some(backend) = backend {
+            context.insert_data(BackendData { backend });
+        }
 
         let mut js = JS {
             context,
+            jobs,
             js_operation_rx,
             monitoring_queue,
             plugins,
             plugin_id2filename: HashMap::new(),
+            manifest_errors: HashMap::new(),
+            trace: None,
+            watchdog: Watchdog::default(),
+            callback_budget: DEFAULT_CALLBACK_BUDGET,
+            app_version: APP_VERSION.to_string(),
+            registry: None,
+            save_config,
+            paused: false,
+            events: 0,
+            event_micros: 0,
         };
-        js.init_console()?;
-        js.init_hotkey()?;
-        js.init_keycode()?;
-        js.register_constants()?;
-        js.register_builtin_functions()?;
+        js.init_api()?;
+        let bridge = js.init_bridge()?;
+        js.register_constant(js_string!("$$REALM"), bridge)?;
+        js.register_builtin_functions(DRIVER_BUILTINS)?;
         js.load_driver()?;
+        js.lock_driver_realm()?;
         js.load_bundled()?;
         Ok(js)
     }
 
     pub fn get_filename_by_plugin_id(&self, plugin_id: &String) -> Option<String> {
         let result = self.plugin_id2filename.get(plugin_id).cloned();
-        if result == None {
+        if result.is_none() {
             log::info!(
                 "unknown plugin_id({})... current filename map is {:?}",
                 plugin_id,
@@ -141,6 +286,33 @@ impl JS {
             kCGEventFlagMaskNumericPad,
         )?;
 
+        // Mouse event types, for sendMouseEvent
+        self.register_constant(js_string!("kCGEventLeftMouseDown"), kCGEventLeftMouseDown)?;
+        self.register_constant(js_string!("kCGEventLeftMouseUp"), kCGEventLeftMouseUp)?;
+        self.register_constant(js_string!("kCGEventRightMouseDown"), kCGEventRightMouseDown)?;
+        self.register_constant(js_string!("kCGEventRightMouseUp"), kCGEventRightMouseUp)?;
+        self.register_constant(js_string!("kCGEventOtherMouseDown"), kCGEventOtherMouseDown)?;
+        self.register_constant(js_string!("kCGEventOtherMouseUp"), kCGEventOtherMouseUp)?;
+        self.register_constant(js_string!("kCGEventMouseMoved"), kCGEventMouseMoved)?;
+        self.register_constant(
+            js_string!("kCGEventLeftMouseDragged"),
+            kCGEventLeftMouseDragged,
+        )?;
+        self.register_constant(
+            js_string!("kCGEventRightMouseDragged"),
+            kCGEventRightMouseDragged,
+        )?;
+        self.register_constant(
+            js_string!("kCGEventOtherMouseDragged"),
+            kCGEventOtherMouseDragged,
+        )?;
+        self.register_constant(js_string!("kCGEventScrollWheel"), kCGEventScrollWheel)?;
+
+        // CGMouseButton
+        self.register_constant(js_string!("kCGMouseButtonLeft"), kCGMouseButtonLeft)?;
+        self.register_constant(js_string!("kCGMouseButtonRight"), kCGMouseButtonRight)?;
+        self.register_constant(js_string!("kCGMouseButtonCenter"), kCGMouseButtonCenter)?;
+
         Ok(())
     }
 
@@ -158,53 +330,384 @@ impl JS {
         Ok(())
     }
 
-    fn register_builtin_functions(&mut self) -> anyhow::Result<()> {
-        fn register(
-            context: &mut Context,
-            name: &str,
-            fn_ptr: NativeFunctionPointer,
-        ) -> anyhow::Result<()> {
-            if let Err(err) =
-                context.register_global_callable(js_string!(name), 1, NativeFunction::from_fn_ptr(fn_ptr))
-            {
+    fn register_builtin_functions(
+        &mut self,
+        builtins: &[(&str, NativeFunctionPointer)],
+    ) -> anyhow::Result<()> {
+        for (name, fn_ptr) in builtins {
+            if let Err(err) = self.context.register_global_callable(
+                js_string!(*name),
+                1,
+                NativeFunction::from_fn_ptr(*fn_ptr),
+            ) {
                 return Err(anyhow!("Cannot register `{}` function: {:?}", name, err));
             }
-
-            Ok(())
         }
+        Ok(())
+    }
 
-        register(
-            &mut self.context,
-            "sendFlagsChangedEvent",
-            JsBuiltin::send_flags_changed_event,
-        )?;
-        register(
-            &mut self.context,
-            "sendKeyboardEvent",
-            JsBuiltin::send_keyboard_event,
-        )?;
-        register(
-            &mut self.context,
-            "$$loadAppConfigJson",
-            JsBuiltin::load_app_config_json,
+    // Registers the API shared by the driver and the plugins into the current realm.
+    fn init_api(&mut self) -> anyhow::Result<()> {
+        self.init_console()?;
+        self.init_hotkey()?;
+        self.init_keycode()?;
+        self.register_constants()?;
+        self.register_builtin_functions(PUBLIC_BUILTINS)?;
+        Ok(())
+    }
+
+    // Evaluates realm.js in the current realm. driver.js calls into the realm through it.
+    fn init_bridge(&mut self) -> anyhow::Result<JsObject> {
+        let bridge = self
+            .context
+            .eval(Source::from_bytes(include_str!("../js/realm.js")))
+            .map_err(|err| anyhow!("Cannot evaluate realm.js: {:?}", err))?;
+        let bridge = bridge
+            .as_object()
+            .ok_or_else(|| anyhow!("realm.js must return an object"))?;
+        let owner = NativeFunction::from_fn_ptr(PluginRealm::owner)
+            .to_js_function(self.context.realm());
+        bridge
+            .set(js_string!("owner"), owner, false, &mut self.context)
+            .map_err(|err| anyhow!("Cannot set the owner of realm.js: {:?}", err))?;
+        Ok(bridge)
+    }
+
+    // Plugins only get the objects of their own realm. The prototypes are still frozen, in case
+    // one of the driver leaks.
+    fn lock_driver_realm(&mut self) -> anyhow::Result<()> {
+        self.eval(
+            "[Object.prototype, Array.prototype, Function.prototype, HotKey.prototype]\
+                .forEach((value) => Object.freeze(value));"
+                .to_string(),
         )?;
         Ok(())
     }
 
+    /// Evaluates a plugin file in a new realm.
+    ///
+    /// Each file gets its own global scope. The API (builtins, constants, `storage` and the
+    /// functions listed in `DRIVER_API`) is injected as frozen read-only globals. They are all
+    /// objects of the plugin realm, and driver.js passes only copies made in the plugin realm
+    /// (events, configs), so the driver and the other plugins aren't reachable from the plugin.
+    ///
+    /// With a manifest, the plugin gets only the APIs of its declared permissions, and it fails
+    /// if it requires a newer application.
+    ///
+    /// The code is instrumented for the budget of the callbacks; see instrument.rs.
+    pub fn eval_plugin(
+        &mut self,
+        src: &str,
+        manifest: Option<&PluginManifest>,
+    ) -> anyhow::Result<JsValue> {
+        self.run_plugin(src, manifest)?
+            .map_err(|err| anyhow!("Cannot execute javascript code: {:?}", err))
+    }
+
+    // Same as `eval_plugin`, but gives back the exception thrown by the plugin as is.
+    pub(crate) fn run_plugin(
+        &mut self,
+        src: &str,
+        manifest: Option<&PluginManifest>,
+    ) -> anyhow::Result<JsResult<JsValue>> {
+        let src = instrument(src)?;
+        let denied = match manifest {
+            Some(manifest) => {
+                manifest.check_app_version(&self.app_version)?;
+                manifest.denied_globals()
+            }
+            None => vec![],
+        };
+        if let Some(manifest) = manifest {
+            self.call_driver(
+                "$$setMouseMotion",
+                &[
+                    JsValue::from(js_string!(manifest.id.as_str())),
+                    JsValue::new(manifest.mouse_motion),
+                ],
+            )?;
+        }
+        let owner = manifest.map(|manifest| manifest.id.clone());
+        let realm = self.create_plugin_realm(&denied, owner)?;
+        let driver_realm = self.context.enter_realm(realm);
+        let (result, overrun) =
+            self.with_budget(LOAD_BUDGET, |js| js.context.eval(Source::from_bytes(&src)));
+        self.context.enter_realm(driver_realm);
+        self.run_jobs();
+        if let Some(overrun) = overrun {
+            return Err(anyhow!("Cannot load the plugin: {}", overrun.message()));
+        }
+        Ok(result)
+    }
+
+    // Evaluates a snippet sent by the UI, instrumented and under the budget of a plugin file, so
+    // that an endless loop can't freeze the event tap. It gets a realm of its own, as a plugin file
+    // without a manifest, unless `driver` asks for the internals of driver.js.
+    fn eval_snippet(&mut self, src: &str, driver: bool) -> anyhow::Result<serde_json::Value> {
+        let src = instrument(src)?;
+        let saved_realm = if driver {
+            log::warn!("Evaluating a snippet in the driver realm");
+            None
+        } else {
+            let realm = self.create_plugin_realm(&[], None)?;
+            Some(self.context.enter_realm(realm))
+        };
+        let (result, overrun) =
+            self.with_budget(LOAD_BUDGET, |js| js.context.eval(Source::from_bytes(&src)));
+        // Converted in the realm of the snippet, which owns the value.
+        let json = result.map(|value| value.to_json(&mut self.context));
+        if let Some(saved_realm) = saved_realm {
+            self.context.enter_realm(saved_realm);
+        }
+        self.run_jobs();
+        if let Some(overrun) = overrun {
+            return Err(anyhow!("Cannot run the snippet: {}", overrun.message()));
+        }
+        let json = json
+            .map_err(|err| anyhow!("Cannot execute javascript code: {:?}", err))?
+            .map_err(|err| anyhow!("Cannot convert the value to JSON: {}", err))?;
+        Ok(json.unwrap_or_default())
+    }
+
+    // Runs `f` with `budget` for the plugin code it calls. Returns the plugin which ran out of it,
+    // if any.
+    fn with_budget<T>(
+        &mut self,
+        budget: Duration,
+        f: impl FnOnce(&mut Self) -> T,
+    ) -> (T, Option<Overrun>) {
+        let saved = self.budget().map(|data| data.start(budget));
+        let result = f(self);
+        let overrun = match (self.budget(), saved) {
+            (Some(data), Some(saved)) => data.finish(saved),
+            _ => None,
+        };
+        (result, overrun)
+    }
+
+    fn budget(&self) -> Option<&Budget> {
+        self.context.get_data::<Budget>()
+    }
+
+    fn create_plugin_realm(
+        &mut self,
+        denied: &[(&str, &str)],
+        owner: Option<String>,
+    ) -> anyhow::Result<Realm> {
+        // Read them at every load, since check.rs wraps `$$registerPlugin`.
+        let mut driver_api = Vec::new();
+        for (name, driver_name, call) in DRIVER_API {
+            driver_api.push((*name, self.driver_function(driver_name)?, *call));
+        }
+        let schedule_flush = self.driver_function("$$scheduleStorageFlush")?;
+
+        let realm = self
+            .context
+            .create_realm()
+            .map_err(|err| anyhow!("Cannot create a realm: {:?}", err))?;
+        let driver_realm = self.context.enter_realm(realm.clone());
+        let result = self.init_plugin_realm(&realm, owner, driver_api, schedule_flush, denied);
+        self.context.enter_realm(driver_realm);
+        result?;
+        Ok(realm)
+    }
+
+    fn init_plugin_realm(
+        &mut self,
+        realm: &Realm,
+        owner: Option<String>,
+        driver_api: Vec<(&str, JsFunction, DriverCall)>,
+        schedule_flush: JsFunction,
+        denied: &[(&str, &str)],
+    ) -> anyhow::Result<()> {
+        self.init_api()?;
+        let bridge = self.init_bridge()?;
+        realm
+            .host_defined_mut()
+            .insert(PluginRealm::new(owner, bridge));
+
+        let mut names = vec![
+            JsValue::from(js_string!(Console::NAME)),
+            js_string!("HotKey").into(),
+            js_string!("Key").into(),
+        ];
+        names.extend(PUBLIC_BUILTINS.iter().map(|(name, _)| js_string!(*name).into()));
+        for (name, function, call) in driver_api {
+            let native = NativeFunction::from_copy_closure_with_captures(call, function);
+            if let Err(err) = self
+                .context
+                .register_global_callable(js_string!(name), 0, native)
+            {
+                return Err(anyhow!("Cannot register `{}`: {:?}", name, err));
+            }
+            names.push(js_string!(name).into());
+        }
+
+        let tick = NativeFunction::from_fn_ptr(Budget::tick);
+        if let Err(err) = self
+            .context
+            .register_global_callable(js_string!(TICK), 0, tick)
+        {
+            return Err(anyhow!("Cannot register `{}`: {:?}", TICK, err));
+        }
+        names.push(js_string!(TICK).into());
+
+        let storage = self.build_storage(schedule_flush)?;
+        if let Err(err) =
+            self.context
+                .register_global_property(js_string!("storage"), storage, Attribute::all())
+        {
+            return Err(anyhow!("Cannot register `storage`: {:?}", err));
+        }
+        names.push(js_string!("storage").into());
+
+        let names = JsArray::from_iter(names, &mut self.context);
+        let denied: Vec<JsValue> = denied
+            .iter()
+            .map(|(name, permission)| {
+                let pair = [js_string!(*name).into(), js_string!(*permission).into()];
+                JsArray::from_iter(pair, &mut self.context).into()
+            })
+            .collect();
+        let denied = JsArray::from_iter(denied, &mut self.context);
+        self.context
+            .eval(Source::from_bytes(LOCK_GLOBALS))
+            .and_then(|lock| JsFunction::try_from_js(&lock, &mut self.context))
+            .and_then(|lock| {
+                lock.call(
+                    &JsValue::undefined(),
+                    &[names.into(), denied.into()],
+                    &mut self.context,
+                )
+            })
+            .map_err(|err| anyhow!("Cannot lock the plugin globals: {:?}", err))?;
+        Ok(())
+    }
+
+    // The `storage` object of the current realm, which reads and writes the namespace of the
+    // owner of the realm.
+    fn build_storage(&mut self, schedule_flush: JsFunction) -> anyhow::Result<JsValue> {
+        let natives: Vec<JsValue> = [
+            NativeFunction::from_fn_ptr(PluginRealm::storage_get),
+            NativeFunction::from_copy_closure_with_captures(
+                PluginRealm::storage_set,
+                schedule_flush.clone(),
+            ),
+            NativeFunction::from_copy_closure_with_captures(
+                PluginRealm::storage_delete,
+                schedule_flush,
+            ),
+            NativeFunction::from_fn_ptr(PluginRealm::storage_keys),
+        ]
+        .into_iter()
+        .map(|native| native.to_js_function(self.context.realm()).into())
+        .collect();
+        self.context
+            .eval(Source::from_bytes(STORAGE_API))
+            .and_then(|build| JsFunction::try_from_js(&build, &mut self.context))
+            .and_then(|build| build.call(&JsValue::undefined(), &natives, &mut self.context))
+            .map_err(|err| anyhow!("Cannot build `storage`: {:?}", err))
+    }
+
+    fn driver_function(&mut self, name: &str) -> anyhow::Result<JsFunction> {
+        let function = self
+            .context
+            .global_object()
+            .get(js_string!(name), &mut self.context)
+            .map_err(|err| anyhow!("Cannot get {}: {:?}", name, err))?;
+        JsFunction::try_from_js(&function, &mut self.context)
+            .map_err(|err| anyhow!("Cannot get {} as JsFunction: {:?}", name, err))
+    }
+
     pub fn eval(&mut self, src: String) -> anyhow::Result<JsValue> {
-        return match self.context.eval(Source::from_bytes(&src)) {
+        let result = match self.context.eval(Source::from_bytes(&src)) {
             Ok(value) => Ok(value),
             Err(err) => Err(anyhow!("Cannot execute javascript code: {:?}", err)),
         };
+        self.run_jobs();
+        result
+    }
+
+    // Runs the pending Promise jobs, i.e. the continuations of `await` and `then` callbacks.
+    // Call this whenever JS code may have settled a Promise.
+    //
+    // Each job runs as the plugin which owns its realm: the jobs of a disabled plugin are dropped,
+    // and an aborted job is charged to its plugin.
+    //
+    // The jobs share one callback budget, since a job can queue the next one forever, e.g.
+    // `while (true) await null;`. Once it runs out, the rest waits for the next call.
+    fn run_jobs(&mut self) {
+        let (_, overrun) = self.with_budget(self.callback_budget, |js| {
+            while let Some(job) = js.jobs.pop() {
+                let owner = job_realm(&job).and_then(PluginRealm::owner_of);
+                if let Some(owner) = &owner {
+                    match js.is_enabled(owner) {
+                        Ok(true) => {}
+                        Ok(false) => continue,
+                        Err(err) => log::error!("{:?}", err),
+                    }
+                }
+                match run_job(job, &mut js.context) {
+                    Ok(_) => {}
+                    Err(err) if err.as_native().is_some_and(|err| err.is_runtime_limit()) => {
+                        if let Err(err) = js.on_callback_aborted(owner, &err.to_string()) {
+                            log::error!("{:?}", err);
+                        }
+                    }
+                    Err(err) => log::error!("Exception in a Promise job: {:?}", err),
+                }
+                if js.budget().is_some_and(Budget::is_exceeded) {
+                    break;
+                }
+            }
+        });
+        if let Some(overrun) = overrun {
+            if let Err(err) = self.on_callback_aborted(overrun.plugin_id.clone(), &overrun.message())
+            {
+                log::error!("{:?}", err);
+            }
+        }
+    }
+
+    fn is_enabled(&mut self, plugin_id: &str) -> anyhow::Result<bool> {
+        let enabled = self.call_driver("$$isEnabled", &[JsValue::from(js_string!(plugin_id))])?;
+        Ok(enabled.to_boolean())
+    }
+
+    /// Whether an enabled plugin asked for the pointer movements in its manifest. The backends
+    /// don't send them to `send_event` otherwise.
+    pub fn wants_mouse_motion(&mut self) -> anyhow::Result<bool> {
+        Ok(self.call_driver("$$wantsMouseMotion", &[])?.to_boolean())
+    }
+
+    /// Parses `src` without running it.
+    pub fn check_syntax(&mut self, src: &str) -> anyhow::Result<()> {
+        Script::parse(Source::from_bytes(src), None, &mut self.context)
+            .map_err(|err| anyhow!("Syntax error: {}", err))?;
+        Ok(())
     }
 
     // Call this method when key/mouse event was received.
     // This method calls JS handlers.
-    pub fn send_event(
-        &mut self,
-        cg_event_type: CGEventType,
-        cg_event_ref: CGEventRef,
-    ) -> anyhow::Result<bool> {
+    //
+    // Returns None if the plugins swallowed the event. Otherwise returns the event to deliver to
+    // the application, which differs from `event` if a plugin rewrote it.
+    pub fn send_event(&mut self, event: Event) -> anyhow::Result<Option<Event>> {
+        // Usually the run loop has applied them already. This keeps the order when an operation
+        // and a key event arrive at once.
+        self.process_js_operations();
+        if self.paused {
+            return Ok(Some(event));
+        }
+
+        let started_at = Instant::now();
+        let result = self.dispatch_event(event);
+        self.events += 1;
+        self.event_micros += started_at.elapsed().as_micros() as u64;
+        result
+    }
+
+    fn dispatch_event(&mut self, event: Event) -> anyhow::Result<Option<Event>> {
         let invoke_event = self
             .context
             .global_object()
@@ -213,28 +716,9 @@ impl JS {
         let invoke_event = JsFunction::try_from_js(&invoke_event, &mut self.context)
             .map_err(|err| anyhow!("Cannot get $$invokeEvent as JsFunction: {:?}", err))?;
 
-        let mut needs_config_reload = false;
-        let operations = self.get_js_operations();
-        for op in operations {
-            match op {
-                JsOperation::ReloadConfig => {
-                    needs_config_reload = true;
-                }
-                JsOperation::ReloadPlugins => {
-                    if let Err(err) = self.reload_plugins() {
-                        log::error!("cannot reload plugin: {:?}", err)
-                    }
-                }
-                JsOperation::UnloadPlugin { plugin_id, .. } => {
-                    if let Err(err) = self.unload_plugin(plugin_id.clone()) {
-                        log::error!("cannot unload plugin({}): {:?}", plugin_id, err)
-                    }
-                }
-            }
-        }
-
-        let event = Event::from_cf(cg_event_type, cg_event_ref);
-        if let Some(queue) = &self.monitoring_queue {
+        // The pointer movements would push the key events out of the queue in a moment.
+        let motion = cg_event_type(&event.event_type).is_some_and(is_motion_event);
+        if let (Some(queue), false) = (&self.monitoring_queue, motion) {
             match queue.write() {
                 Ok(mut queue) => {
                     queue.push_back(event.clone());
@@ -248,44 +732,473 @@ impl JS {
             }
         }
 
-        let js_key_event = self.build_key_event(&event, cg_event_type)?;
-        let result = invoke_event
-            .call(
+        let js_key_event = self.build_key_event(&event)?;
+        let (result, overrun) = self.with_budget(self.callback_budget, |js| {
+            invoke_event.call(
                 &JsValue::undefined(),
-                &[
-                    JsValue::from(js_key_event),
-                    JsValue::new(needs_config_reload),
-                ],
-                &mut self.context,
+                &[JsValue::from(js_key_event)],
+                &mut js.context,
             )
-            .map_err(|err| anyhow!("Cannot call $$invokeEvent as JsFunction: {:?}", err))?;
-        let result = result.as_boolean().unwrap_or(true);
+        });
+        let result = match (result, overrun) {
+            // driver.js caught the error of the budget, and the callbacks after it failed as well.
+            (Ok(_), Some(overrun)) => {
+                self.on_callback_aborted(overrun.plugin_id.clone(), &overrun.message())?;
+                self.run_jobs();
+                Some(event.clone())
+            }
+            (Ok(result), None) => {
+                self.run_jobs();
+                match self.read_key_event(&event, &result) {
+                    Ok(result) => result,
+                    Err(err) => {
+                        log::error!(
+                            "Plugin returned an invalid event. Passing the original: {:?}",
+                            err
+                        );
+                        Some(event.clone())
+                    }
+                }
+            }
+            (Err(err), _) if err.as_native().is_some_and(|err| err.is_runtime_limit()) => {
+                let plugin_id = self.running_plugin_id()?;
+                self.on_callback_aborted(plugin_id, &err.to_string())?;
+                Some(event.clone())
+            }
+            (Err(err), _) => {
+                return Err(anyhow!("Cannot call $$invokeEvent as JsFunction: {:?}", err));
+            }
+        };
+
+        if let Some(trace) = &mut self.trace {
+            let injected = match self.context.get_data::<InjectedEvents>() {
+                Some(injected) => injected.events.take(),
+                None => vec![],
+            };
+            let modified = result.as_ref().filter(|e| !e.same_input(&event)).cloned();
+            if let Err(err) = trace.write(event, result.is_some(), modified, injected) {
+                log::error!("Cannot write trace({:?}): {:?}", trace.path(), err);
+            }
+        }
+
         Ok(result)
     }
 
-    fn get_js_operations(&mut self) -> Vec<JsOperation> {
-        let mut result = Vec::new();
-        if let Some(rx) = &self.js_operation_rx {
-            'out: loop {
-                match rx.try_recv() {
-                    Ok(op) => {
-                        log::info!("Received operation: {:?}", op);
-                        result.push(op);
-                    }
-                    Err(err) => match err {
-                        TryRecvError::Empty => {
-                            log::debug!("needs_plugin_reload: empty");
-                            break 'out;
-                        }
-                        TryRecvError::Disconnected => {
-                            log::warn!("needs_plugin_reload: disconnected");
-                            break 'out;
-                        }
-                    },
+    // Converts the return value of $$invokeEvent, which is false or the event object to deliver.
+    fn read_key_event(
+        &mut self,
+        original: &Event,
+        value: &JsValue,
+    ) -> anyhow::Result<Option<Event>> {
+        let Some(object) = value.as_object() else {
+            // undefined and true pass the original event.
+            return Ok(if value.is_boolean() && !value.to_boolean() {
+                None
+            } else {
+                Some(original.clone())
+            });
+        };
+
+        let mut get = |key: &str| {
+            object
+                .get(js_string!(key), &mut self.context)
+                .map_err(|err| anyhow!("Cannot get {} of the event: {:?}", key, err))
+        };
+        let event_type = get("type")?;
+        let keycode = get("keycode")?;
+        let flags = get("flags")?;
+
+        let event_type = event_type
+            .as_string()
+            .map(|s| s.to_std_string_escaped())
+            .ok_or_else(|| anyhow!("type of the event must be a string"))?;
+        let cg_event_type = cg_event_type(&event_type)
+            .ok_or_else(|| anyhow!("Unknown event type: {}", event_type))?;
+        if is_mouse_event(cg_event_type) != original.mouse.is_some() {
+            return Err(anyhow!(
+                "Cannot rewrite {} to {}",
+                original.event_type,
+                event_type
+            ));
+        }
+        let keycode = keycode
+            .as_number()
+            .ok_or_else(|| anyhow!("keycode of the event must be a number"))?;
+        let flags = flags
+            .as_number()
+            .ok_or_else(|| anyhow!("flags of the event must be a number"))?;
+
+        let mut event = original.rewrite(cg_event_type, keycode as i64, flags as u64);
+        if let Some(mouse) = &mut event.mouse {
+            let mut number = |key: &str| -> anyhow::Result<Option<f64>> {
+                Ok(get(key)?.as_number())
+            };
+            if let Some(x) = number("x")? {
+                mouse.x = x;
+            }
+            if let Some(y) = number("y")? {
+                mouse.y = y;
+            }
+            if let Some(button) = number("button")? {
+                mouse.button = button as i64;
+            }
+            if let Some(delta_x) = number("deltaX")? {
+                mouse.delta_x = delta_x as i64;
+            }
+            if let Some(delta_y) = number("deltaY")? {
+                mouse.delta_y = delta_y as i64;
+            }
+        }
+        Ok(Some(event))
+    }
+
+    /// Starts recording the processed events into `path`. See `trace` module for the format.
+    pub fn start_trace(&mut self, path: PathBuf) -> anyhow::Result<()> {
+        let trace = TraceWriter::create(&path, &self.app_config()?)?;
+        log::info!("Recording trace to {:?}", path);
+        self.context.insert_data(InjectedEvents::default());
+        self.trace = Some(trace);
+        Ok(())
+    }
+
+    fn clock(&self) -> &std::cell::Cell<Clock> {
+        &self
+            .context
+            .get_data::<ClockData>()
+            .expect("ClockData is inserted in JS::new")
+            .clock
+    }
+
+    pub fn now_ms(&self) -> u64 {
+        self.clock().get().now_ms()
+    }
+
+    /// Switches to the virtual clock, which only moves by `advance_clock_to`.
+    pub fn use_virtual_clock(&mut self) {
+        self.clock().set(Clock::Virtual(0));
+    }
+
+    /// Replaces the plugin storage. None keeps it in memory, so that tests and replays don't touch
+    /// the user's data.
+    pub fn use_storage(&mut self, path: Option<PathBuf>) {
+        self.context.insert_data(StorageData {
+            storage: RefCell::new(PluginStorage::new(path)),
+        });
+    }
+
+    /// Publishes the loaded plugins to `registry`, now and whenever they change.
+    pub fn share_registry(&mut self, registry: SharedPluginRegistry) -> anyhow::Result<()> {
+        self.registry = Some(registry);
+        self.publish_registry()
+    }
+
+    fn publish_registry(&mut self) -> anyhow::Result<()> {
+        let Some(registry) = self.registry.clone() else {
+            return Ok(());
+        };
+        let plugins = self.get_config_schema()?.plugins;
+        let mut registry = registry
+            .write()
+            .map_err(|err| anyhow!("Cannot get lock for the plugin registry: {:?}", err))?;
+        registry.plugins = plugins;
+        registry.filenames = self.plugin_id2filename.clone();
+        registry.manifest_errors = self.manifest_errors.clone();
+        Ok(())
+    }
+
+    // The configuration the plugins run with.
+    fn app_config(&self) -> anyhow::Result<AppConfig> {
+        match self.context.get_data::<ConfigData>().and_then(|data| data.config.clone()) {
+            Some(config) => Ok(config),
+            None => AppConfig::load(),
+        }
+    }
+
+    /// Overrides `DEFAULT_CALLBACK_BUDGET`.
+    pub fn set_callback_budget(&mut self, budget: Duration) {
+        self.callback_budget = budget;
+    }
+
+    /// Sets the version compared with `minAppVersion`, i.e. the version of the application rather
+    /// than the one of this crate. Call it before `load_user_scripts`.
+    pub fn set_app_version(&mut self, version: &str) -> anyhow::Result<()> {
+        parse_version(version)?;
+        self.app_version = version.to_string();
+        Ok(())
+    }
+
+    pub fn advance_clock_to(&mut self, ms: u64) {
+        let clock = self.clock();
+        let mut now = clock.get();
+        now.advance_to(ms);
+        clock.set(now);
+    }
+
+    /// Runs the setTimeout/setInterval callbacks which are due. Returns the milliseconds until the
+    /// next timer, or None if there's no timer.
+    ///
+    /// Backends call this on the thread which runs `send_event`, after every event and whenever the
+    /// returned delay has passed.
+    pub fn run_timers(&mut self) -> anyhow::Result<Option<u64>> {
+        if self.paused {
+            return Ok(None);
+        }
+        let now = JsValue::from(self.now_ms() as f64);
+        let due = self.call_driver("$$dueTimers", std::slice::from_ref(&now))?;
+        let due = due
+            .as_string()
+            .ok_or_else(|| anyhow!("$$dueTimers returned a non-string value"))?
+            .to_std_string_escaped();
+        let due: Vec<u64> = serde_json::from_str(&due)?;
+        let run_timer = self.driver_function("$$runTimer")?;
+        for id in due {
+            let (result, overrun) = self.with_budget(self.callback_budget, |js| {
+                run_timer.call(
+                    &JsValue::undefined(),
+                    &[JsValue::from(id as f64), now.clone()],
+                    &mut js.context,
+                )
+            });
+            if let Some(overrun) = overrun {
+                self.on_callback_aborted(overrun.plugin_id.clone(), &overrun.message())?;
+            }
+            match result {
+                Ok(_) => {}
+                // Charge the plugin, and go on with the timers of the others.
+                Err(err) if err.as_native().is_some_and(|err| err.is_runtime_limit()) => {
+                    let plugin_id = self.running_plugin_id()?;
+                    self.on_callback_aborted(plugin_id, &err.to_string())?;
                 }
+                Err(err) => return Err(anyhow!("Cannot call $$runTimer: {:?}", err)),
             }
         }
-        result
+        // The timers may have resolved a Promise, e.g. `await sleep(50)`, and its continuation may
+        // add timers. Run it before asking for the next delay.
+        self.run_jobs();
+
+        // Events sent by timers don't belong to any captured event. Record them on their own.
+        if let Some(trace) = &mut self.trace {
+            let injected = match self.context.get_data::<InjectedEvents>() {
+                Some(injected) => injected.events.take(),
+                None => vec![],
+            };
+            if !injected.is_empty() {
+                if let Err(err) = trace.write_timer(injected) {
+                    log::error!("Cannot write trace({:?}): {:?}", trace.path(), err);
+                }
+            }
+        }
+
+        let next_timer_delay = self
+            .context
+            .global_object()
+            .get(js_string!("$$nextTimerDelay"), &mut self.context)
+            .map_err(|err| anyhow!("Cannot get $$nextTimerDelay: {:?}", err))?;
+        let next_timer_delay = JsFunction::try_from_js(&next_timer_delay, &mut self.context)
+            .map_err(|err| anyhow!("Cannot get $$nextTimerDelay as JsFunction: {:?}", err))?;
+        let delay = next_timer_delay
+            .call(&JsValue::undefined(), &[], &mut self.context)
+            .map_err(|err| anyhow!("Cannot call $$nextTimerDelay: {:?}", err))?;
+
+        // Promise jobs left over by a job which ran out of the budget.
+        if !self.jobs.is_empty() {
+            return Ok(Some(0));
+        }
+        Ok(delay
+            .as_number()
+            .filter(|delay| *delay >= 0.0)
+            .map(|delay| delay as u64))
+    }
+
+    /// Moves the virtual clock forward to `ms`, firing the timers on the way at their due time.
+    pub fn run_timers_until(&mut self, ms: u64) -> anyhow::Result<()> {
+        while let Some(delay) = self.run_timers()? {
+            let due = self.now_ms() + delay;
+            if due > ms {
+                break;
+            }
+            self.advance_clock_to(due);
+        }
+        self.advance_clock_to(ms);
+        self.run_timers()?;
+        Ok(())
+    }
+
+    pub fn stop_trace(&mut self) {
+        if let Some(trace) = self.trace.take() {
+            log::info!("Stopped recording trace to {:?}", trace.path());
+        }
+        self.context.remove_data::<InjectedEvents>();
+    }
+
+    /// Lets the run loop apply the operations as soon as they arrive. `wake` is called on the
+    /// sender thread; it must make the run loop call `process_js_operations`.
+    pub fn set_js_operation_waker(&self, wake: impl Fn() + Send + 'static) {
+        if let Some(rx) = &self.js_operation_rx {
+            rx.set_waker(wake);
+        }
+    }
+
+    /// Applies the received operations, and replies the results.
+    pub fn process_js_operations(&mut self) {
+        loop {
+            let Some(message) = self.js_operation_rx.as_ref().and_then(|rx| rx.try_recv()) else {
+                break;
+            };
+            let request = &message.request;
+            log::info!("Received operation({}): {:?}", request.id, request.operation);
+            let result = if request.version == PROTOCOL_VERSION {
+                self.apply_js_operation(&request.operation)
+            } else {
+                Err(anyhow!(
+                    "Unsupported protocol version: {} (expected {})",
+                    request.version,
+                    PROTOCOL_VERSION
+                ))
+            };
+            if let Err(err) = &result {
+                log::error!("Cannot apply {:?}: {:?}", request.operation, err);
+            }
+            // Plugins may have been (un)loaded, also by `Eval`. Publish before replying, so that
+            // the UI reads the new state once it gets the response.
+            if let Err(err) = self.publish_registry() {
+                log::error!("Cannot publish the plugin registry: {:?}", err);
+            }
+            message.reply(result);
+        }
+    }
+
+    fn apply_js_operation(&mut self, operation: &JsOperation) -> anyhow::Result<JsOperationOutput> {
+        match operation {
+            JsOperation::ReloadConfig => {
+                self.call_driver("$$reloadConfig", &[])?;
+            }
+            JsOperation::ReloadPlugins => self.reload_plugins()?,
+            JsOperation::UnloadPlugin { plugin_id } => self.unload_plugin(plugin_id.clone())?,
+            JsOperation::StartTrace { path } => {
+                let path = match path {
+                    Some(path) => PathBuf::from(path),
+                    None => default_trace_path()?,
+                };
+                self.start_trace(path)?;
+            }
+            JsOperation::StopTrace => self.stop_trace(),
+            JsOperation::SetPluginEnabled { plugin_id, enabled } => {
+                self.set_plugin_enabled(plugin_id, *enabled)?
+            }
+            JsOperation::SetPluginOrder { plugin_ids } => self.set_plugin_order(plugin_ids)?,
+            JsOperation::Eval { src, driver } => {
+                return Ok(JsOperationOutput::Value(self.eval_snippet(src, *driver)?));
+            }
+            JsOperation::Pause => self.paused = true,
+            JsOperation::Resume => self.paused = false,
+            JsOperation::GetStats => return Ok(JsOperationOutput::Stats(self.stats()?)),
+            JsOperation::InjectEvent { event } => {
+                let delivered = self.dispatch_event(event.clone())?;
+                return Ok(JsOperationOutput::Delivered(delivered));
+            }
+        }
+        Ok(JsOperationOutput::Done)
+    }
+
+    fn set_plugin_enabled(&mut self, plugin_id: &str, enabled: bool) -> anyhow::Result<()> {
+        if !self.loaded_plugins()?.contains(plugin_id) {
+            return Err(anyhow!("Plugin is not loaded: {}", plugin_id));
+        }
+        self.call_driver(
+            "$$setPluginEnabled",
+            &[JsValue::from(js_string!(plugin_id)), JsValue::new(enabled)],
+        )?;
+        if self.save_config {
+            let mut config = AppConfig::load()?;
+            config.set_value(&format!("plugins.{}.enabled", plugin_id), &enabled.to_string())?;
+            config.save()?;
+        }
+        Ok(())
+    }
+
+    fn set_plugin_order(&mut self, plugin_ids: &[String]) -> anyhow::Result<()> {
+        let loaded_plugins = self.loaded_plugins()?;
+        if let Some(plugin_id) = plugin_ids.iter().find(|id| !loaded_plugins.contains(*id)) {
+            return Err(anyhow!("Plugin is not loaded: {}", plugin_id));
+        }
+        for (plugin_id, priority) in order_priorities(plugin_ids) {
+            self.call_driver(
+                "$$setPluginPriority",
+                &[JsValue::from(js_string!(plugin_id)), JsValue::new(priority as f64)],
+            )?;
+        }
+        if self.save_config {
+            let mut config = AppConfig::load()?;
+            config.set_plugin_order(plugin_ids);
+            config.save()?;
+        }
+        Ok(())
+    }
+
+    /// Statistics of the live plugins.
+    pub fn stats(&mut self) -> anyhow::Result<RuntimeStats> {
+        let json = self.call_driver("$$pluginStats", &[])?;
+        let json = json
+            .as_string()
+            .ok_or_else(|| anyhow!("$$pluginStats returned a non-string value"))?
+            .to_std_string_escaped();
+        let mut plugins: Vec<PluginStats> = serde_json::from_str(&json)?;
+        for plugin in &mut plugins {
+            plugin.filename = self.plugin_id2filename.get(&plugin.id).cloned();
+            plugin.aborted = self.watchdog.offences(&plugin.id);
+        }
+        Ok(RuntimeStats {
+            paused: self.paused,
+            events: self.events,
+            event_micros: self.event_micros,
+            plugins,
+        })
+    }
+
+    // The plugin whose callback driver.js is running, if any.
+    fn running_plugin_id(&mut self) -> anyhow::Result<Option<String>> {
+        let plugin_id = self.call_driver("$$runningPluginId", &[])?;
+        Ok(plugin_id.as_string().map(|id| id.to_std_string_escaped()))
+    }
+
+    // boa aborted a callback of `plugin_id` (None for the driver) which exceeded the runtime
+    // limits. The plugin is disabled when it happens repeatedly.
+    fn on_callback_aborted(
+        &mut self,
+        plugin_id: Option<String>,
+        message: &str,
+    ) -> anyhow::Result<()> {
+        let Some(plugin_id) = plugin_id else {
+            log::error!("Aborted the driver: {}", message);
+            return Ok(());
+        };
+        log::error!("Aborted the callback of {}: {}", plugin_id, message);
+        if !self.watchdog.record(&plugin_id) {
+            return Ok(());
+        }
+
+        let reason = format!(
+            "Disabled after its callback was aborted {} times: {}",
+            MAX_OFFENCES, message
+        );
+        log::error!("{}: {}", plugin_id, reason);
+        self.call_driver(
+            "$$setPluginEnabled",
+            &[JsValue::from(js_string!(plugin_id.as_str())), JsValue::new(false)],
+        )?;
+        if self.save_config {
+            let mut config = AppConfig::load()?;
+            config.disable_plugin(&plugin_id, &reason);
+            config.save()?;
+        }
+        Ok(())
+    }
+
+    fn call_driver(&mut self, name: &str, args: &[JsValue]) -> anyhow::Result<JsValue> {
+        self.driver_function(name)?
+            .call(&JsValue::undefined(), args, &mut self.context)
+            .map_err(|err| anyhow!("Cannot call {} as JsFunction: {:?}", name, err))
     }
 
     fn unload_plugin(&mut self, plugin_id: String) -> anyhow::Result<()> {
@@ -308,11 +1221,7 @@ impl JS {
         Ok(())
     }
 
-    fn build_key_event(
-        &mut self,
-        event: &Event,
-        cg_event_type: CGEventType,
-    ) -> anyhow::Result<JsObject> {
+    fn build_key_event(&mut self, event: &Event) -> anyhow::Result<JsObject> {
         let key_event = JsObject::with_object_proto(self.context.intrinsics());
 
         fn set<K, V>(js: &mut JS, key_event: &JsObject, key: K, value: V) -> anyhow::Result<()>
@@ -340,15 +1249,39 @@ impl JS {
             JsValue::from(event.keycode),
         )?;
 
-        if cg_event_type == kCGEventFlagsChanged {
-            set(
-                self,
-                &key_event,
-                js_string!("flags"),
-                JsValue::from(event.flags),
-            )?;
+        set(
+            self,
+            &key_event,
+            js_string!("flags"),
+            JsValue::from(event.flags),
+        )?;
+
+        for (name, value) in event.modifiers.entries() {
+            set(self, &key_event, js_string!(name), JsValue::from(value))?;
         }
 
+        if let Some(mouse) = &event.mouse {
+            set(self, &key_event, js_string!("x"), JsValue::from(mouse.x))?;
+            set(self, &key_event, js_string!("y"), JsValue::from(mouse.y))?;
+            set(self, &key_event, js_string!("button"), JsValue::from(mouse.button))?;
+            set(self, &key_event, js_string!("deltaX"), JsValue::from(mouse.delta_x))?;
+            set(self, &key_event, js_string!("deltaY"), JsValue::from(mouse.delta_y))?;
+        }
+
+        set(
+            self,
+            &key_event,
+            js_string!("autorepeat"),
+            JsValue::from(event.autorepeat),
+        )?;
+
+        set(
+            self,
+            &key_event,
+            js_string!("timestamp"),
+            JsValue::from(event.timestamp as f64),
+        )?;
+
         Ok(key_event)
     }
 
@@ -359,7 +1292,7 @@ impl JS {
 
     fn load_bundled(&mut self) -> anyhow::Result<JsValue> {
         let src = include_str!("../js/dynamic-macro.js");
-        self.eval(src.to_string())
+        self.eval_plugin(src, None)
     }
 
     fn reload_plugins(&mut self) -> anyhow::Result<()> {
@@ -375,25 +1308,78 @@ impl JS {
 
             let mut last_loaded_plugins = self.loaded_plugins()?;
 
+            // The files are read again, and they may have been fixed since.
+            for plugin_id in std::mem::take(&mut self.manifest_errors).keys() {
+                self.plugin_id2filename.remove(plugin_id);
+            }
+
             for plugin_snippet in plugin_snippets {
                 let filename = plugin_snippet.filename.as_str();
+                let manifest = plugin_snippet.manifest.as_ref();
+                if let Some(err) = &plugin_snippet.manifest_error {
+                    log::error!("Cannot load {}: {}", filename, err);
+                    let plugin_id = PluginManifest::declared_id(&plugin_snippet.src)
+                        .filter(|plugin_id| !self.plugin_id2filename.contains_key(plugin_id))
+                        .unwrap_or_else(|| filename.to_string());
+                    self.plugin_id2filename
+                        .insert(plugin_id.clone(), filename.to_string());
+                    self.manifest_errors.insert(plugin_id, err.clone());
+                    continue;
+                }
 
-                if let Err(err) = self.eval(plugin_snippet.src) {
+                if let Err(err) = self.eval_plugin(&plugin_snippet.src, manifest) {
                     log::error!("Cannot load {}: {:?}", plugin_snippet.filename, err)
                 }
 
                 let current_loaded_plugins = self.loaded_plugins()?;
 
-                let diff = current_loaded_plugins.difference(&last_loaded_plugins);
-                for plugin_id in diff {
+                if let Some(manifest) = manifest {
+                    if !current_loaded_plugins.contains(&manifest.id) {
+                        log::error!(
+                            "{} declares {} in its manifest but doesn't register it",
+                            filename,
+                            manifest.id
+                        );
+                    }
+                    self.rekey_manifest_error(&manifest.id);
+                    if let Some(other) = self.plugin_id2filename.get(&manifest.id) {
+                        log::warn!(
+                            "{} is declared by both {} and {}",
+                            manifest.id,
+                            other,
+                            filename
+                        );
+                    }
                     self.plugin_id2filename
-                        .insert(plugin_id.to_string(), filename.to_string());
+                        .insert(manifest.id.clone(), filename.to_string());
+                } else {
+                    // Without a manifest, guess the ids from the newly registered plugins.
+                    log::warn!("{} doesn't have a manifest", filename);
+                    let diff = current_loaded_plugins.difference(&last_loaded_plugins);
+                    for plugin_id in diff {
+                        self.rekey_manifest_error(plugin_id);
+                        self.plugin_id2filename
+                            .insert(plugin_id.to_string(), filename.to_string());
+                    }
                 }
 
                 last_loaded_plugins = current_loaded_plugins;
             }
         }
-        Ok(())
+        self.publish_registry()
+    }
+
+    // A file with a broken manifest, keyed by its declared `@id`, gives way to the file which
+    // registers that id, and is keyed by its filename instead.
+    fn rekey_manifest_error(&mut self, plugin_id: &str) {
+        let Some(err) = self.manifest_errors.remove(plugin_id) else {
+            return;
+        };
+        if let Some(filename) = self.plugin_id2filename.remove(plugin_id) {
+            self.plugin_id2filename
+                .insert(filename.clone(), filename.clone());
+            self.manifest_errors.insert(filename, err);
+        }
     }
 
     pub fn loaded_plugins(&mut self) -> anyhow::Result<HashSet<String>> {
@@ -432,7 +1418,7 @@ pub struct ConfigSchemaList {
     pub plugins: Vec<ConfigSchema>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ConfigSchema {
     pub id: String,
     name: String,
@@ -443,10 +1429,11 @@ pub struct ConfigSchema {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::js_operation::{js_operation_channel, JsOperationSender, JsRequest};
 
     #[test]
     fn test_get_config_schema() -> anyhow::Result<()> {
-        let mut js = JS::new(None, None, None)?;
+        let mut js = JS::new_with_config(None, None, None, None, AppConfig::default())?;
         let schema = js.get_config_schema()?;
         assert_eq!(schema.plugins.first().unwrap().id, "builtin.dynamicmacro");
         assert_eq!(schema.plugins.first().unwrap().name, "Dynamic Macro");
@@ -454,12 +1441,375 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_js_operations() -> anyhow::Result<()> {
+        let (tx, rx) = js_operation_channel();
+        let mut js = JS::new_with_config(Some(rx), None, None, None, AppConfig::default())?;
+        let (wake_tx, wake_rx) = std::sync::mpsc::channel();
+        js.set_js_operation_waker(move || wake_tx.send(()).unwrap());
+
+        // A file where the trace directory should be.
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("file"), "")?;
+        let trace_path = dir.path().join("file").join("trace.jsonl");
+
+        let sender = std::thread::spawn(move || {
+            let timeout = std::time::Duration::from_secs(10);
+            let unload = tx.call(
+                JsOperation::UnloadPlugin {
+                    plugin_id: "builtin.dynamicmacro".to_string(),
+                },
+                timeout,
+            );
+            let trace = tx.call(
+                JsOperation::StartTrace {
+                    path: Some(trace_path.to_string_lossy().to_string()),
+                },
+                timeout,
+            );
+            (unload, trace)
+        });
+        // Applied by the run loop without any key event.
+        for _ in 0..2 {
+            wake_rx.recv()?;
+            js.process_js_operations();
+        }
+
+        let (unload, trace) = sender.join().unwrap();
+        unload?;
+        assert!(trace.is_err());
+        assert!(js.loaded_plugins()?.is_empty());
+        Ok(())
+    }
+
+    // Sends the operation from another thread, as the UI does, and applies it.
+    fn request(
+        js: &mut JS,
+        tx: &JsOperationSender,
+        operation: JsOperation,
+    ) -> anyhow::Result<JsOperationOutput> {
+        let (wake_tx, wake_rx) = std::sync::mpsc::channel();
+        js.set_js_operation_waker(move || {
+            let _ = wake_tx.send(());
+        });
+        let tx = tx.clone();
+        let sender = std::thread::spawn(move || {
+            tx.call(operation, std::time::Duration::from_secs(10))
+        });
+        wake_rx.recv()?;
+        js.process_js_operations();
+        sender.join().unwrap()
+    }
+
+    #[test]
+    fn test_js_requests() -> anyhow::Result<()> {
+        let (tx, rx) = js_operation_channel();
+        let mut js = JS::new_with_config(Some(rx), None, None, None, AppConfig::default())?;
+        let id = "builtin.dynamicmacro".to_string();
+
+        let eval = |src: &str, driver: bool| JsOperation::Eval {
+            src: src.into(),
+            driver,
+        };
+        let output = request(&mut js, &tx, eval("[1 + 1, 'a']", false))?;
+        assert!(matches!(output, JsOperationOutput::Value(v) if v == serde_json::json!([2, "a"])));
+        assert!(request(&mut js, &tx, eval("throw 1", false)).is_err());
+        // Snippets can't reach driver.js, nor freeze the thread.
+        let output = request(&mut js, &tx, eval("typeof $$CALLBACKS", false))?;
+        assert!(matches!(output, JsOperationOutput::Value(v) if v == "undefined"));
+        assert!(request(&mut js, &tx, eval("while (true) {}", false)).is_err());
+        assert!(request(&mut js, &tx, eval("while (true) {}", true)).is_err());
+        // Unless the driver realm is asked for.
+        let output = request(&mut js, &tx, eval("$$IDS.length", true))?;
+        assert!(matches!(output, JsOperationOutput::Value(v) if v == 1));
+
+        let event = Event::new(kCGEventKeyDown, 0, 0);
+        let output = request(&mut js, &tx, JsOperation::InjectEvent { event: event.clone() })?;
+        assert!(matches!(output, JsOperationOutput::Delivered(Some(_))));
+        js.send_event(event.clone())?;
+
+        let JsOperationOutput::Stats(stats) = request(&mut js, &tx, JsOperation::GetStats)? else {
+            panic!("Expected Stats");
+        };
+        assert_eq!(stats.events, 1);
+        assert_eq!(stats.plugins[0].id, id);
+        assert_eq!(stats.plugins[0].calls, 2);
+        assert!(stats.plugins[0].enabled);
+
+        let disable = JsOperation::SetPluginEnabled {
+            plugin_id: id.clone(),
+            enabled: false,
+        };
+        request(&mut js, &tx, disable)?;
+        assert!(!js.stats()?.plugins[0].enabled);
+        let unknown = JsOperation::SetPluginEnabled {
+            plugin_id: "unknown".to_string(),
+            enabled: false,
+        };
+        assert!(request(&mut js, &tx, unknown).is_err());
+        let unknown = JsOperation::SetPluginOrder {
+            plugin_ids: vec![id.clone(), "unknown".to_string()],
+        };
+        assert!(request(&mut js, &tx, unknown).is_err());
+
+        // Paused: events pass through without reaching the plugins.
+        request(&mut js, &tx, JsOperation::Pause)?;
+        js.send_event(event.clone())?;
+        assert!(js.stats()?.paused);
+        assert_eq!(js.stats()?.events, 1);
+        request(&mut js, &tx, JsOperation::Resume)?;
+        js.send_event(event)?;
+        assert_eq!(js.stats()?.events, 2);
+
+        // Requests of another protocol version are rejected.
+        let (reply_tx, reply_rx) = std::sync::mpsc::channel();
+        let old = JsRequest {
+            version: PROTOCOL_VERSION + 1,
+            id: 42,
+            operation: JsOperation::GetStats,
+        };
+        std::thread::spawn(move || {
+            reply_tx
+                .send(tx.request(old, std::time::Duration::from_secs(10)))
+                .unwrap()
+        });
+        let response = loop {
+            js.process_js_operations();
+            if let Ok(response) = reply_rx.try_recv() {
+                break response?;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        };
+        assert_eq!(response.id, 42);
+        assert!(response.result.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_share_registry() -> anyhow::Result<()> {
+        let (tx, rx) = js_operation_channel();
+        let mut js = JS::new_with_config(Some(rx), None, None, None, AppConfig::default())?;
+        let registry = SharedPluginRegistry::default();
+        js.share_registry(registry.clone())?;
+        assert!(registry.read().unwrap().config_schema("builtin.dynamicmacro").is_some());
+
+        let src = r#"registerPlugin("test.registry", "Registry", "", () => true, [])"#;
+        request(
+            &mut js,
+            &tx,
+            JsOperation::Eval {
+                src: src.into(),
+                driver: false,
+            },
+        )?;
+        assert_eq!(registry.read().unwrap().plugins.len(), 2);
+
+        let unload = JsOperation::UnloadPlugin {
+            plugin_id: "test.registry".to_string(),
+        };
+        request(&mut js, &tx, unload)?;
+        assert!(registry.read().unwrap().config_schema("test.registry").is_none());
+
+        // User scripts are published with their filenames.
+        let dir = tempfile::tempdir()?;
+        let plugins = Plugins::new_with_basedir(dir.path().to_string_lossy().to_string());
+        plugins.add("test.file".into(), "File".into(), "".into())?;
+        let mut js =
+            JS::new_with_config(None, None, Some(plugins), None, AppConfig::default())?;
+        js.use_storage(None);
+        js.share_registry(registry.clone())?;
+        assert_eq!(registry.read().unwrap().filename("test.file"), None);
+        js.load_user_scripts()?;
+        assert_eq!(registry.read().unwrap().filename("test.file"), Some("test.file.js"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_publish_manifest_errors() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let plugins = Plugins::new_with_basedir(dir.path().to_string_lossy().to_string());
+        let register = r#"registerPlugin("test.broken", "Broken", "", () => true, [])"#;
+        let broken = format!(
+            "// ==KeyScripten==\n// @id test.broken\n// @color red\n// ==/KeyScripten==\n{}",
+            register
+        );
+        plugins.write("broken.js".into(), broken)?;
+        plugins.write("noid.js".into(), "// ==KeyScripten==\nregisterPlugin();".into())?;
+        let mut js = JS::new_with_config(
+            None,
+            None,
+            Some(Plugins::new_with_basedir(dir.path().to_string_lossy().to_string())),
+            None,
+            AppConfig::default(),
+        )?;
+        let registry = SharedPluginRegistry::default();
+        js.share_registry(registry.clone())?;
+        js.load_user_scripts()?;
+        {
+            let registry = registry.read().unwrap();
+            assert!(registry.config_schema("test.broken").is_none());
+            assert_eq!(registry.filename("test.broken"), Some("broken.js"));
+            assert!(registry.manifest_errors["test.broken"].contains("@color"));
+            assert_eq!(registry.filename("noid.js"), Some("noid.js"));
+            assert!(registry.manifest_errors.contains_key("noid.js"));
+        }
+
+        // Once fixed, the file is loaded on the next reload.
+        let fixed = format!(
+            "// ==KeyScripten==\n// @id test.broken\n// ==/KeyScripten==\n{}",
+            register
+        );
+        plugins.write("broken.js".into(), fixed)?;
+        js.load_user_scripts()?;
+        let registry = registry.read().unwrap();
+        assert!(registry.config_schema("test.broken").is_some());
+        assert_eq!(registry.filename("test.broken"), Some("broken.js"));
+        assert!(!registry.manifest_errors.contains_key("test.broken"));
+        assert!(registry.manifest_errors.contains_key("noid.js"));
+        Ok(())
+    }
+
     #[test]
     fn test_eval() -> anyhow::Result<()> {
-        let mut js = JS::new(None, None, None)?;
+        let mut js = JS::new_with_config(None, None, None, None, AppConfig::default())?;
         let value = js.eval("3+4".to_string())?;
         let got = value.to_u32(&mut js.context).unwrap();
         assert_eq!(got, 7);
         Ok(())
     }
+
+    #[test]
+    fn test_storage_is_flushed_by_timer() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("storage.json");
+
+        let mut js = JS::new_with_config(None, None, None, None, AppConfig::default())?;
+        js.use_virtual_clock();
+        js.use_storage(Some(path.clone()));
+        let manifest = PluginManifest::from_json(r#"{"id": "test.a", "permissions": ["storage"]}"#)?;
+        js.eval_plugin(r#"storage.set("k", {v: 1})"#, Some(&manifest))?;
+        js.run_timers_until(999)?;
+        assert!(!path.exists());
+        js.run_timers_until(1000)?;
+        let saved: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+        assert_eq!(saved, serde_json::json!({"test.a": {"k": {"v": 1}}}));
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_app_version() -> anyhow::Result<()> {
+        let mut js = JS::new_with_config(None, None, None, None, AppConfig::default())?;
+        let manifest = PluginManifest::from_json(r#"{"id": "test.a", "minAppVersion": "1.0"}"#)?;
+        js.set_app_version("0.6.0")?;
+        assert!(js.eval_plugin("1", Some(&manifest)).is_err());
+        js.set_app_version("1.0.0")?;
+        js.eval_plugin("1", Some(&manifest))?;
+        assert!(js.set_app_version("v1.0.0").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mouse_motion() -> anyhow::Result<()> {
+        let queue = Arc::new(RwLock::new(VecDeque::new()));
+        let mut js =
+            JS::new_with_config(None, Some(queue.clone()), None, None, AppConfig::default())?;
+        let motion = PluginManifest::from_json(r#"{"id": "test.motion", "mouseMotion": true}"#)?;
+        let keys = PluginManifest::from_json(r#"{"id": "test.keys"}"#)?;
+        for manifest in [&motion, &keys] {
+            let src = format!(r#"registerPlugin("{}", "", "", () => true, []);"#, manifest.id);
+            js.eval_plugin(&src, Some(manifest))?;
+        }
+        assert!(js.wants_mouse_motion()?);
+
+        js.send_event(Event::new_mouse(kCGEventMouseMoved, crate::event::Mouse::default(), 0))?;
+        js.send_event(Event::new(kCGEventKeyDown, 0, 0))?;
+        let calls = |js: &mut JS, id: &str| -> anyhow::Result<u64> {
+            let stats = js.stats()?;
+            Ok(stats.plugins.iter().find(|p| p.id == id).unwrap().calls)
+        };
+        assert_eq!(calls(&mut js, "test.motion")?, 2);
+        assert_eq!(calls(&mut js, "test.keys")?, 1);
+        let queued: Vec<String> =
+            queue.read().unwrap().iter().map(|e| e.event_type.clone()).collect();
+        assert_eq!(queued, vec!["keyDown"]);
+
+        js.set_plugin_enabled("test.motion", false)?;
+        assert!(!js.wants_mouse_motion()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_names() -> anyhow::Result<()> {
+        let mut js = JS::new_with_config(None, None, None, None, AppConfig::default())?;
+        let value = js.eval(
+            r#"[Key.nameOf(Key.A), Key.nameOf(Key.DIGIT_1), Key.nameOf(Key.KEYPAD_ENTER),
+                String(Key.nameOf(52)), new HotKey("M-C-t  j+k").toString()].join(",")"#
+                .to_string(),
+        )?;
+        let got = value.to_string(&mut js.context).unwrap().to_std_string_escaped();
+        assert_eq!(got, "a,1,keypad_enter,undefined,C-M-t j+k");
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_event_modifiers() -> anyhow::Result<()> {
+        let mut js = JS::new_with_config(None, None, None, None, AppConfig::default())?;
+        js.eval(
+            r#"let lastEvent;
+            registerPlugin("test.last", "last", "", (event) => { lastEvent = event; return true; }, []);"#
+                .to_string(),
+        )?;
+        let flags = kCGEventFlagMaskControl
+            | crate::cg_constants::kCGEventFlagMaskLeftControl
+            | kCGEventFlagMaskAlphaShift;
+        js.send_event(Event::new(kCGEventKeyDown, 0, flags).with_autorepeat(true))?;
+
+        let json = js.eval("JSON.stringify(lastEvent)".to_string())?;
+        let json = json.as_string().unwrap().to_std_string_escaped();
+        let event: serde_json::Value = serde_json::from_str(&json)?;
+        assert_eq!(event["type"], "keyDown");
+        assert_eq!(event["flags"], flags);
+        assert_eq!(event["ctrl"], true);
+        assert_eq!(event["leftCtrl"], true);
+        assert_eq!(event["rightCtrl"], false);
+        assert_eq!(event["capsLock"], true);
+        assert_eq!(event["shift"], false);
+        assert_eq!(event["autorepeat"], true);
+        assert!(event["timestamp"].as_u64().unwrap() > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_trace() -> anyhow::Result<()> {
+        let temp_dir = tempfile::TempDir::with_prefix("trace")?;
+        let path = temp_dir.path().join("trace.jsonl");
+
+        let backend = Arc::new(crate::mock_backend::MockBackend::new());
+        let mut js =
+            JS::new_with_config(None, None, None, Some(backend), AppConfig::default())?;
+        js.use_virtual_clock();
+        js.start_trace(path.clone())?;
+        for event in crate::harness::keystrokes("a b a b C-t")? {
+            js.send_event(event)?;
+        }
+        // Timers which don't inject anything aren't recorded.
+        js.eval_plugin("setTimeout(() => {}, 10); setTimeout(() => sendKeys('b'), 20);", None)?;
+        js.run_timers_until(20)?;
+        js.stop_trace();
+
+        let trace = crate::trace::read_trace(&path)?;
+        assert_eq!(trace.entries.len(), 13);
+        // keyDown of `C-t` is swallowed, and `a b` is injected instead.
+        let entry = &trace.entries[9];
+        assert_eq!(entry.event.as_ref().unwrap().event_type, "keyDown");
+        assert!(!entry.pass);
+        let injected: Vec<i64> = entry.injected.iter().map(|e| e.keycode).collect();
+        assert_eq!(injected, vec![0, 0, 11, 0]);
+        // The events sent by the timer are an entry of their own.
+        let entry = &trace.entries[12];
+        assert!(entry.event.is_none());
+        let injected: Vec<i64> = entry.injected.iter().map(|e| e.keycode).collect();
+        assert_eq!(injected, vec![0, 11, 11]);
+        Ok(())
+    }
 }
@@ -1,11 +1,36 @@
-use crate::app_config::AppConfig;
-use crate::send::{send_flags_changed_event, send_keyboard_event};
-use apple_sys::CoreGraphics::{CGEventFlags, CGKeyCode};
+use crate::app_config::{AppConfig, ConfigData};
+use crate::backend::{BackendData, InputBackend};
+use crate::cg_constants::{
+    kCGEventFlagsChanged, kCGEventKeyDown, kCGEventKeyUp, kCGEventScrollWheel, kCGMouseButtonCenter,
+    kCGMouseButtonLeft, kCGMouseButtonRight, CGEventFlags, CGEventType, CGKeyCode,
+};
+use crate::clock::ClockData;
+use crate::hotkey::{key_events, parse_keys};
+use crate::storage::StorageData;
+use crate::event::{event_type, is_mouse_event, Event, Mouse};
+use crate::trace::InjectedEvents;
 use boa_engine::{Context, js_string, JsArgs, JsError, JsNativeError, JsResult, JsString, JsValue};
+use std::sync::Arc;
 
 pub struct JsBuiltin {}
 
 impl JsBuiltin {
+    fn backend(context: &Context) -> JsResult<Arc<dyn InputBackend>> {
+        match context.get_data::<BackendData>() {
+            Some(data) => Ok(Arc::clone(&data.backend)),
+            None => Err(JsNativeError::typ()
+                .with_message("There's no input backend to send events")
+                .into()),
+        }
+    }
+
+    // Remember the injected event, if the trace is being recorded.
+    fn record_injected(context: &Context, event: Event) {
+        if let Some(injected) = context.get_data::<InjectedEvents>() {
+            injected.events.borrow_mut().push(event);
+        }
+    }
+
     pub fn send_flags_changed_event(
         _this: &JsValue,
         args: &[JsValue],
@@ -13,11 +38,15 @@ impl JsBuiltin {
     ) -> JsResult<JsValue> {
         let flags: &JsValue = args.get_or_undefined(0);
 
-        if let Err(err) = send_flags_changed_event(flags.to_i32(context).unwrap() as CGEventFlags) {
+        let flags = flags.to_i32(context).unwrap() as CGEventFlags;
+
+        let backend = Self::backend(context)?;
+        if let Err(err) = backend.send_flags_changed_event(flags) {
             return Err(JsNativeError::typ()
                 .with_message(format!("Cannot run send_flags_changed_event: {:?}", err))
                 .into());
         }
+        Self::record_injected(context, Event::new(kCGEventFlagsChanged, 0, flags));
 
         Ok(JsValue::undefined())
     }
@@ -31,28 +60,209 @@ impl JsBuiltin {
         let flags: &JsValue = args.get_or_undefined(1);
         let pressed: &JsValue = args.get_or_undefined(2);
 
-        if let Err(err) = send_keyboard_event(
-            keycode.to_i32(context).unwrap() as CGKeyCode,
-            flags.to_i32(context).unwrap() as CGEventFlags,
-            pressed.to_boolean(),
-        ) {
+        let keycode = keycode.to_i32(context).unwrap() as CGKeyCode;
+        let flags = flags.to_i32(context).unwrap() as CGEventFlags;
+        let pressed = pressed.to_boolean();
+
+        let backend = Self::backend(context)?;
+        if let Err(err) = backend.send_keyboard_event(keycode, flags, pressed) {
             return Err(JsNativeError::typ()
                 .with_message(format!("Cannot run send_keyboard_event: {:?}", err))
                 .into());
         }
+        let event_type = if pressed { kCGEventKeyDown } else { kCGEventKeyUp };
+        Self::record_injected(context, Event::new(event_type, keycode as i64, flags));
+
+        Ok(JsValue::undefined())
+    }
+
+    // sendMouseEvent(eventType, button, x, y, flags)
+    // `button` defaults to the button of `eventType`. The cursor position is kept if `x` or `y` is
+    // omitted.
+    pub fn send_mouse_event(
+        _this: &JsValue,
+        args: &[JsValue],
+        context: &mut Context,
+    ) -> JsResult<JsValue> {
+        let cg_event_type = args.get_or_undefined(0).to_u32(context)? as CGEventType;
+        if !is_mouse_event(cg_event_type) || cg_event_type == kCGEventScrollWheel {
+            return Err(JsNativeError::typ()
+                .with_message(format!(
+                    "sendMouseEvent: unsupported event type {}. Use sendScroll for scrolling.",
+                    cg_event_type
+                ))
+                .into());
+        }
+        let button = match args.get_or_undefined(1) {
+            button if button.is_undefined() => default_button(cg_event_type),
+            button => button.to_i32(context)? as i64,
+        };
+        let x = args.get_or_undefined(2);
+        let y = args.get_or_undefined(3);
+        let position = if x.is_undefined() || y.is_undefined() {
+            None
+        } else {
+            Some((x.to_number(context)?, y.to_number(context)?))
+        };
+        let flags = args.get_or_undefined(4).to_i32(context)? as CGEventFlags;
+
+        let backend = Self::backend(context)?;
+        if let Err(err) = backend.send_mouse_event(cg_event_type, button, position, flags) {
+            return Err(JsNativeError::typ()
+                .with_message(format!("Cannot run send_mouse_event: {:?}", err))
+                .into());
+        }
+        let (x, y) = position.unwrap_or_default();
+        let mouse = Mouse {
+            x,
+            y,
+            button,
+            ..Mouse::default()
+        };
+        Self::record_injected(context, Event::new_mouse(cg_event_type, mouse, flags));
 
         Ok(JsValue::undefined())
     }
 
+    // sendScroll(deltaY, deltaX, flags)
+    // Deltas are in lines. Positive deltaY scrolls up.
+    pub fn send_scroll(
+        _this: &JsValue,
+        args: &[JsValue],
+        context: &mut Context,
+    ) -> JsResult<JsValue> {
+        let delta_y = args.get_or_undefined(0).to_i32(context)? as i64;
+        let delta_x = args.get_or_undefined(1).to_i32(context)? as i64;
+        let flags = args.get_or_undefined(2).to_i32(context)? as CGEventFlags;
+
+        let backend = Self::backend(context)?;
+        if let Err(err) = backend.send_scroll_event(delta_x, delta_y, flags) {
+            return Err(JsNativeError::typ()
+                .with_message(format!("Cannot run send_scroll_event: {:?}", err))
+                .into());
+        }
+        let mouse = Mouse {
+            delta_x,
+            delta_y,
+            ..Mouse::default()
+        };
+        Self::record_injected(context, Event::new_mouse(kCGEventScrollWheel, mouse, flags));
+
+        Ok(JsValue::undefined())
+    }
+
+    // sendKeys(keys, flags)
+    // Types whitespace separated hotkeys like "C-a C-k M-v RET". The modifiers held when it's
+    // called are released first, and `flags` is restored at the end if it's given.
+    pub fn send_keys(
+        _this: &JsValue,
+        args: &[JsValue],
+        context: &mut Context,
+    ) -> JsResult<JsValue> {
+        let keys = args
+            .get_or_undefined(0)
+            .to_string(context)?
+            .to_std_string_escaped();
+        let restore_flags = match args.get_or_undefined(1) {
+            flags if flags.is_undefined() => None,
+            flags => Some(flags.to_i32(context)? as CGEventFlags),
+        };
+        // Parse everything first, so that nothing is sent for an invalid notation.
+        let hotkeys = parse_keys(&keys).map_err(|err| {
+            JsNativeError::typ().with_message(format!("Cannot parse keys `{}`: {:?}", keys, err))
+        })?;
+        let events = key_events(&hotkeys, restore_flags);
+
+        let backend = Self::backend(context)?;
+        for event in events {
+            let result = if event.event_type == event_type(kCGEventFlagsChanged) {
+                backend.send_flags_changed_event(event.flags)
+            } else {
+                let keydown = event.event_type == event_type(kCGEventKeyDown);
+                backend.send_keyboard_event(event.keycode as CGKeyCode, event.flags, keydown)
+            };
+            if let Err(err) = result {
+                return Err(JsNativeError::typ()
+                    .with_message(format!("Cannot run send_keys: {:?}", err))
+                    .into());
+            }
+            Self::record_injected(context, event);
+        }
+
+        Ok(JsValue::undefined())
+    }
+
+    // sendText(text)
+    // Types the unicode string. It doesn't depend on the current modifiers or the keyboard layout
+    // if the backend supports it.
+    pub fn send_text(
+        _this: &JsValue,
+        args: &[JsValue],
+        context: &mut Context,
+    ) -> JsResult<JsValue> {
+        let text = args
+            .get_or_undefined(0)
+            .to_string(context)?
+            .to_std_string_escaped();
+
+        let backend = Self::backend(context)?;
+        if let Err(err) = backend.send_text(&text) {
+            return Err(JsNativeError::typ()
+                .with_message(format!("Cannot run send_text: {:?}", err))
+                .into());
+        }
+        // One key press, the same as `MockBackend` records, so that the replay matches.
+        for event_type in [kCGEventKeyDown, kCGEventKeyUp] {
+            Self::record_injected(context, Event::new(event_type, 0, 0).with_text(&text));
+        }
+
+        Ok(JsValue::undefined())
+    }
+
+    fn storage(context: &Context) -> JsResult<&StorageData> {
+        context.get_data::<StorageData>().ok_or_else(|| {
+            JsNativeError::typ()
+                .with_message("There's no plugin storage")
+                .into()
+        })
+    }
+
+    // $$storageFlush()
+    pub fn storage_flush(
+        _this: &JsValue,
+        _args: &[JsValue],
+        context: &mut Context,
+    ) -> JsResult<JsValue> {
+        if let Err(err) = Self::storage(context)?.storage.borrow_mut().flush() {
+            return Err(JsNativeError::typ()
+                .with_message(format!("Cannot save plugin storage: {:?}", err))
+                .into());
+        }
+        Ok(JsValue::undefined())
+    }
+
+    // Milliseconds from the JS clock. Timers in driver.js are scheduled with it.
+    pub fn now(_this: &JsValue, _args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+        let now = match context.get_data::<ClockData>() {
+            Some(data) => data.clock.get().now_ms(),
+            None => 0,
+        };
+        Ok(JsValue::from(now as f64))
+    }
+
     pub fn load_app_config_json(
         _this: &JsValue,
         _args: &[JsValue],
-        _context: &mut Context,
+        context: &mut Context,
     ) -> JsResult<JsValue> {
-        match AppConfig::load() {
+        let config = match context.get_data::<ConfigData>().and_then(|data| data.config.clone()) {
+            Some(config) => Ok(config),
+            None => AppConfig::load(),
+        };
+        match config {
             Ok(config) => match serde_json::to_string(&config) {
                 Ok(json) => {
-                    return Ok(JsValue::new(JsString::from(json.as_str())));
+                    Ok(JsValue::new(JsString::from(json.as_str())))
                 }
                 Err(err) => Err(JsError::from_opaque(
                     js_string!(format!("Cannot make json: {:?}", err)).into(),
@@ -64,3 +274,12 @@ impl JsBuiltin {
         }
     }
 }
+
+fn default_button(cg_event_type: CGEventType) -> i64 {
+    let button = match event_type(cg_event_type) {
+        "rightMouseDown" | "rightMouseUp" | "rightMouseDragged" => kCGMouseButtonRight,
+        "otherMouseDown" | "otherMouseUp" | "otherMouseDragged" => kCGMouseButtonCenter,
+        _ => kCGMouseButtonLeft,
+    };
+    button as i64
+}
@@ -323,7 +323,7 @@ impl Console {
         console: &Self,
         context: &mut Context,
     ) -> JsResult<JsValue> {
-        let assertion = args.first().map_or(false, JsValue::to_boolean);
+        let assertion = args.first().is_some_and(JsValue::to_boolean);
 
         if !assertion {
             let mut args: Vec<JsValue> = args.iter().skip(1).cloned().collect();
@@ -514,7 +514,7 @@ impl Console {
         console: &mut Self,
         context: &mut Context,
     ) -> JsResult<JsValue> {
-        let label = match args.get(0) {
+        let label = match args.first() {
             Some(value) => value.to_string(context)?,
             None => "default".into(),
         };
@@ -543,7 +543,7 @@ impl Console {
         console: &mut Self,
         context: &mut Context,
     ) -> JsResult<JsValue> {
-        let label = match args.get(0) {
+        let label = match args.first() {
             Some(value) => value.to_string(context)?,
             None => "default".into(),
         };
@@ -582,12 +582,14 @@ impl Console {
         console: &mut Self,
         context: &mut Context,
     ) -> JsResult<JsValue> {
-        let label = match args.get(0) {
+        let label = match args.first() {
             Some(value) => value.to_string(context)?,
             None => "default".into(),
         };
 
-        if console.timer_map.get(&label).is_some() {
+        // The entry API would borrow `console`, which `logger` needs.
+        #[allow(clippy::map_entry)]
+        if console.timer_map.contains_key(&label) {
             logger(
                 LogMessage::Warn(format!(
                     "Timer '{}' already exist",
@@ -619,7 +621,7 @@ impl Console {
         console: &Self,
         context: &mut Context,
     ) -> JsResult<JsValue> {
-        let label = match args.get(0) {
+        let label = match args.first() {
             Some(value) => value.to_string(context)?,
             None => "default".into(),
         };
@@ -663,7 +665,7 @@ impl Console {
         console: &mut Self,
         context: &mut Context,
     ) -> JsResult<JsValue> {
-        let label = match args.get(0) {
+        let label = match args.first() {
             Some(value) => value.to_string(context)?,
             None => "default".into(),
         };
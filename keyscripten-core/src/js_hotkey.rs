@@ -1,11 +1,15 @@
-use crate::hotkey::HotKey;
+use crate::clock::ClockData;
+use crate::hotkey::KeySequence;
 use boa_engine::class::{Class, ClassBuilder};
-use boa_engine::{Context, js_string, JsArgs, JsData, JsError, JsResult, JsValue, NativeFunction};
+use boa_engine::object::builtins::JsArray;
+use boa_engine::object::ObjectInitializer;
+use boa_engine::property::Attribute;
+use boa_engine::{Context, js_string, JsArgs, JsData, JsError, JsObject, JsResult, JsValue, NativeFunction};
 use boa_gc::{Finalize, Trace};
 
 #[derive(Debug, Trace, Finalize, JsData)]
 pub struct JsHotKey {
-    hotkey: HotKey,
+    hotkey: KeySequence,
 }
 
 impl JsHotKey {
@@ -56,10 +60,132 @@ impl JsHotKey {
             }
         };
 
-        let result = this_ref.hotkey.matches(flags, keycode);
+        let Some(hotkey) = this_ref.hotkey.single() else {
+            return Err(JsError::from_opaque(
+                js_string!("JsHotKey.matches doesn't support key sequences and chords. Use feed.")
+                    .into(),
+            ));
+        };
+        let result = hotkey.matches(flags, keycode);
 
         Ok(JsValue::new(result))
     }
+
+    // Feeds the event object to the sequence matcher. Returns "match", "partial" or "none".
+    // Events other than keyDown return "none" without changing the state.
+    fn feed(this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+        let Some(event) = args.first().and_then(|event| event.as_object()) else {
+            return Err(JsError::from_opaque(
+                js_string!("first argument of the JsHotKey.feed should be an event.").into(),
+            ));
+        };
+        let event_type = event
+            .get(js_string!("type"), context)?
+            .to_string(context)?
+            .to_std_string_escaped();
+        if event_type != "keyDown" {
+            return Ok(js_string!("none").into());
+        }
+        let keycode = event.get(js_string!("keycode"), context)?.to_uint16(context)?;
+        let flags = event.get(js_string!("flags"), context)?.to_number(context)? as u64;
+        let autorepeat = event.get(js_string!("autorepeat"), context)?.to_boolean();
+        let now = now_ms(context);
+
+        let obj = this_object(this)?;
+        let Some(mut this_ref) = obj.downcast_mut::<Self>() else {
+            return Err(JsError::from_opaque(
+                js_string!("the 'this' object is not a JsHotkey").into(),
+            ));
+        };
+        let state = this_ref.hotkey.feed(flags, keycode, autorepeat, now);
+        Ok(js_string!(state.as_str()).into())
+    }
+
+    fn reset(this: &JsValue, _args: &[JsValue], _context: &mut Context) -> JsResult<JsValue> {
+        let obj = this_object(this)?;
+        let Some(mut this_ref) = obj.downcast_mut::<Self>() else {
+            return Err(JsError::from_opaque(
+                js_string!("the 'this' object is not a JsHotkey").into(),
+            ));
+        };
+        this_ref.hotkey.reset();
+        Ok(JsValue::undefined())
+    }
+
+    // Returns the keyDown events of the cancelled partial matches as `{ keycode, flags }`, and
+    // forgets them.
+    fn take_cancelled(
+        this: &JsValue,
+        _args: &[JsValue],
+        context: &mut Context,
+    ) -> JsResult<JsValue> {
+        let obj = this_object(this)?;
+        let Some(mut this_ref) = obj.downcast_mut::<Self>() else {
+            return Err(JsError::from_opaque(
+                js_string!("the 'this' object is not a JsHotkey").into(),
+            ));
+        };
+        let cancelled = this_ref.hotkey.take_cancelled();
+        drop(this_ref);
+
+        let events: Vec<JsValue> = cancelled
+            .iter()
+            .map(|press| {
+                ObjectInitializer::new(context)
+                    .property(js_string!("keycode"), press.keycode, Attribute::all())
+                    .property(js_string!("flags"), press.flags as f64, Attribute::all())
+                    .build()
+                    .into()
+            })
+            .collect();
+        Ok(JsArray::from_iter(events, context).into())
+    }
+
+    fn is_pending(this: &JsValue, _args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+        let now = now_ms(context);
+        let obj = this_object(this)?;
+        let Some(this_ref) = obj.downcast_ref::<Self>() else {
+            return Err(JsError::from_opaque(
+                js_string!("the 'this' object is not a JsHotkey").into(),
+            ));
+        };
+        Ok(JsValue::new(this_ref.hotkey.is_pending(now)))
+    }
+
+    fn to_string(this: &JsValue, _args: &[JsValue], _context: &mut Context) -> JsResult<JsValue> {
+        let obj = this_object(this)?;
+        let Some(this_ref) = obj.downcast_ref::<Self>() else {
+            return Err(JsError::from_opaque(
+                js_string!("the 'this' object is not a JsHotkey").into(),
+            ));
+        };
+        Ok(js_string!(this_ref.hotkey.to_string()).into())
+    }
+}
+
+fn this_object(this: &JsValue) -> JsResult<JsObject> {
+    this.as_object().ok_or_else(|| {
+        JsError::from_opaque(js_string!("the 'this' object is not a JsHotkey").into())
+    })
+}
+
+fn now_ms(context: &Context) -> u64 {
+    match context.get_data::<ClockData>() {
+        Some(data) => data.clock.get().now_ms(),
+        None => 0,
+    }
+}
+
+// Reads a millisecond option like `{ timeout: 500 }`. Returns None if it's not given.
+fn read_timeout(options: &JsValue, name: &str, context: &mut Context) -> JsResult<Option<u64>> {
+    let Some(options) = options.as_object() else {
+        return Ok(None);
+    };
+    let value = options.get(js_string!(name), context)?;
+    if value.is_undefined() || value.is_null() {
+        return Ok(None);
+    }
+    Ok(Some(value.to_number(context)?.max(0.0) as u64))
 }
 
 impl Class for JsHotKey {
@@ -71,9 +197,16 @@ impl Class for JsHotKey {
         let string_notation = args.get_or_undefined(0).to_string(context)?;
         let string_notation = string_notation.to_std_string_escaped();
 
-        let hotkey = HotKey::from_str(string_notation.as_str()).map_err(|err| {
+        let mut hotkey = KeySequence::from_str(string_notation.as_str()).map_err(|err| {
             JsError::from_opaque(js_string!(format!("Cannot parse shortcut key: {:?}", err)).into())
         })?;
+        let options = args.get_or_undefined(1);
+        if let Some(timeout) = read_timeout(options, "timeout", context)? {
+            hotkey.timeout_ms = timeout;
+        }
+        if let Some(timeout) = read_timeout(options, "chordTimeout", context)? {
+            hotkey.chord_timeout_ms = timeout;
+        }
 
         Ok(JsHotKey { hotkey })
     }
@@ -81,6 +214,15 @@ impl Class for JsHotKey {
     /// Here is where the class is initialized, to be inserted into the global object.
     fn init(class: &mut ClassBuilder) -> JsResult<()> {
         class.method(js_string!("matches"), 0, NativeFunction::from_fn_ptr(Self::matches));
+        class.method(js_string!("feed"), 1, NativeFunction::from_fn_ptr(Self::feed));
+        class.method(js_string!("reset"), 0, NativeFunction::from_fn_ptr(Self::reset));
+        class.method(js_string!("isPending"), 0, NativeFunction::from_fn_ptr(Self::is_pending));
+        class.method(
+            js_string!("takeCancelled"),
+            0,
+            NativeFunction::from_fn_ptr(Self::take_cancelled),
+        );
+        class.method(js_string!("toString"), 0, NativeFunction::from_fn_ptr(Self::to_string));
 
         Ok(())
     }
@@ -0,0 +1,61 @@
+use boa_engine::job::{Job, JobExecutor};
+use boa_engine::realm::Realm;
+use boa_engine::{Context, JsResult, JsValue};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+/// The Promise jobs, i.e. the continuations of `await` and the `then` callbacks.
+///
+/// boa's default executor runs them all at once, and drops the rest on the first error. js.rs
+/// pops them one by one instead, so that an aborted job is charged to the plugin of its realm and
+/// the jobs of the other plugins still run.
+#[derive(Default)]
+pub(crate) struct JobQueue {
+    jobs: RefCell<VecDeque<Job>>,
+}
+
+impl JobQueue {
+    pub(crate) fn pop(&self) -> Option<Job> {
+        self.jobs.borrow_mut().pop_front()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.jobs.borrow().is_empty()
+    }
+}
+
+/// The realm the job runs in, if any.
+pub(crate) fn job_realm(job: &Job) -> Option<&Realm> {
+    match job {
+        Job::PromiseJob(job) => job.realm(),
+        Job::GenericJob(job) => Some(job.realm()),
+        _ => None,
+    }
+}
+
+pub(crate) fn run_job(job: Job, context: &mut Context) -> JsResult<JsValue> {
+    match job {
+        Job::PromiseJob(job) => job.call(context),
+        Job::GenericJob(job) => job.call(context),
+        // Not enqueued, see `enqueue_job`.
+        _ => Ok(JsValue::undefined()),
+    }
+}
+
+impl JobExecutor for JobQueue {
+    fn enqueue_job(self: Rc<Self>, job: Job, _context: &mut Context) {
+        match job {
+            Job::PromiseJob(_) | Job::GenericJob(_) => self.jobs.borrow_mut().push_back(job),
+            // Only native async functions and `Atomics.waitAsync` make them. The API has neither.
+            job => log::error!("Dropped an unsupported job: {:?}", job),
+        }
+    }
+
+    fn run_jobs(self: Rc<Self>, context: &mut Context) -> JsResult<()> {
+        while let Some(job) = self.pop() {
+            run_job(job, context)?;
+        }
+        Ok(())
+    }
+}
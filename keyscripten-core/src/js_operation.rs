@@ -1,10 +1,212 @@
+use crate::event::Event;
+use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Version of `JsRequest`/`JsResponse`. Bump it on incompatible changes.
+pub const PROTOCOL_VERSION: u32 = 1;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub enum JsOperation {
     ReloadConfig,
     ReloadPlugins,
     UnloadPlugin { plugin_id: String },
+    // Record processed events to the trace file. Default path is used if `path` is None.
+    StartTrace { path: Option<String> },
+    StopTrace,
+    // Saved to config.json, like the checkbox in the settings.
+    SetPluginEnabled { plugin_id: String, enabled: bool },
+    // Saved to config.json as the priorities, like reordering the list in the settings. The
+    // plugins run in the order of `plugin_ids`.
+    SetPluginOrder { plugin_ids: Vec<String> },
+    // Evaluates `src` in a realm of its own, under the budget of a plugin file. Returns the value
+    // as JSON. `driver` evaluates it in the driver realm instead, to debug driver.js.
+    Eval {
+        src: String,
+        #[serde(default)]
+        driver: bool,
+    },
+    // While paused, every event passes through and the timers don't fire.
+    Pause,
+    Resume,
+    GetStats,
+    // Passes the event to the plugins as if it was captured. Returns the event to deliver.
+    InjectEvent { event: Event },
+}
+
+/// A request to the JS thread. `id` is returned in the response as is.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct JsRequest {
+    pub version: u32,
+    pub id: u64,
+    pub operation: JsOperation,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct JsResponse {
+    pub version: u32,
+    pub id: u64,
+    pub result: Result<JsOperationOutput, String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub enum JsOperationOutput {
+    Done,
+    Value(serde_json::Value),
+    Stats(RuntimeStats),
+    // None if the plugins swallowed the event.
+    Delivered(Option<Event>),
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RuntimeStats {
+    pub paused: bool,
+    // Events passed to `JS::send_event`, and the time spent in it.
+    pub events: u64,
+    pub event_micros: u64,
+    pub plugins: Vec<PluginStats>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PluginStats {
+    pub id: String,
+    pub name: String,
+    pub filename: Option<String>,
+    pub enabled: bool,
+    pub calls: u64,
+    pub swallowed: u64,
+    pub exceptions: u64,
+    // Callbacks aborted by the watchdog since the plugin was (re-)enabled.
+    #[serde(default)]
+    pub aborted: u32,
+}
+
+/// A request and the channel to report its result to.
+pub struct JsOperationMessage {
+    pub request: JsRequest,
+    reply: Option<Sender<JsResponse>>,
+}
+
+impl JsOperationMessage {
+    /// Reports the result to the sender, if it waits for it.
+    pub fn reply(self, result: anyhow::Result<JsOperationOutput>) {
+        if let Some(reply) = self.reply {
+            // The sender may have given up waiting.
+            let _ = reply.send(JsResponse {
+                version: PROTOCOL_VERSION,
+                id: self.request.id,
+                result: result.map_err(|err| format!("{:?}", err)),
+            });
+        }
+    }
+}
+
+// Wakes up the run loop of the JS thread. The backend installs it.
+type Waker = Arc<Mutex<Option<Box<dyn Fn() + Send>>>>;
+
+/// Sends operations to the JS thread, and wakes up its run loop, so that they're applied without
+/// waiting for the next key event.
+#[derive(Clone)]
+pub struct JsOperationSender {
+    tx: Sender<JsOperationMessage>,
+    waker: Waker,
+    next_id: Arc<AtomicU64>,
+}
+
+impl JsOperationSender {
+    /// Sends the operation without waiting for the result.
+    pub fn send(&self, operation: JsOperation) -> anyhow::Result<()> {
+        self.post(JsOperationMessage {
+            request: self.new_request(operation),
+            reply: None,
+        })
+    }
+
+    /// Sends the operation and waits until the JS thread applies it.
+    pub fn call(
+        &self,
+        operation: JsOperation,
+        timeout: Duration,
+    ) -> anyhow::Result<JsOperationOutput> {
+        self.request(self.new_request(operation), timeout)?
+            .result
+            .map_err(|err| anyhow!("{}", err))
+    }
+
+    /// Sends the request and waits for the response. Fails if the JS thread doesn't respond.
+    pub fn request(&self, request: JsRequest, timeout: Duration) -> anyhow::Result<JsResponse> {
+        let (reply_tx, reply_rx) = channel();
+        self.post(JsOperationMessage {
+            request,
+            reply: Some(reply_tx),
+        })?;
+        match reply_rx.recv_timeout(timeout) {
+            Ok(response) => Ok(response),
+            Err(RecvTimeoutError::Timeout) => {
+                Err(anyhow!("The JS thread didn't respond in {:?}", timeout))
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(anyhow!("The JS thread dropped the operation")),
+        }
+    }
+
+    fn new_request(&self, operation: JsOperation) -> JsRequest {
+        JsRequest {
+            version: PROTOCOL_VERSION,
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            operation,
+        }
+    }
+
+    fn post(&self, message: JsOperationMessage) -> anyhow::Result<()> {
+        self.tx
+            .send(message)
+            .map_err(|err| anyhow!("Cannot send the operation to the JS thread: {:?}", err))?;
+        if let Some(wake) = self.waker.lock().unwrap().as_ref() {
+            wake();
+        }
+        Ok(())
+    }
+}
+
+/// The JS thread side of the operation channel.
+pub struct JsOperationReceiver {
+    rx: Receiver<JsOperationMessage>,
+    waker: Waker,
+}
+
+impl JsOperationReceiver {
+    /// Installs the function which makes the run loop call `JS::process_js_operations`. It's
+    /// called on the sender thread.
+    pub fn set_waker(&self, wake: impl Fn() + Send + 'static) {
+        *self.waker.lock().unwrap() = Some(Box::new(wake));
+    }
+
+    pub fn try_recv(&self) -> Option<JsOperationMessage> {
+        match self.rx.try_recv() {
+            Ok(message) => Some(message),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                log::debug!("js operation channel is disconnected");
+                None
+            }
+        }
+    }
+}
+
+pub fn js_operation_channel() -> (JsOperationSender, JsOperationReceiver) {
+    let (tx, rx) = channel();
+    let waker: Waker = Arc::new(Mutex::new(None));
+    (
+        JsOperationSender {
+            tx,
+            waker: waker.clone(),
+            next_id: Arc::new(AtomicU64::new(1)),
+        },
+        JsOperationReceiver { rx, waker },
+    )
 }
 
 #[cfg(test)]
@@ -29,5 +231,31 @@ mod tests {
             } => assert_eq!(plugin_id, "example-plugin"),
             _ => panic!("Expected UnloadPlugin"),
         }
+
+        let json = r#"{ "StartTrace": { "path": null } }"#;
+        let op: JsOperation = serde_json::from_str(json).unwrap();
+        assert!(matches!(op, JsOperation::StartTrace { path: None }));
+
+        let json = r#"{ "SetPluginEnabled": { "plugin_id": "example-plugin", "enabled": false } }"#;
+        let op: JsOperation = serde_json::from_str(json).unwrap();
+        assert!(matches!(op, JsOperation::SetPluginEnabled { enabled: false, .. }));
+    }
+
+    #[test]
+    fn test_request_and_response() {
+        let json = r#"{ "version": 1, "id": 7, "operation": { "Eval": { "src": "1 + 1" } } }"#;
+        let request: JsRequest = serde_json::from_str(json).unwrap();
+        assert_eq!(request.id, 7);
+        assert!(matches!(request.operation, JsOperation::Eval { .. }));
+
+        let response = JsResponse {
+            version: PROTOCOL_VERSION,
+            id: 7,
+            result: Ok(JsOperationOutput::Value(serde_json::json!(2))),
+        };
+        assert_eq!(
+            serde_json::to_string(&response).unwrap(),
+            r#"{"version":1,"id":7,"result":{"Ok":{"Value":2}}}"#
+        );
     }
 }
@@ -0,0 +1,272 @@
+use crate::storage::StorageData;
+use boa_engine::context::HostHooks;
+use boa_engine::error::JsNativeErrorKind;
+use boa_engine::object::builtins::JsFunction;
+use boa_engine::realm::Realm;
+use boa_engine::{
+    js_string, Context, JsArgs, JsData, JsError, JsNativeError, JsObject, JsResult, JsString,
+    JsValue,
+};
+use boa_gc::{Finalize, Trace};
+use std::cell::RefCell;
+
+// Builds the `storage` object of a plugin realm. The natives it closes over aren't globals.
+pub(crate) const STORAGE_API: &str = r#"
+((get, set, remove, keys) => {
+    const parse = JSON.parse;
+    const stringify = JSON.stringify;
+    return {
+        get(key, defaultValue) {
+            const json = get(String(key));
+            return json === undefined ? defaultValue : parse(json);
+        },
+        set(key, value) {
+            const json = stringify(value);
+            if (json === undefined) {
+                throw new TypeError(`Cannot store ${typeof value} in the storage`);
+            }
+            set(String(key), json);
+        },
+        delete(key) {
+            return remove(String(key));
+        },
+        keys() {
+            return parse(keys());
+        },
+    };
+})
+"#;
+
+/// A native function of a plugin realm which calls a function of driver.js, given as the capture.
+pub(crate) type DriverCall =
+    fn(&JsValue, &[JsValue], &JsFunction, &mut Context) -> JsResult<JsValue>;
+
+/// Rejects `eval` and `Function` in the plugin realms. The code they compile wouldn't be
+/// instrumented, so it could run past the budget of the callback.
+pub(crate) struct PluginHooks;
+
+impl HostHooks for PluginHooks {
+    fn ensure_can_compile_strings(
+        &self,
+        realm: Realm,
+        _parameters: &[JsString],
+        _body: &JsString,
+        _direct: bool,
+        _context: &mut Context,
+    ) -> JsResult<()> {
+        if realm.host_defined().get::<PluginRealm>().is_some() {
+            return Err(JsNativeError::eval()
+                .with_message("eval and Function aren't available to plugins")
+                .into());
+        }
+        Ok(())
+    }
+}
+
+/// Data of the realm of a plugin file, kept in the host defined slot of the realm.
+///
+/// Native functions run in the realm they were created in, so the natives injected into a plugin
+/// realm find it through `context.realm()`.
+#[derive(Trace, Finalize, JsData)]
+pub(crate) struct PluginRealm {
+    // The plugin which owns the realm: the id in the manifest, or else the first registered id.
+    #[unsafe_ignore_trace]
+    owner: RefCell<Option<String>>,
+    // The object made by realm.js in this realm.
+    bridge: JsObject,
+}
+
+impl PluginRealm {
+    pub(crate) fn new(owner: Option<String>, bridge: JsObject) -> Self {
+        PluginRealm {
+            owner: RefCell::new(owner),
+            bridge,
+        }
+    }
+
+    /// Returns None for the driver realm, and for a plugin realm which hasn't registered yet.
+    pub(crate) fn owner_of(realm: &Realm) -> Option<String> {
+        realm
+            .host_defined()
+            .get::<PluginRealm>()
+            .and_then(|data| data.owner.borrow().clone())
+    }
+
+    // owner() of the realm.js objects: the owner of their realm, or null.
+    pub(crate) fn owner(
+        _this: &JsValue,
+        _args: &[JsValue],
+        context: &mut Context,
+    ) -> JsResult<JsValue> {
+        Ok(match Self::owner_of(context.realm()) {
+            Some(owner) => js_string!(owner).into(),
+            None => JsValue::null(),
+        })
+    }
+
+    // registerPlugin(id, ...) of the plugin realms. The first id becomes the owner of a realm
+    // without a manifest.
+    pub(crate) fn register_plugin(
+        this: &JsValue,
+        args: &[JsValue],
+        register: &JsFunction,
+        context: &mut Context,
+    ) -> JsResult<JsValue> {
+        if let Some(id) = args.first().and_then(JsValue::as_string) {
+            if let Some(data) = context.realm().host_defined().get::<PluginRealm>() {
+                data.owner
+                    .borrow_mut()
+                    .get_or_insert_with(|| id.to_std_string_escaped());
+            }
+        }
+        Self::call_driver(this, args, register, context)
+    }
+
+    // Calls `function` of driver.js with the realm.js object of the current realm, followed by
+    // `args`.
+    //
+    // Whatever comes back from the driver realm is an object of the driver realm, and its
+    // `constructor` would give the plugin the `Function` of the driver, i.e. eval of the driver
+    // realm. So only primitive values are returned, and errors are thrown again as new errors of
+    // the plugin realm.
+    pub(crate) fn call_driver(
+        _this: &JsValue,
+        args: &[JsValue],
+        function: &JsFunction,
+        context: &mut Context,
+    ) -> JsResult<JsValue> {
+        let bridge = match context.realm().host_defined().get::<PluginRealm>() {
+            Some(data) => data.bridge.clone(),
+            None => {
+                return Err(JsNativeError::error()
+                    .with_message("Not in a plugin realm")
+                    .into())
+            }
+        };
+        let mut driver_args = vec![JsValue::from(bridge)];
+        driver_args.extend_from_slice(args);
+        let result = function
+            .call(&JsValue::undefined(), &driver_args, context)
+            .map_err(|err| Self::rethrow(err, context))?;
+        Ok(if result.is_object() {
+            JsValue::undefined()
+        } else {
+            result
+        })
+    }
+
+    fn rethrow(err: JsError, context: &mut Context) -> JsError {
+        // The watchdog must stay uncatchable.
+        if err.as_native().is_some_and(|err| err.is_runtime_limit()) {
+            return err;
+        }
+        if err.as_opaque().is_some_and(|value| !value.is_object()) {
+            return err;
+        }
+        let native = match err.try_native(context) {
+            Ok(native) => {
+                let error = match native.kind {
+                    JsNativeErrorKind::Type => JsNativeError::typ(),
+                    JsNativeErrorKind::Range => JsNativeError::range(),
+                    JsNativeErrorKind::Reference => JsNativeError::reference(),
+                    JsNativeErrorKind::Syntax => JsNativeError::syntax(),
+                    _ => JsNativeError::error(),
+                };
+                error.with_message(native.message().to_string())
+            }
+            Err(_) => JsNativeError::error().with_message(err.to_string()),
+        };
+        native.into()
+    }
+
+    fn storage_owner(context: &Context) -> JsResult<String> {
+        Self::owner_of(context.realm()).ok_or_else(|| {
+            JsNativeError::error()
+                .with_message("storage needs @id in the manifest, or a registered plugin")
+                .into()
+        })
+    }
+
+    fn storage(context: &Context) -> JsResult<&StorageData> {
+        context.get_data::<StorageData>().ok_or_else(|| {
+            JsNativeError::typ()
+                .with_message("There's no plugin storage")
+                .into()
+        })
+    }
+
+    // get(key): returns the JSON string, or undefined.
+    pub(crate) fn storage_get(
+        _this: &JsValue,
+        args: &[JsValue],
+        context: &mut Context,
+    ) -> JsResult<JsValue> {
+        let plugin_id = Self::storage_owner(context)?;
+        let key = args.get_or_undefined(0).to_string(context)?.to_std_string_escaped();
+
+        let value = Self::storage(context)?.storage.borrow_mut().get(&plugin_id, &key);
+        Ok(match value {
+            Some(value) => js_string!(value.to_string()).into(),
+            None => JsValue::undefined(),
+        })
+    }
+
+    // set(key, json). `schedule_flush` is `$$scheduleStorageFlush` of driver.js.
+    pub(crate) fn storage_set(
+        _this: &JsValue,
+        args: &[JsValue],
+        schedule_flush: &JsFunction,
+        context: &mut Context,
+    ) -> JsResult<JsValue> {
+        let plugin_id = Self::storage_owner(context)?;
+        let key = args.get_or_undefined(0).to_string(context)?.to_std_string_escaped();
+        let json = args.get_or_undefined(1).to_string(context)?.to_std_string_escaped();
+        let value = serde_json::from_str(&json).map_err(|err| {
+            JsNativeError::typ().with_message(format!("Invalid JSON for storage: {:?}", err))
+        })?;
+
+        Self::storage(context)?
+            .storage
+            .borrow_mut()
+            .set(&plugin_id, &key, value);
+        schedule_flush
+            .call(&JsValue::undefined(), &[], context)
+            .map_err(|err| Self::rethrow(err, context))?;
+        Ok(JsValue::undefined())
+    }
+
+    // delete(key): returns false if there was no such key.
+    pub(crate) fn storage_delete(
+        _this: &JsValue,
+        args: &[JsValue],
+        schedule_flush: &JsFunction,
+        context: &mut Context,
+    ) -> JsResult<JsValue> {
+        let plugin_id = Self::storage_owner(context)?;
+        let key = args.get_or_undefined(0).to_string(context)?.to_std_string_escaped();
+
+        let deleted = Self::storage(context)?
+            .storage
+            .borrow_mut()
+            .delete(&plugin_id, &key);
+        schedule_flush
+            .call(&JsValue::undefined(), &[], context)
+            .map_err(|err| Self::rethrow(err, context))?;
+        Ok(JsValue::new(deleted))
+    }
+
+    // keys(): returns the keys as a JSON array.
+    pub(crate) fn storage_keys(
+        _this: &JsValue,
+        _args: &[JsValue],
+        context: &mut Context,
+    ) -> JsResult<JsValue> {
+        let plugin_id = Self::storage_owner(context)?;
+
+        let keys = Self::storage(context)?.storage.borrow_mut().keys(&plugin_id);
+        let json = serde_json::to_string(&keys).map_err(|err| {
+            JsNativeError::typ().with_message(format!("Cannot serialize keys: {:?}", err))
+        })?;
+        Ok(js_string!(json).into())
+    }
+}
@@ -1,100 +1,89 @@
-use std::collections::HashMap;
-use apple_sys::CoreGraphics::CGKeyCode;
+use crate::cg_constants::{kCGEventFlagMaskShift, CGEventFlags, CGKeyCode};
+use anyhow::anyhow;
 
-type KeyCodeMap = HashMap<&'static str, CGKeyCode>;
+include!(concat!(env!("OUT_DIR"), "/keycode_table.rs"));
 
-fn build_key_code_map() -> KeyCodeMap {
-    let mut map: KeyCodeMap = HashMap::new();
-    map.insert("alt", 58);
-    map.insert("alt_gr", 61);
-    map.insert("backspace", 51);
-    map.insert("caps_lock", 57);
-    map.insert("control_left", 59);
-    map.insert("control_right", 62);
-    map.insert("down_arrow", 125);
-    map.insert("escape", 53);
-    map.insert("f1", 122);
-    map.insert("f10", 109);
-    map.insert("f11", 103);
-    map.insert("f12", 111);
-    map.insert("f2", 120);
-    map.insert("f3", 99);
-    map.insert("f4", 118);
-    map.insert("f5", 96);
-    map.insert("f6", 97);
-    map.insert("f7", 98);
-    map.insert("f8", 100);
-    map.insert("f9", 101);
-    map.insert("function", 63);
-    map.insert("left_arrow", 123);
-    map.insert("meta_left", 55);
-    map.insert("meta_right", 54);
-    map.insert("return", 36);
-    map.insert("enter", 36);
-    map.insert("right_arrow", 124);
-    map.insert("shift_left", 56);
-    map.insert("shift_right", 60);
-    map.insert("space", 49); // space
-    map.insert("tab", 48); // tab
-    map.insert("up", 126); // up arrow
-    map.insert("`", 50); // backquote
-    map.insert("num1", 18);
-    map.insert("num2", 19);
-    map.insert("num3", 20);
-    map.insert("num4", 21);
-    map.insert("num5", 23);
-    map.insert("num6", 22);
-    map.insert("num7", 26);
-    map.insert("num8", 28);
-    map.insert("num9", 25);
-    map.insert("num0", 29);
-    map.insert("-", 27); // minus
-    map.insert("=", 24); // equal
-    map.insert("q", 12);
-    map.insert("w", 13);
-    map.insert("e", 14);
-    map.insert("r", 15);
-    map.insert("t", 17);
-    map.insert("y", 16);
-    map.insert("u", 32);
-    map.insert("i", 34);
-    map.insert("o", 31);
-    map.insert("p", 35);
-    map.insert("{", 33); // left_bracket
-    map.insert("}", 30); // right_bracket
-    map.insert("a", 0);
-    map.insert("s", 1);
-    map.insert("d", 2);
-    map.insert("f", 3);
-    map.insert("g", 5);
-    map.insert("h", 4);
-    map.insert("j", 38);
-    map.insert("k", 40);
-    map.insert("l", 37);
-    map.insert(";", 41); // semicolon
-    map.insert("'", 39); // quote
-    map.insert("\\", 42); // backslash
-    map.insert("z", 6);
-    map.insert("x", 7);
-    map.insert("c", 8);
-    map.insert("v", 9);
-    map.insert("b", 11);
-    map.insert("n", 45);
-    map.insert("m", 46);
-    map.insert(",", 43); // comma
-    map.insert(".", 47); // dot
-    map.insert("/", 44); // slash
-    map
+pub(crate) fn get_keycode(keyname: &str) -> Option<CGKeyCode> {
+    let keyname = keyname.to_ascii_lowercase();
+    KEYCODES
+        .iter()
+        .find(|(_, names)| names.contains(&keyname.as_str()))
+        .map(|(keycode, _)| *keycode)
 }
 
-pub(crate) fn get_keycode(keyname: &str) -> Option<CGKeyCode> {
-    let map = build_key_code_map();
-    map.get(&*keyname.to_ascii_lowercase()).copied()
+/// Returns the canonical name of the key, e.g. `"a"` or `"left_arrow"`.
+pub(crate) fn get_keyname(keycode: CGKeyCode) -> Option<&'static str> {
+    KEYCODES
+        .iter()
+        .find(|(code, _)| *code == keycode)
+        .map(|(_, names)| names[0])
+}
+
+/// Names which can be JS identifiers, e.g. `left_arrow` but not `[`. `Key` has them in upper case.
+pub(crate) fn js_constants() -> impl Iterator<Item = (String, CGKeyCode)> {
+    KEYCODES.iter().flat_map(|(keycode, names)| {
+        names
+            .iter()
+            .filter(|name| {
+                name.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+                    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            })
+            .map(move |name| (name.to_ascii_uppercase(), *keycode))
+    })
+}
+
+// Characters typed with shift on the US layout, and the key which types them.
+const SHIFTED_CHARS: &[(char, &str)] = &[
+    ('~', "`"),
+    ('!', "1"),
+    ('@', "2"),
+    ('#', "3"),
+    ('$', "4"),
+    ('%', "5"),
+    ('^', "6"),
+    ('&', "7"),
+    ('*', "8"),
+    ('(', "9"),
+    (')', "0"),
+    ('_', "-"),
+    ('+', "="),
+    ('{', "["),
+    ('}', "]"),
+    ('|', "\\"),
+    (':', ";"),
+    ('"', "'"),
+    ('<', ","),
+    ('>', "."),
+    ('?', "/"),
+];
+
+/// Returns the key presses which type `text` on the US layout. Fails if there's a character which
+/// isn't on the keyboard, e.g. non-ASCII ones.
+pub(crate) fn text_keystrokes(text: &str) -> anyhow::Result<Vec<(CGKeyCode, CGEventFlags)>> {
+    text.chars()
+        .map(|c| {
+            let (name, flags) = match c {
+                ' ' => ("space".to_string(), 0),
+                '\t' => ("tab".to_string(), 0),
+                '\n' => ("return".to_string(), 0),
+                'A'..='Z' => (c.to_ascii_lowercase().to_string(), kCGEventFlagMaskShift),
+                _ => match SHIFTED_CHARS.iter().find(|(shifted, _)| *shifted == c) {
+                    Some((_, key)) => (key.to_string(), kCGEventFlagMaskShift),
+                    None => (c.to_string(), 0),
+                },
+            };
+            match get_keycode(&name) {
+                Some(keycode) if c.is_ascii() => Ok((keycode, flags)),
+                _ => Err(anyhow!("Cannot type {:?} with the keyboard", c)),
+            }
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashSet;
 
     #[test]
     fn test_get_keycode() {
@@ -103,5 +92,65 @@ mod tests {
         assert_eq!(get_keycode("a"), Some(0));
         assert_eq!(get_keycode("A"), Some(0));
         assert_eq!(get_keycode("non_existing_key"), None);
+        assert_eq!(get_keycode("1"), Some(18));
+        assert_eq!(get_keycode("num1"), Some(18));
+        assert_eq!(get_keycode("keypad_1"), Some(83));
+        assert_eq!(get_keycode("jis_eisu"), Some(102));
+    }
+
+    #[test]
+    fn test_get_keyname() {
+        assert_eq!(get_keyname(0), Some("a"));
+        assert_eq!(get_keyname(36), Some("return"));
+        assert_eq!(get_keyname(126), Some("up_arrow"));
+        assert_eq!(get_keyname(52), None);
+    }
+
+    #[test]
+    fn test_table_is_consistent() {
+        let mut keycodes = HashSet::new();
+        let mut names = HashSet::new();
+        for (keycode, key_names) in KEYCODES {
+            assert!(keycodes.insert(keycode), "duplicated keycode: {}", keycode);
+            for name in *key_names {
+                assert_eq!(*name, name.to_ascii_lowercase(), "names must be lower case");
+                assert!(names.insert(name), "duplicated name: {}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_js_constants() {
+        let constants: Vec<(String, CGKeyCode)> = js_constants().collect();
+        for (name, keycode) in [
+            ("A", 0),
+            ("NUM1", 18),
+            ("DIGIT_1", 18),
+            ("SEMI_COLON", 41),
+            ("BACK_QUOTE", 50),
+            ("UP_ARROW", 126),
+            ("KEYPAD_ENTER", 76),
+        ] {
+            assert!(constants.contains(&(name.to_string(), keycode)), "{}", name);
+        }
+        assert!(!constants.iter().any(|(name, _)| name == "["));
+    }
+
+    #[test]
+    fn test_text_keystrokes() -> anyhow::Result<()> {
+        assert_eq!(
+            text_keystrokes("Hi {1}\n")?,
+            vec![
+                (4, kCGEventFlagMaskShift),
+                (34, 0),
+                (49, 0),
+                (33, kCGEventFlagMaskShift),
+                (18, 0),
+                (30, kCGEventFlagMaskShift),
+                (36, 0),
+            ]
+        );
+        assert!(text_keystrokes("こんにちは").is_err());
+        Ok(())
     }
 }
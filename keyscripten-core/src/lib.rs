@@ -1,16 +1,36 @@
 pub mod app_config;
+pub mod backend;
+// Some of the constants are only used by the macOS backend.
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
 mod cg_constants;
+pub mod check;
+pub mod clock;
 pub mod event;
+#[cfg(target_os = "macos")]
 pub mod grab;
+pub mod harness;
 mod hotkey;
+mod instrument;
 pub mod js;
 mod js_builtin;
 pub mod js_console;
 mod js_hotkey;
+mod js_job;
 mod js_keycode;
 pub mod js_operation;
+mod js_realm;
 mod keycode;
+#[cfg(target_os = "linux")]
+pub mod linux_backend;
+pub mod manifest;
+pub mod mock_backend;
 pub mod plugin;
+pub mod registry;
+pub mod replay;
+#[cfg(target_os = "macos")]
 mod send;
+pub mod storage;
+pub mod trace;
+pub mod watchdog;
 
 const APP_NAME: &str = "keyscripten";
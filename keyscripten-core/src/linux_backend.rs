@@ -0,0 +1,731 @@
+use crate::backend::InputBackend;
+use crate::cg_constants::{
+    kCGEventFlagMaskAlphaShift, kCGEventFlagMaskAlternate, kCGEventFlagMaskCommand,
+    kCGEventFlagMaskControl, kCGEventFlagMaskLeftAlternate, kCGEventFlagMaskLeftCommand,
+    kCGEventFlagMaskLeftControl, kCGEventFlagMaskLeftShift, kCGEventFlagMaskRightAlternate,
+    kCGEventFlagMaskRightCommand, kCGEventFlagMaskRightControl, kCGEventFlagMaskRightShift,
+    kCGEventFlagMaskSecondaryFn, kCGEventFlagMaskShift, kCGEventFlagsChanged, kCGEventKeyDown,
+    kCGEventKeyUp, CGEventFlags, CGEventType, CGKeyCode,
+};
+use crate::event::{event_type, Event};
+use crate::js::JS;
+use anyhow::anyhow;
+use evdev::uinput::VirtualDevice;
+use evdev::{
+    AttributeSet, Device, EventSummary, InputEvent, KeyCode, KeyEvent, MiscCode,
+    RelativeAxisCode, RelativeAxisEvent, SynchronizationCode,
+};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+// Events emitted by this device are the ones sent from this application itself. It's never grabbed,
+// so the device name works as the tag `USER_DATA_FROM_THIS_APP` is on macOS.
+const VIRTUAL_KEYBOARD_NAME: &str = "keyscripten virtual keyboard";
+
+// evdev key event values.
+const KEY_RELEASED: i32 = 0;
+const KEY_PRESSED: i32 = 1;
+const KEY_REPEATED: i32 = 2;
+
+// Mac virtual keycode <-> evdev key code. If a key appears twice, the first entry is used for the
+// injection.
+const KEYMAP: &[(CGKeyCode, KeyCode)] = &[
+    (0, KeyCode::KEY_A),
+    (1, KeyCode::KEY_S),
+    (2, KeyCode::KEY_D),
+    (3, KeyCode::KEY_F),
+    (4, KeyCode::KEY_H),
+    (5, KeyCode::KEY_G),
+    (6, KeyCode::KEY_Z),
+    (7, KeyCode::KEY_X),
+    (8, KeyCode::KEY_C),
+    (9, KeyCode::KEY_V),
+    (10, KeyCode::KEY_102ND),
+    (11, KeyCode::KEY_B),
+    (12, KeyCode::KEY_Q),
+    (13, KeyCode::KEY_W),
+    (14, KeyCode::KEY_E),
+    (15, KeyCode::KEY_R),
+    (16, KeyCode::KEY_Y),
+    (17, KeyCode::KEY_T),
+    (18, KeyCode::KEY_1),
+    (19, KeyCode::KEY_2),
+    (20, KeyCode::KEY_3),
+    (21, KeyCode::KEY_4),
+    (22, KeyCode::KEY_6),
+    (23, KeyCode::KEY_5),
+    (24, KeyCode::KEY_EQUAL),
+    (25, KeyCode::KEY_9),
+    (26, KeyCode::KEY_7),
+    (27, KeyCode::KEY_MINUS),
+    (28, KeyCode::KEY_8),
+    (29, KeyCode::KEY_0),
+    (30, KeyCode::KEY_RIGHTBRACE),
+    (31, KeyCode::KEY_O),
+    (32, KeyCode::KEY_U),
+    (33, KeyCode::KEY_LEFTBRACE),
+    (34, KeyCode::KEY_I),
+    (35, KeyCode::KEY_P),
+    (36, KeyCode::KEY_ENTER),
+    (37, KeyCode::KEY_L),
+    (38, KeyCode::KEY_J),
+    (39, KeyCode::KEY_APOSTROPHE),
+    (40, KeyCode::KEY_K),
+    (41, KeyCode::KEY_SEMICOLON),
+    (42, KeyCode::KEY_BACKSLASH),
+    (43, KeyCode::KEY_COMMA),
+    (44, KeyCode::KEY_SLASH),
+    (45, KeyCode::KEY_N),
+    (46, KeyCode::KEY_M),
+    (47, KeyCode::KEY_DOT),
+    (48, KeyCode::KEY_TAB),
+    (49, KeyCode::KEY_SPACE),
+    (50, KeyCode::KEY_GRAVE),
+    (51, KeyCode::KEY_BACKSPACE),
+    (53, KeyCode::KEY_ESC),
+    (54, KeyCode::KEY_RIGHTMETA),
+    (55, KeyCode::KEY_LEFTMETA),
+    (56, KeyCode::KEY_LEFTSHIFT),
+    (57, KeyCode::KEY_CAPSLOCK),
+    (58, KeyCode::KEY_LEFTALT),
+    (59, KeyCode::KEY_LEFTCTRL),
+    (60, KeyCode::KEY_RIGHTSHIFT),
+    (61, KeyCode::KEY_RIGHTALT),
+    (62, KeyCode::KEY_RIGHTCTRL),
+    (63, KeyCode::KEY_FN),
+    (64, KeyCode::KEY_F17),
+    (65, KeyCode::KEY_KPDOT),
+    (67, KeyCode::KEY_KPASTERISK),
+    (69, KeyCode::KEY_KPPLUS),
+    (71, KeyCode::KEY_NUMLOCK),
+    (72, KeyCode::KEY_VOLUMEUP),
+    (73, KeyCode::KEY_VOLUMEDOWN),
+    (74, KeyCode::KEY_MUTE),
+    (75, KeyCode::KEY_KPSLASH),
+    (76, KeyCode::KEY_KPENTER),
+    (78, KeyCode::KEY_KPMINUS),
+    (79, KeyCode::KEY_F18),
+    (80, KeyCode::KEY_F19),
+    (81, KeyCode::KEY_KPEQUAL),
+    (82, KeyCode::KEY_KP0),
+    (83, KeyCode::KEY_KP1),
+    (84, KeyCode::KEY_KP2),
+    (85, KeyCode::KEY_KP3),
+    (86, KeyCode::KEY_KP4),
+    (87, KeyCode::KEY_KP5),
+    (88, KeyCode::KEY_KP6),
+    (89, KeyCode::KEY_KP7),
+    (90, KeyCode::KEY_F20),
+    (91, KeyCode::KEY_KP8),
+    (92, KeyCode::KEY_KP9),
+    (93, KeyCode::KEY_YEN),
+    (94, KeyCode::KEY_RO),
+    (95, KeyCode::KEY_KPJPCOMMA),
+    (96, KeyCode::KEY_F5),
+    (97, KeyCode::KEY_F6),
+    (98, KeyCode::KEY_F7),
+    (99, KeyCode::KEY_F3),
+    (100, KeyCode::KEY_F8),
+    (101, KeyCode::KEY_F9),
+    // Eisu/Kana. Apple keyboards report them as Hanja/Hangeul on Linux.
+    (102, KeyCode::KEY_MUHENKAN),
+    (102, KeyCode::KEY_HANJA),
+    (103, KeyCode::KEY_F11),
+    (104, KeyCode::KEY_HENKAN),
+    (104, KeyCode::KEY_HANGEUL),
+    (105, KeyCode::KEY_F13),
+    (106, KeyCode::KEY_F16),
+    (107, KeyCode::KEY_F14),
+    (109, KeyCode::KEY_F10),
+    (111, KeyCode::KEY_F12),
+    (113, KeyCode::KEY_F15),
+    (114, KeyCode::KEY_INSERT),
+    (114, KeyCode::KEY_HELP),
+    (115, KeyCode::KEY_HOME),
+    (116, KeyCode::KEY_PAGEUP),
+    (117, KeyCode::KEY_DELETE),
+    (118, KeyCode::KEY_F4),
+    (119, KeyCode::KEY_END),
+    (120, KeyCode::KEY_F2),
+    (121, KeyCode::KEY_PAGEDOWN),
+    (122, KeyCode::KEY_F1),
+    (123, KeyCode::KEY_LEFT),
+    (124, KeyCode::KEY_RIGHT),
+    (125, KeyCode::KEY_DOWN),
+    (126, KeyCode::KEY_UP),
+];
+
+// CGMouseButton number -> evdev button.
+const MOUSE_BUTTONS: &[KeyCode] = &[
+    KeyCode::BTN_LEFT,
+    KeyCode::BTN_RIGHT,
+    KeyCode::BTN_MIDDLE,
+    KeyCode::BTN_SIDE,
+    KeyCode::BTN_EXTRA,
+];
+
+// Modifier flag -> keys which set the flag. The first key is used to synthesize the flag.
+const MODIFIERS: &[(CGEventFlags, &[KeyCode])] = &[
+    (
+        kCGEventFlagMaskShift,
+        &[KeyCode::KEY_LEFTSHIFT, KeyCode::KEY_RIGHTSHIFT],
+    ),
+    (
+        kCGEventFlagMaskControl,
+        &[KeyCode::KEY_LEFTCTRL, KeyCode::KEY_RIGHTCTRL],
+    ),
+    (
+        kCGEventFlagMaskAlternate,
+        &[KeyCode::KEY_LEFTALT, KeyCode::KEY_RIGHTALT],
+    ),
+    (
+        kCGEventFlagMaskCommand,
+        &[KeyCode::KEY_LEFTMETA, KeyCode::KEY_RIGHTMETA],
+    ),
+    (kCGEventFlagMaskSecondaryFn, &[KeyCode::KEY_FN]),
+];
+
+// Device dependent flags, which tell the side of the pressed modifier key.
+const DEVICE_MODIFIERS: &[(KeyCode, CGEventFlags)] = &[
+    (KeyCode::KEY_LEFTSHIFT, kCGEventFlagMaskLeftShift),
+    (KeyCode::KEY_RIGHTSHIFT, kCGEventFlagMaskRightShift),
+    (KeyCode::KEY_LEFTCTRL, kCGEventFlagMaskLeftControl),
+    (KeyCode::KEY_RIGHTCTRL, kCGEventFlagMaskRightControl),
+    (KeyCode::KEY_LEFTALT, kCGEventFlagMaskLeftAlternate),
+    (KeyCode::KEY_RIGHTALT, kCGEventFlagMaskRightAlternate),
+    (KeyCode::KEY_LEFTMETA, kCGEventFlagMaskLeftCommand),
+    (KeyCode::KEY_RIGHTMETA, kCGEventFlagMaskRightCommand),
+];
+
+pub fn to_mac_keycode(key: KeyCode) -> Option<CGKeyCode> {
+    KEYMAP
+        .iter()
+        .find(|(_, linux)| *linux == key)
+        .map(|(mac, _)| *mac)
+}
+
+pub fn to_linux_keycode(keycode: CGKeyCode) -> Option<KeyCode> {
+    KEYMAP
+        .iter()
+        .find(|(mac, _)| *mac == keycode)
+        .map(|(_, linux)| *linux)
+}
+
+fn is_modifier(key: KeyCode) -> bool {
+    key == KeyCode::KEY_CAPSLOCK || MODIFIERS.iter().any(|(_, keys)| keys.contains(&key))
+}
+
+/// Pressed modifier keys, translated to `CGEventFlags`.
+#[derive(Default)]
+struct ModifierState {
+    pressed: HashSet<KeyCode>,
+    caps_lock: bool,
+}
+
+impl ModifierState {
+    fn update(&mut self, key: KeyCode, value: i32) {
+        if key == KeyCode::KEY_CAPSLOCK {
+            if value == KEY_PRESSED {
+                self.caps_lock = !self.caps_lock;
+            }
+        } else if value == KEY_RELEASED {
+            self.pressed.remove(&key);
+        } else {
+            self.pressed.insert(key);
+        }
+    }
+
+    fn flags(&self) -> CGEventFlags {
+        let mut flags = 0;
+        for (flag, keys) in MODIFIERS {
+            if keys.iter().any(|key| self.pressed.contains(key)) {
+                flags |= flag;
+            }
+        }
+        for (key, flag) in DEVICE_MODIFIERS {
+            if self.pressed.contains(key) {
+                flags |= flag;
+            }
+        }
+        if self.caps_lock {
+            flags |= kCGEventFlagMaskAlphaShift;
+        }
+        flags
+    }
+}
+
+struct VirtualKeyboard {
+    device: VirtualDevice,
+    // modifiers currently held down on the virtual device.
+    modifiers: ModifierState,
+    // modifiers held down on the grabbed keyboards. The virtual device returns to them after an
+    // injected event.
+    physical: ModifierState,
+    // Pointer and scan code events of the grabbed devices, emitted together at their SYN_REPORT.
+    passthrough: Vec<InputEvent>,
+}
+
+impl VirtualKeyboard {
+    fn new() -> anyhow::Result<Self> {
+        let mut keys = AttributeSet::<KeyCode>::new();
+        for code in 1..256 {
+            keys.insert(KeyCode::new(code));
+        }
+        for (_, key) in KEYMAP {
+            keys.insert(*key);
+        }
+        // Buttons and wheels for sendMouseEvent/sendScroll. REL_X/REL_Y make the device a pointer.
+        // The other axes and MSC_SCAN are for the events of grabbed keyboards with a pointer.
+        for button in MOUSE_BUTTONS {
+            keys.insert(*button);
+        }
+        let mut axes = AttributeSet::<RelativeAxisCode>::new();
+        for code in 0..=RelativeAxisCode::REL_HWHEEL_HI_RES.0 {
+            if code != RelativeAxisCode::REL_RESERVED.0 {
+                axes.insert(RelativeAxisCode(code));
+            }
+        }
+        let mut misc = AttributeSet::<MiscCode>::new();
+        misc.insert(MiscCode::MSC_SCAN);
+        let device = VirtualDevice::builder()
+            .map_err(|err| anyhow!("Cannot open /dev/uinput: {:?}", err))?
+            .name(VIRTUAL_KEYBOARD_NAME)
+            .with_keys(&keys)?
+            .with_relative_axes(&axes)?
+            .with_msc(&misc)?
+            .build()
+            .map_err(|err| anyhow!("Cannot create virtual keyboard: {:?}", err))?;
+        Ok(VirtualKeyboard {
+            device,
+            modifiers: ModifierState::default(),
+            physical: ModifierState::default(),
+            passthrough: Vec::new(),
+        })
+    }
+
+    fn emit(&mut self, key: KeyCode, value: i32) -> anyhow::Result<()> {
+        if is_modifier(key) {
+            self.modifiers.update(key, value);
+        }
+        self.device
+            .emit(&[*KeyEvent::new(key, value)])
+            .map_err(|err| anyhow!("Cannot emit {:?}: {:?}", key, err))
+    }
+
+    fn scroll(&mut self, delta_x: i64, delta_y: i64) -> anyhow::Result<()> {
+        let mut events = Vec::new();
+        if delta_y != 0 {
+            events.push(*RelativeAxisEvent::new(RelativeAxisCode::REL_WHEEL, delta_y as i32));
+        }
+        if delta_x != 0 {
+            events.push(*RelativeAxisEvent::new(RelativeAxisCode::REL_HWHEEL, delta_x as i32));
+        }
+        self.device
+            .emit(&events)
+            .map_err(|err| anyhow!("Cannot emit scroll: {:?}", err))
+    }
+
+    fn flush_passthrough(&mut self) -> anyhow::Result<()> {
+        if self.passthrough.is_empty() {
+            return Ok(());
+        }
+        let events = std::mem::take(&mut self.passthrough);
+        self.device
+            .emit(&events)
+            .map_err(|err| anyhow!("Cannot emit {:?}: {:?}", events, err))
+    }
+
+    // Emits the event rewritten by a plugin in place of the captured key. The modifiers are set to
+    // the event's flags while emitting it, and then restored to the physical ones.
+    fn emit_rewritten(&mut self, event: &Event) -> anyhow::Result<()> {
+        if event.event_type == event_type(kCGEventFlagsChanged) {
+            return self.set_flags(event.flags);
+        }
+
+        let keycode = event.keycode as CGKeyCode;
+        let key = to_linux_keycode(keycode)
+            .ok_or_else(|| anyhow!("Cannot map keycode {} to a Linux key", keycode))?;
+        let value = if event.event_type == event_type(kCGEventKeyUp) {
+            KEY_RELEASED
+        } else if event.autorepeat {
+            KEY_REPEATED
+        } else {
+            KEY_PRESSED
+        };
+        self.emit_with_flags(event.flags, |keyboard| keyboard.emit(key, value))
+    }
+
+    // Emits with the modifiers set to `flags`, and restores the physical ones.
+    fn emit_with_flags(
+        &mut self,
+        flags: CGEventFlags,
+        emit: impl FnOnce(&mut Self) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        self.set_flags(flags)?;
+        emit(self)?;
+        self.set_flags(self.physical.flags())
+    }
+
+    // Presses or releases the modifier keys so that the virtual device has exactly `flags`.
+    fn set_flags(&mut self, flags: CGEventFlags) -> anyhow::Result<()> {
+        for (flag, keys) in MODIFIERS {
+            let pressed: Vec<KeyCode> = keys
+                .iter()
+                .filter(|key| self.modifiers.pressed.contains(key))
+                .cloned()
+                .collect();
+            if flags & flag != 0 {
+                if pressed.is_empty() {
+                    self.emit(keys[0], KEY_PRESSED)?;
+                }
+            } else {
+                for key in pressed {
+                    self.emit(key, KEY_RELEASED)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+thread_local! {
+    // `JS` isn't `Send`. `grab_setup` and `grab_run` must be called on the same thread, as
+    // the macOS backend attaches the tap to the run loop of the current thread.
+    static GRABBED_JS: RefCell<Option<JS>> = const { RefCell::new(None) };
+}
+
+// Wakes up the event loop of `grab_run`.
+enum LoopInput {
+    Event(InputEvent),
+    JsOperation,
+}
+
+/// The Linux backend. It grabs every keyboard under `/dev/input` exclusively, and re-emits the
+/// events through a uinput virtual keyboard.
+pub struct EvdevBackend {
+    virtual_keyboard: Mutex<VirtualKeyboard>,
+    receiver: Mutex<Option<Receiver<LoopInput>>>,
+}
+
+impl EvdevBackend {
+    pub fn new() -> anyhow::Result<Self> {
+        Ok(EvdevBackend {
+            virtual_keyboard: Mutex::new(VirtualKeyboard::new()?),
+            receiver: Mutex::new(None),
+        })
+    }
+
+    // Translates the captured event to `Event`, and passes it to JS. The event is re-emitted unless
+    // JS swallowed it.
+    fn dispatch(&self, js: &mut JS, event: InputEvent) -> anyhow::Result<()> {
+        let (key, value) = match event.destructure() {
+            EventSummary::Key(_, key, value) => (key, value),
+            // The pointer of a keyboard with a touchpad or a trackpoint.
+            EventSummary::RelativeAxis(..) | EventSummary::Misc(..) => {
+                self.virtual_keyboard.lock().unwrap().passthrough.push(event);
+                return Ok(());
+            }
+            EventSummary::Synchronization(_, SynchronizationCode::SYN_REPORT, _) => {
+                return self.virtual_keyboard.lock().unwrap().flush_passthrough();
+            }
+            _ => return Ok(()),
+        };
+
+        let Some(keycode) = to_mac_keycode(key) else {
+            // JS can't describe the key. Pass it through as is.
+            return self.virtual_keyboard.lock().unwrap().emit(key, value);
+        };
+
+        let event_type = if is_modifier(key) {
+            // Modifier keys are reported as flagsChanged, the same as CoreGraphics does.
+            let mut virtual_keyboard = self.virtual_keyboard.lock().unwrap();
+            virtual_keyboard.physical.update(key, value);
+            if value == KEY_REPEATED {
+                // Repeats only a modifier which JS passed on.
+                if virtual_keyboard.modifiers.pressed.contains(&key) {
+                    virtual_keyboard.emit(key, value)?;
+                }
+                return Ok(());
+            }
+            kCGEventFlagsChanged
+        } else if value == KEY_RELEASED {
+            kCGEventKeyUp
+        } else {
+            kCGEventKeyDown
+        };
+
+        let flags = self.virtual_keyboard.lock().unwrap().physical.flags();
+        let event =
+            Event::new(event_type, keycode as i64, flags).with_autorepeat(value == KEY_REPEATED);
+        let delivered = match js.send_event(event.clone()) {
+            Ok(delivered) => delivered,
+            Err(err) => {
+                log::error!("Cannot call JS callback: {:?}", err);
+                Some(event.clone())
+            }
+        };
+        let mut virtual_keyboard = self.virtual_keyboard.lock().unwrap();
+        match delivered {
+            Some(delivered) if delivered.same_input(&event) => virtual_keyboard.emit(key, value)?,
+            Some(delivered) => virtual_keyboard.emit_rewritten(&delivered)?,
+            None => log::debug!("Don't send keyboard event to the destination."),
+        }
+        Ok(())
+    }
+}
+
+fn is_keyboard(device: &Device) -> bool {
+    if device.name() == Some(VIRTUAL_KEYBOARD_NAME) {
+        return false;
+    }
+    match device.supported_keys() {
+        Some(keys) => keys.contains(KeyCode::KEY_A) && keys.contains(KeyCode::KEY_ENTER),
+        None => false,
+    }
+}
+
+impl InputBackend for EvdevBackend {
+    fn grab_setup(&self, js: JS) -> anyhow::Result<()> {
+        let (tx, rx) = channel::<LoopInput>();
+
+        let mut grabbed = 0;
+        for (path, mut device) in evdev::enumerate() {
+            if !is_keyboard(&device) {
+                continue;
+            }
+            // The virtual device can't reproduce an absolute pointer, e.g. a touchpad.
+            if device.supported_absolute_axes().is_some() {
+                log::info!("Skipped {:?}({:?}): it has absolute axes", path, device.name());
+                continue;
+            }
+            if let Err(err) = device.grab() {
+                log::error!("Cannot grab {:?}: {:?}", path, err);
+                continue;
+            }
+            log::info!("Grabbed {:?}({:?})", path, device.name());
+            grabbed += 1;
+
+            let tx = tx.clone();
+            thread::spawn(move || loop {
+                match device.fetch_events() {
+                    Ok(events) => {
+                        for event in events {
+                            if tx.send(LoopInput::Event(event)).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Err(err) => {
+                        log::error!("Cannot read events from {:?}: {:?}", path, err);
+                        return;
+                    }
+                }
+            });
+        }
+        if grabbed == 0 {
+            return Err(anyhow!(
+                "There's no keyboard to grab. Does this user have read permission on /dev/input/event*?"
+            ));
+        }
+
+        let wake_tx = Mutex::new(tx);
+        js.set_js_operation_waker(move || {
+            let _ = wake_tx.lock().unwrap().send(LoopInput::JsOperation);
+        });
+        *self.receiver.lock().unwrap() = Some(rx);
+        GRABBED_JS.with(|slot| *slot.borrow_mut() = Some(js));
+        Ok(())
+    }
+
+    fn grab_run(&self) {
+        let Some(rx) = self.receiver.lock().unwrap().take() else {
+            log::error!("grab_run is called before grab_setup");
+            return;
+        };
+        let Some(mut js) = GRABBED_JS.with(|slot| slot.borrow_mut().take()) else {
+            log::error!("grab_run must be called on the thread which called grab_setup");
+            return;
+        };
+
+        log::info!("Running evdev event loop");
+        // Operations may have been sent while loading the plugins.
+        js.process_js_operations();
+        loop {
+            // Runs the due JS timers, and waits for the next event until the next timer.
+            let next_timer = match js.run_timers() {
+                Ok(delay) => delay,
+                Err(err) => {
+                    log::error!("Cannot run JS timers: {:?}", err);
+                    None
+                }
+            };
+            let event = match next_timer {
+                Some(delay) => match rx.recv_timeout(Duration::from_millis(delay)) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                },
+                None => match rx.recv() {
+                    Ok(event) => event,
+                    Err(_) => break,
+                },
+            };
+            match event {
+                LoopInput::Event(event) => {
+                    if let Err(err) = self.dispatch(&mut js, event) {
+                        log::error!("Cannot dispatch event: {:?}", err);
+                    }
+                }
+                LoopInput::JsOperation => js.process_js_operations(),
+            }
+        }
+    }
+
+    fn send_keyboard_event(
+        &self,
+        keycode: CGKeyCode,
+        flags: CGEventFlags,
+        keydown: bool,
+    ) -> anyhow::Result<()> {
+        let key = to_linux_keycode(keycode)
+            .ok_or_else(|| anyhow!("Unknown keycode for evdev: {}", keycode))?;
+
+        log::debug!("Sending keyboard event: {:?}", key);
+        let value = if keydown { KEY_PRESSED } else { KEY_RELEASED };
+        self.virtual_keyboard
+            .lock()
+            .unwrap()
+            .emit_with_flags(flags, |keyboard| keyboard.emit(key, value))
+    }
+
+    fn send_flags_changed_event(&self, flags: CGEventFlags) -> anyhow::Result<()> {
+        self.virtual_keyboard.lock().unwrap().set_flags(flags)
+    }
+
+    // evdev pointers are relative, so the cursor can't be moved to an absolute position.
+    fn send_mouse_event(
+        &self,
+        cg_event_type: CGEventType,
+        button: i64,
+        position: Option<(f64, f64)>,
+        flags: CGEventFlags,
+    ) -> anyhow::Result<()> {
+        if position.is_some() {
+            return Err(anyhow!(
+                "The Linux backend can't move the cursor to an absolute position"
+            ));
+        }
+        let value = match event_type(cg_event_type) {
+            "leftMouseDown" | "rightMouseDown" | "otherMouseDown" => KEY_PRESSED,
+            "leftMouseUp" | "rightMouseUp" | "otherMouseUp" => KEY_RELEASED,
+            // Moving to the current position is a no-op.
+            _ => return Ok(()),
+        };
+        let key = usize::try_from(button)
+            .ok()
+            .and_then(|button| MOUSE_BUTTONS.get(button))
+            .ok_or_else(|| anyhow!("Unknown mouse button for evdev: {}", button))?;
+
+        log::debug!("Sending mouse event: {:?}", key);
+        self.virtual_keyboard
+            .lock()
+            .unwrap()
+            .emit_with_flags(flags, |keyboard| keyboard.emit(*key, value))
+    }
+
+    fn send_scroll_event(
+        &self,
+        delta_x: i64,
+        delta_y: i64,
+        flags: CGEventFlags,
+    ) -> anyhow::Result<()> {
+        self.virtual_keyboard
+            .lock()
+            .unwrap()
+            .emit_with_flags(flags, |keyboard| keyboard.scroll(delta_x, delta_y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keycode_translation() {
+        assert_eq!(to_mac_keycode(KeyCode::KEY_A), Some(0));
+        assert_eq!(to_mac_keycode(KeyCode::KEY_T), Some(17));
+        assert_eq!(to_mac_keycode(KeyCode::KEY_HANJA), Some(102));
+        assert_eq!(to_mac_keycode(KeyCode::KEY_PRINT), None);
+
+        assert_eq!(to_linux_keycode(0), Some(KeyCode::KEY_A));
+        assert_eq!(to_linux_keycode(102), Some(KeyCode::KEY_MUHENKAN));
+        assert_eq!(to_linux_keycode(52), None);
+    }
+
+    #[test]
+    fn test_modifier_state() {
+        let mut state = ModifierState::default();
+        state.update(KeyCode::KEY_RIGHTCTRL, KEY_PRESSED);
+        state.update(KeyCode::KEY_LEFTSHIFT, KEY_PRESSED);
+        assert_eq!(
+            state.flags(),
+            kCGEventFlagMaskControl
+                | kCGEventFlagMaskRightControl
+                | kCGEventFlagMaskShift
+                | kCGEventFlagMaskLeftShift
+        );
+
+        state.update(KeyCode::KEY_LEFTSHIFT, KEY_RELEASED);
+        state.update(KeyCode::KEY_CAPSLOCK, KEY_PRESSED);
+        state.update(KeyCode::KEY_CAPSLOCK, KEY_RELEASED);
+        assert_eq!(
+            state.flags(),
+            kCGEventFlagMaskControl | kCGEventFlagMaskRightControl | kCGEventFlagMaskAlphaShift
+        );
+    }
+
+    // Needs write permission on /dev/uinput: `cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_send_keyboard_event() -> anyhow::Result<()> {
+        let backend = EvdevBackend::new()?;
+        let path = backend
+            .virtual_keyboard
+            .lock()
+            .unwrap()
+            .device
+            .enumerate_dev_nodes_blocking()?
+            .next()
+            .ok_or_else(|| anyhow!("no device node"))??;
+        let mut device = Device::open(path)?;
+
+        // The physical modifiers, none here, are restored after each event.
+        backend.send_keyboard_event(0, kCGEventFlagMaskControl, true)?;
+        backend.send_keyboard_event(0, kCGEventFlagMaskControl, false)?;
+
+        let mut keys = Vec::new();
+        while keys.len() < 6 {
+            for event in device.fetch_events()? {
+                if let EventSummary::Key(_, key, value) = event.destructure() {
+                    keys.push((key, value));
+                }
+            }
+        }
+        assert_eq!(
+            keys,
+            vec![
+                (KeyCode::KEY_LEFTCTRL, KEY_PRESSED),
+                (KeyCode::KEY_A, KEY_PRESSED),
+                (KeyCode::KEY_LEFTCTRL, KEY_RELEASED),
+                (KeyCode::KEY_LEFTCTRL, KEY_PRESSED),
+                (KeyCode::KEY_A, KEY_RELEASED),
+                (KeyCode::KEY_LEFTCTRL, KEY_RELEASED),
+            ]
+        );
+        Ok(())
+    }
+}
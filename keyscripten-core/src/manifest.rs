@@ -0,0 +1,291 @@
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+/// Version of this crate. The application passes its own version to `JS::set_app_version`.
+pub const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Permissions a plugin with a manifest must declare, and the globals each of them grants.
+/// Plugins without a manifest get all of them.
+pub const PERMISSIONS: &[(&str, &[&str])] = &[
+    (
+        "sendEvents",
+        &[
+            "sendFlagsChangedEvent",
+            "sendKeyboardEvent",
+            "sendMouseEvent",
+            "sendScroll",
+            "sendText",
+            "sendKeys",
+        ],
+    ),
+    ("storage", &["storage"]),
+];
+
+const HEADER_START: &str = "==KeyScripten==";
+const HEADER_END: &str = "==/KeyScripten==";
+
+/// Metadata of a plugin file, declared without running it.
+///
+/// It's either a header comment block at the top of the file:
+///
+/// ```javascript
+/// // ==KeyScripten==
+/// // @id example.hello
+/// // @name Hello
+/// // @version 1.0.0
+/// // @author Jane Doe
+/// // @minAppVersion 0.1.0
+/// // @permission sendEvents
+/// // @mouseMotion
+/// // ==/KeyScripten==
+/// ```
+///
+/// or a sidecar JSON file next to it, e.g. `hello.json` for `hello.js`, with the same keys, a
+/// `permissions` array and `"mouseMotion": true`. The sidecar takes precedence.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct PluginManifest {
+    pub id: String,
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub author: Option<String>,
+    pub min_app_version: Option<String>,
+    #[serde(default)]
+    pub permissions: Vec<String>,
+    /// Whether the plugin receives mouseMoved and the drag events. The others never see them, and
+    /// the backend doesn't listen to them while no plugin asks for them.
+    #[serde(default)]
+    pub mouse_motion: bool,
+}
+
+impl PluginManifest {
+    /// Parses the header comment block. Returns None if the file doesn't start with one.
+    pub fn from_header(src: &str) -> anyhow::Result<Option<PluginManifest>> {
+        let mut lines = src
+            .lines()
+            .map(str::trim)
+            .skip_while(|line| line.is_empty());
+        match lines.next().and_then(comment_body) {
+            Some(HEADER_START) => {}
+            _ => return Ok(None),
+        }
+
+        let mut manifest = PluginManifest::default();
+        let mut id = None;
+        for line in lines {
+            let body = comment_body(line)
+                .ok_or_else(|| anyhow!("Manifest isn't closed by `// {}`", HEADER_END))?;
+            if body == HEADER_END {
+                manifest.id = id.ok_or_else(|| anyhow!("Manifest doesn't have @id"))?;
+                manifest.validate()?;
+                return Ok(Some(manifest));
+            }
+            if body.is_empty() {
+                continue;
+            }
+            let (key, value) = body
+                .strip_prefix('@')
+                .map(|rest| rest.split_once(char::is_whitespace).unwrap_or((rest, "")))
+                .ok_or_else(|| anyhow!("Invalid manifest line: {}", line))?;
+            let value = value.trim().to_string();
+            match key {
+                "id" => id = Some(value),
+                "name" => manifest.name = Some(value),
+                "version" => manifest.version = Some(value),
+                "author" => manifest.author = Some(value),
+                "minAppVersion" => manifest.min_app_version = Some(value),
+                "permission" => manifest
+                    .permissions
+                    .extend(value.split_whitespace().map(str::to_string)),
+                "mouseMotion" if value.is_empty() => manifest.mouse_motion = true,
+                _ => return Err(anyhow!("Unknown manifest key: @{}", key)),
+            }
+        }
+        Err(anyhow!("Manifest isn't closed by `// {}`", HEADER_END))
+    }
+
+    /// The `@id` of the header comment block, even if the rest of the header is invalid.
+    pub fn declared_id(src: &str) -> Option<String> {
+        let mut lines = src
+            .lines()
+            .map(str::trim)
+            .skip_while(|line| line.is_empty());
+        if lines.next().and_then(comment_body) != Some(HEADER_START) {
+            return None;
+        }
+        lines
+            .map_while(comment_body)
+            .take_while(|body| *body != HEADER_END)
+            .find_map(|body| match body.strip_prefix('@')?.split_once(char::is_whitespace)? {
+                ("id", id) => Some(id.trim().to_string()),
+                _ => None,
+            })
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<PluginManifest> {
+        let manifest: PluginManifest = serde_json::from_str(json)?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.id.is_empty() {
+            return Err(anyhow!("Manifest id is empty"));
+        }
+        for permission in &self.permissions {
+            if !PERMISSIONS.iter().any(|(name, _)| name == permission) {
+                return Err(anyhow!("Unknown permission: {}", permission));
+            }
+        }
+        if let Some(version) = &self.min_app_version {
+            parse_version(version)?;
+        }
+        Ok(())
+    }
+
+    /// Fails if the plugin requires a newer application than `app_version`. Development builds
+    /// (0.0.0) run every plugin.
+    pub fn check_app_version(&self, app_version: &str) -> anyhow::Result<()> {
+        let Some(min_app_version) = &self.min_app_version else {
+            return Ok(());
+        };
+        let current = parse_version(app_version)?;
+        if current == [0, 0, 0] || current >= parse_version(min_app_version)? {
+            return Ok(());
+        }
+        Err(anyhow!(
+            "{} requires KeyScripten {} or later. This is {}",
+            self.id,
+            min_app_version,
+            app_version
+        ))
+    }
+
+    /// The globals which the plugin isn't allowed to use, and the permission each of them needs.
+    pub fn denied_globals(&self) -> Vec<(&'static str, &'static str)> {
+        PERMISSIONS
+            .iter()
+            .filter(|(permission, _)| !self.permissions.iter().any(|p| p == permission))
+            .flat_map(|(permission, names)| names.iter().map(move |name| (*name, *permission)))
+            .collect()
+    }
+}
+
+fn comment_body(line: &str) -> Option<&str> {
+    line.strip_prefix("//").map(str::trim)
+}
+
+// "1.2" is the same as "1.2.0". Pre-release suffixes aren't supported.
+pub(crate) fn parse_version(version: &str) -> anyhow::Result<[u64; 3]> {
+    let mut result = [0; 3];
+    let parts: Vec<&str> = version.split('.').collect();
+    if parts.len() > 3 {
+        return Err(anyhow!("Invalid version: {}", version));
+    }
+    for (i, part) in parts.iter().enumerate() {
+        result[i] = part
+            .parse()
+            .map_err(|err| anyhow!("Invalid version: {}: {:?}", version, err))?;
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_header() -> anyhow::Result<()> {
+        let src = r#"
+            // ==KeyScripten==
+            // @id example.hello
+            // @name Hello world
+            // @version 1.0.0
+            // @author Jane Doe
+            // @minAppVersion 0.1
+            // @permission sendEvents
+            // @permission storage
+            // @mouseMotion
+            // ==/KeyScripten==
+            registerPlugin("example.hello", "Hello world", "", () => true, []);
+        "#;
+        let manifest = PluginManifest::from_header(src)?.unwrap();
+        assert_eq!(manifest.id, "example.hello");
+        assert_eq!(manifest.name.as_deref(), Some("Hello world"));
+        assert_eq!(manifest.author.as_deref(), Some("Jane Doe"));
+        assert_eq!(manifest.permissions, vec!["sendEvents", "storage"]);
+        assert!(manifest.denied_globals().is_empty());
+        assert!(manifest.mouse_motion);
+
+        assert_eq!(PluginManifest::from_header("registerPlugin();")?, None);
+        assert_eq!(PluginManifest::from_header("// hello\n")?, None);
+        for src in [
+            "// ==KeyScripten==\n// @name x\n// ==/KeyScripten==",
+            "// ==KeyScripten==\n// @id x\nregisterPlugin();",
+            "// ==KeyScripten==\n// @id x\n// @color red\n// ==/KeyScripten==",
+            "// ==KeyScripten==\n// @id x\n// @permission network\n// ==/KeyScripten==",
+            "// ==KeyScripten==\n// @id x\n// @minAppVersion 1.x\n// ==/KeyScripten==",
+            "// ==KeyScripten==\n// @id x\n// @mouseMotion yes\n// ==/KeyScripten==",
+        ] {
+            assert!(PluginManifest::from_header(src).is_err(), "{}", src);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_declared_id() {
+        let src = "// ==KeyScripten==\n// @id example.broken\n// @color red\n// ==/KeyScripten==";
+        assert!(PluginManifest::from_header(src).is_err());
+        assert_eq!(
+            PluginManifest::declared_id(src).as_deref(),
+            Some("example.broken")
+        );
+        assert_eq!(
+            PluginManifest::declared_id("// ==KeyScripten==\n// @id x\nregisterPlugin();")
+                .as_deref(),
+            Some("x")
+        );
+        for src in [
+            "// @id x\n",
+            "// ==KeyScripten==\n// @name x\n// ==/KeyScripten==",
+            "// ==KeyScripten==\n// @idx y\n// ==/KeyScripten==",
+            "// ==KeyScripten==\n// ==/KeyScripten==\n// @id x",
+        ] {
+            assert_eq!(PluginManifest::declared_id(src), None, "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_from_json() -> anyhow::Result<()> {
+        let manifest = PluginManifest::from_json(
+            r#"{"id": "example.hello", "version": "1.0.0", "permissions": ["storage"]}"#,
+        )?;
+        assert_eq!(manifest.version.as_deref(), Some("1.0.0"));
+        assert!(!manifest.mouse_motion);
+        assert!(manifest
+            .denied_globals()
+            .contains(&("sendKeys", "sendEvents")));
+        assert!(!manifest
+            .denied_globals()
+            .contains(&("storage", "storage")));
+        assert!(PluginManifest::from_json(r#"{"id": "x", "colour": "red"}"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_app_version() -> anyhow::Result<()> {
+        assert_eq!(parse_version("1.2")?, [1, 2, 0]);
+        assert!(parse_version("1.2.3.4").is_err());
+        assert!(parse_version("1.2.3")? < parse_version("1.10")?);
+
+        let manifest = PluginManifest {
+            id: "x".to_string(),
+            min_app_version: Some("999.0.0".to_string()),
+            ..Default::default()
+        };
+        assert!(manifest.check_app_version("0.6.0").is_err());
+        manifest.check_app_version("999.0")?;
+        manifest.check_app_version("0.0.0")?;
+        Ok(())
+    }
+}
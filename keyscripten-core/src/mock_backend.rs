@@ -0,0 +1,109 @@
+use crate::backend::InputBackend;
+use crate::cg_constants::{
+    kCGEventFlagsChanged, kCGEventKeyDown, kCGEventKeyUp, kCGEventScrollWheel, CGEventFlags,
+    CGEventType, CGKeyCode,
+};
+use crate::event::{Event, Mouse};
+use crate::js::JS;
+use anyhow::anyhow;
+use std::sync::Mutex;
+
+/// In-memory backend. It doesn't capture anything, and it records every injected event instead of
+/// posting it to the OS.
+#[derive(Default)]
+pub struct MockBackend {
+    injected: Mutex<Vec<Event>>,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        MockBackend {
+            injected: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the events injected so far.
+    pub fn injected(&self) -> Vec<Event> {
+        self.injected.lock().unwrap().clone()
+    }
+
+    pub fn clear(&self) {
+        self.injected.lock().unwrap().clear();
+    }
+
+    fn record(&self, event: Event) {
+        log::debug!("MockBackend: injected {:?}", event);
+        self.injected.lock().unwrap().push(event);
+    }
+}
+
+impl InputBackend for MockBackend {
+    fn grab_setup(&self, _js: JS) -> anyhow::Result<()> {
+        Err(anyhow!(
+            "MockBackend can't capture events. Feed events through `Harness` instead."
+        ))
+    }
+
+    fn grab_run(&self) {}
+
+    fn send_keyboard_event(
+        &self,
+        keycode: CGKeyCode,
+        flags: CGEventFlags,
+        keydown: bool,
+    ) -> anyhow::Result<()> {
+        let event_type = if keydown {
+            kCGEventKeyDown
+        } else {
+            kCGEventKeyUp
+        };
+        self.record(Event::new(event_type, keycode as i64, flags));
+        Ok(())
+    }
+
+    fn send_flags_changed_event(&self, flags: CGEventFlags) -> anyhow::Result<()> {
+        self.record(Event::new(kCGEventFlagsChanged, 0, flags));
+        Ok(())
+    }
+
+    // Records the text as is, like the macOS backend which attaches it to a key press.
+    fn send_text(&self, text: &str) -> anyhow::Result<()> {
+        self.record(Event::new(kCGEventKeyDown, 0, 0).with_text(text));
+        self.record(Event::new(kCGEventKeyUp, 0, 0).with_text(text));
+        Ok(())
+    }
+
+    // There's no cursor. Events without `position` are recorded at (0, 0).
+    fn send_mouse_event(
+        &self,
+        cg_event_type: CGEventType,
+        button: i64,
+        position: Option<(f64, f64)>,
+        flags: CGEventFlags,
+    ) -> anyhow::Result<()> {
+        let (x, y) = position.unwrap_or_default();
+        let mouse = Mouse {
+            x,
+            y,
+            button,
+            ..Mouse::default()
+        };
+        self.record(Event::new_mouse(cg_event_type, mouse, flags));
+        Ok(())
+    }
+
+    fn send_scroll_event(
+        &self,
+        delta_x: i64,
+        delta_y: i64,
+        flags: CGEventFlags,
+    ) -> anyhow::Result<()> {
+        let mouse = Mouse {
+            delta_x,
+            delta_y,
+            ..Mouse::default()
+        };
+        self.record(Event::new_mouse(kCGEventScrollWheel, mouse, flags));
+        Ok(())
+    }
+}
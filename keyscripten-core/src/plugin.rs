@@ -1,3 +1,4 @@
+use crate::manifest::PluginManifest;
 use crate::APP_NAME;
 use anyhow::anyhow;
 use serde_json::json;
@@ -8,6 +9,10 @@ use std::path::Path;
 pub struct PluginSnippet {
     pub filename: String,
     pub src: String,
+    pub manifest: Option<PluginManifest>,
+    // Why the manifest can't be read. The code is still returned, so that it can be fixed in the
+    // editor, but it must not run without its manifest.
+    pub manifest_error: Option<String>,
 }
 
 pub struct Plugins {
@@ -51,14 +56,22 @@ impl Plugins {
             }
         }
 
+        // `fs::read_dir` returns entries in arbitrary order, depending on the filesystem. Plugins
+        // with the same priority are dispatched in the loading order.
+        filenames.sort();
+
         Ok(filenames)
     }
 
     pub fn add(&self, plugin_id: String, name: String, description: String) -> anyhow::Result<()> {
+        // Header values end at the line break.
+        let single_line = |s: &str| s.replace(['\r', '\n'], " ");
         let content = include_str!("../resources/plugin-template.js")
-            .replace("/*ID*/{}", &json!(plugin_id).to_string())
-            .replace("/*NAME*/{}", &json!(name).to_string())
-            .replace("/*DESC*/{}", &json!(description).to_string());
+            .replace("/*ID_TEXT*/", &single_line(&plugin_id))
+            .replace("/*NAME_TEXT*/", &single_line(&name))
+            .replace("/*ID*/ {}", &json!(plugin_id).to_string())
+            .replace("/*NAME*/ {}", &json!(name).to_string())
+            .replace("/*DESC*/ {}", &json!(description).to_string());
         self.write(format!("{}.js", plugin_id), content)
     }
 
@@ -89,7 +102,50 @@ impl Plugins {
         let pluginpath = plugins.join(filename.clone());
         log::info!("Reading plugin: {:?}", pluginpath);
         let src = fs::read_to_string(pluginpath.as_path())?;
-        Ok(PluginSnippet { filename, src })
+        let (manifest, manifest_error) = match self.read_manifest(&filename, &src) {
+            Ok(manifest) => (manifest, None),
+            Err(err) => (None, Some(format!("{}", err))),
+        };
+        Ok(PluginSnippet {
+            filename,
+            src,
+            manifest,
+            manifest_error,
+        })
+    }
+
+    // The sidecar JSON takes precedence over the header comment block.
+    fn read_manifest(&self, filename: &str, src: &str) -> anyhow::Result<Option<PluginManifest>> {
+        let header = PluginManifest::from_header(src)
+            .map_err(|err| anyhow!("Invalid manifest in {}: {:?}", filename, err))?;
+        let sidecar = Path::new(&self.basedir).join(Self::sidecar_filename(filename));
+        if !sidecar.exists() {
+            return Ok(header);
+        }
+        if header.is_some() {
+            log::warn!("{} has both a header manifest and {:?}", filename, sidecar);
+        }
+        let json = fs::read_to_string(&sidecar)?;
+        PluginManifest::from_json(&json)
+            .map(Some)
+            .map_err(|err| anyhow!("Invalid manifest in {:?}: {:?}", sidecar, err))
+    }
+
+    fn sidecar_filename(filename: &str) -> String {
+        format!("{}.json", filename.strip_suffix(".js").unwrap_or(filename))
+    }
+
+    /// Finds the file declaring `plugin_id` in its manifest, without running any plugin.
+    pub fn find_by_plugin_id(&self, plugin_id: &str) -> anyhow::Result<Option<String>> {
+        for filename in self.list()? {
+            let src = fs::read_to_string(Path::new(&self.basedir).join(&filename))?;
+            match self.read_manifest(&filename, &src) {
+                Ok(Some(manifest)) if manifest.id == plugin_id => return Ok(Some(filename)),
+                Ok(_) => {}
+                Err(err) => log::warn!("{:?}", err),
+            }
+        }
+        Ok(None)
     }
 
     pub fn read_user_scripts(&self) -> anyhow::Result<Vec<PluginSnippet>> {
@@ -118,11 +174,17 @@ impl Plugins {
             return Err(anyhow!("Cannot instantiate Path object: {:?}", filename));
         }
 
+        let sidecar = plugins.join(Self::sidecar_filename(&filename));
         let pluginpath = plugins.join(filename);
         let pluginpath = pluginpath.as_path();
         log::info!("Deleting plugin: {:?}", pluginpath);
         fs::remove_file(pluginpath)
-            .map_err(|err| anyhow!("Cannot remove file({:?}): {:?}", pluginpath, err))
+            .map_err(|err| anyhow!("Cannot remove file({:?}): {:?}", pluginpath, err))?;
+        if sidecar.exists() {
+            fs::remove_file(&sidecar)
+                .map_err(|err| anyhow!("Cannot remove file({:?}): {:?}", sidecar, err))?;
+        }
+        Ok(())
     }
 }
 
@@ -165,6 +227,17 @@ mod tests {
         let plugin_path = temp_path.join("test_plugin.js");
         assert!(plugin_path.exists());
 
+        // Placeholders in the template are replaced.
+        let src = fs::read_to_string(plugin_path).unwrap();
+        assert!(src.contains(r#"const id = "test_plugin";"#));
+        assert!(src.contains(r#""TestPlugin","#));
+
+        // The manifest is filled in too.
+        let manifest = plugins.read("test_plugin.js".to_string()).unwrap().manifest;
+        let manifest = manifest.expect("template has a manifest");
+        assert_eq!(manifest.id, "test_plugin");
+        assert_eq!(manifest.name.as_deref(), Some("TestPlugin"));
+
         // Clean up is handled automatically by the tempdir crate
     }
 
@@ -192,7 +265,7 @@ mod tests {
             .unwrap();
 
         let plugin_ids = plugins.list().unwrap();
-        assert_eq!(plugin_ids, vec!["plugin_one", "plugin_two"]);
+        assert_eq!(plugin_ids, vec!["plugin_one.js", "plugin_two.js"]);
 
         // Check that both plugin files exist
         assert!(temp_path.join("plugin_one.js").exists());
@@ -228,6 +301,59 @@ mod tests {
         let snippet = plugins.read("hello.js".to_string()).expect("read code");
         assert_eq!(snippet.filename, "hello.js");
         assert_eq!(snippet.src, "code");
+        assert_eq!(snippet.manifest, None);
+        assert_eq!(snippet.manifest_error, None);
+    }
+
+    #[test]
+    fn test_read_manifest() {
+        initialize_logger();
+
+        let temp_dir = TempDir::with_prefix("manifest").unwrap();
+        let temp_path = temp_dir.path();
+        let plugins = Plugins::new_with_basedir(temp_path.to_str().unwrap().to_string());
+
+        plugins
+            .write(
+                "header.js".to_string(),
+                "// ==KeyScripten==\n// @id example.header\n// ==/KeyScripten==\n".to_string(),
+            )
+            .unwrap();
+        plugins
+            .write("sidecar.js".to_string(), "code".to_string())
+            .unwrap();
+        plugins
+            .write(
+                "sidecar.json".to_string(),
+                r#"{"id": "example.sidecar", "permissions": ["storage"]}"#.to_string(),
+            )
+            .unwrap();
+        plugins
+            .write(
+                "broken.js".to_string(),
+                "// ==KeyScripten==\n// @id example.broken\n".to_string(),
+            )
+            .unwrap();
+
+        let snippet = plugins.read("sidecar.js".to_string()).unwrap();
+        assert_eq!(snippet.manifest.unwrap().permissions, vec!["storage"]);
+        let broken = plugins.read("broken.js".to_string()).unwrap();
+        assert_eq!(broken.src, "// ==KeyScripten==\n// @id example.broken\n");
+        assert_eq!(broken.manifest, None);
+        assert!(broken.manifest_error.unwrap().contains("broken.js"));
+
+        assert_eq!(
+            plugins.find_by_plugin_id("example.header").unwrap(),
+            Some("header.js".to_string())
+        );
+        assert_eq!(
+            plugins.find_by_plugin_id("example.sidecar").unwrap(),
+            Some("sidecar.js".to_string())
+        );
+        assert_eq!(plugins.find_by_plugin_id("example.broken").unwrap(), None);
+
+        plugins.delete("sidecar.js".to_string()).unwrap();
+        assert!(!temp_path.join("sidecar.json").exists());
     }
 
     #[test]
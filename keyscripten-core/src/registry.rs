@@ -0,0 +1,35 @@
+use crate::js::{ConfigSchema, ConfigSchemaList};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// The plugins loaded into the live `JS`.
+///
+/// `JS` publishes it whenever the plugins change, so that the UI can read it without loading the
+/// plugins into another `JS`.
+#[derive(Debug, Default, Clone)]
+pub struct PluginRegistry {
+    pub plugins: Vec<ConfigSchema>,
+    // plugin id -> the file which registered it. Bundled plugins don't have one.
+    pub filenames: HashMap<String, String>,
+    // plugin id -> why the manifest can't be read. These files aren't loaded, so they aren't in
+    // `plugins`, but they are in `filenames`.
+    pub manifest_errors: HashMap<String, String>,
+}
+
+pub type SharedPluginRegistry = Arc<RwLock<PluginRegistry>>;
+
+impl PluginRegistry {
+    pub fn config_schema_list(&self) -> ConfigSchemaList {
+        ConfigSchemaList {
+            plugins: self.plugins.clone(),
+        }
+    }
+
+    pub fn config_schema(&self, plugin_id: &str) -> Option<&ConfigSchema> {
+        self.plugins.iter().find(|plugin| plugin.id == plugin_id)
+    }
+
+    pub fn filename(&self, plugin_id: &str) -> Option<&str> {
+        self.filenames.get(plugin_id).map(String::as_str)
+    }
+}
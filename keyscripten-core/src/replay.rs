@@ -0,0 +1,354 @@
+use crate::event::Event;
+use crate::js::JS;
+use crate::keycode::get_keyname;
+use crate::mock_backend::MockBackend;
+use crate::plugin::Plugins;
+use crate::trace::{TraceEntry, TraceFile};
+use std::fmt;
+use std::sync::Arc;
+
+/// Feeds a recorded trace into a fresh `JS` instance, and compares the results with the recording.
+pub struct Replayer {
+    js: JS,
+    backend: Arc<MockBackend>,
+}
+
+/// An event whose result differs from the recording.
+#[derive(Debug)]
+pub struct ReplayDiff {
+    pub index: usize,
+    pub elapsed_ms: u64,
+    /// None for the timers.
+    pub event: Option<Event>,
+    pub expected_pass: bool,
+    pub actual_pass: bool,
+    pub expected_modified: Option<Event>,
+    pub actual_modified: Option<Event>,
+    pub expected_injected: Vec<Event>,
+    pub actual_injected: Vec<Event>,
+}
+
+#[derive(Debug)]
+pub struct ReplayReport {
+    pub total: usize,
+    pub diffs: Vec<ReplayDiff>,
+}
+
+impl ReplayReport {
+    pub fn is_ok(&self) -> bool {
+        self.diffs.is_empty()
+    }
+}
+
+impl Replayer {
+    /// Builds the runtime with driver.js, the bundled plugins and the user scripts in `plugins`.
+    /// The plugins run with the configuration recorded in the header of `trace`, or the default one
+    /// for the traces recorded without it.
+    pub fn new(plugins: Option<Plugins>, trace: &TraceFile) -> anyhow::Result<Self> {
+        let config = trace.header.config.clone().unwrap_or_default();
+        let backend = Arc::new(MockBackend::new());
+        let mut js = JS::new_with_config(None, None, plugins, Some(backend.clone()), config)?;
+        // Replays start with an empty storage, and don't change the user's one.
+        js.use_storage(None);
+        js.load_user_scripts()?;
+        js.use_virtual_clock();
+        Ok(Replayer { js, backend })
+    }
+
+    pub fn replay(&mut self, trace: &TraceFile) -> anyhow::Result<ReplayReport> {
+        let mut diffs = Vec::new();
+        for (index, entry) in trace.entries.iter().enumerate() {
+            if let Some(diff) = self.replay_entry(index, entry)? {
+                diffs.push(diff);
+            }
+        }
+        Ok(ReplayReport {
+            total: trace.entries.len(),
+            diffs,
+        })
+    }
+
+    fn replay_entry(
+        &mut self,
+        index: usize,
+        entry: &TraceEntry,
+    ) -> anyhow::Result<Option<ReplayDiff>> {
+        let (actual_pass, actual_modified) = match &entry.event {
+            Some(event) => {
+                self.js.run_timers_until(entry.elapsed_ms)?;
+                self.backend.clear();
+                let result = self.js.send_event(event.clone())?;
+                (result.is_some(), result.filter(|e| !e.same_input(event)))
+            }
+            None => {
+                self.backend.clear();
+                self.js.run_timers_until(entry.elapsed_ms)?;
+                (true, None)
+            }
+        };
+        let actual_injected = self.backend.injected();
+
+        if actual_pass == entry.pass
+            && same_event(&actual_modified, &entry.modified)
+            && same_events(&actual_injected, &entry.injected)
+        {
+            return Ok(None);
+        }
+        Ok(Some(ReplayDiff {
+            index,
+            elapsed_ms: entry.elapsed_ms,
+            event: entry.event.clone(),
+            expected_pass: entry.pass,
+            actual_pass,
+            expected_modified: entry.modified.clone(),
+            actual_modified,
+            expected_injected: entry.injected.clone(),
+            actual_injected,
+        }))
+    }
+}
+
+// Timestamps are ignored. They never match between the recording and the replay.
+fn same_events(a: &[Event], b: &[Event]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.same_input(b))
+}
+
+fn same_event(a: &Option<Event>, b: &Option<Event>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.same_input(b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+fn format_event(event: &Event) -> String {
+    if event.mouse.is_some() {
+        return format!("{}(flags={:#x})", event.event_type, event.flags);
+    }
+    if let Some(text) = &event.text {
+        return format!("{}(text={:?})", event.event_type, text);
+    }
+    let keyname = u16::try_from(event.keycode)
+        .ok()
+        .and_then(get_keyname)
+        .unwrap_or("unknown");
+    format!(
+        "{}(keycode={}({}), flags={:#x})",
+        event.event_type, event.keycode, keyname, event.flags
+    )
+}
+
+fn format_entry(event: &Option<Event>) -> String {
+    match event {
+        Some(event) => format_event(event),
+        None => "timer".to_string(),
+    }
+}
+
+fn format_modified(event: &Option<Event>) -> String {
+    match event {
+        Some(event) => format!(" modified={}", format_event(event)),
+        None => "".to_string(),
+    }
+}
+
+fn format_events(events: &[Event]) -> String {
+    let events: Vec<String> = events.iter().map(format_event).collect();
+    format!("[{}]", events.join(", "))
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for diff in &self.diffs {
+            writeln!(
+                f,
+                "#{} at {}ms: {}",
+                diff.index,
+                diff.elapsed_ms,
+                format_entry(&diff.event)
+            )?;
+            writeln!(
+                f,
+                "- pass={}{} injected={}",
+                diff.expected_pass,
+                format_modified(&diff.expected_modified),
+                format_events(&diff.expected_injected)
+            )?;
+            writeln!(
+                f,
+                "+ pass={}{} injected={}",
+                diff.actual_pass,
+                format_modified(&diff.actual_modified),
+                format_events(&diff.actual_injected)
+            )?;
+        }
+        write!(
+            f,
+            "{} events replayed, {} differences",
+            self.total,
+            self.diffs.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_config::AppConfig;
+    use crate::harness::keystrokes;
+    use crate::trace::{TraceHeader, TRACE_VERSION};
+
+    fn empty_trace(config: Option<AppConfig>) -> TraceFile {
+        TraceFile {
+            header: TraceHeader {
+                version: TRACE_VERSION,
+                created_at: "".to_string(),
+                config,
+            },
+            entries: Vec::new(),
+        }
+    }
+
+    fn record(keys: &str) -> anyhow::Result<TraceFile> {
+        let mut trace = empty_trace(Some(AppConfig::default()));
+        let mut recorder = Replayer::new(None, &trace)?;
+        for (i, event) in keystrokes(keys)?.into_iter().enumerate() {
+            recorder.backend.clear();
+            let result = recorder.js.send_event(event.clone())?;
+            trace.entries.push(TraceEntry {
+                elapsed_ms: i as u64 * 10,
+                pass: result.is_some(),
+                modified: result.filter(|e| !e.same_input(&event)),
+                event: Some(event),
+                injected: recorder.backend.injected(),
+            });
+        }
+        Ok(trace)
+    }
+
+    #[test]
+    fn test_replay_same_result() -> anyhow::Result<()> {
+        let trace = record("a b a b C-t")?;
+        let report = Replayer::new(None, &trace)?.replay(&trace)?;
+        assert!(report.is_ok(), "{}", report);
+        assert_eq!(report.total, 12);
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_diff() -> anyhow::Result<()> {
+        let mut trace = record("a b a b C-t")?;
+        // pretend the hotkey was passed to the application at recording time.
+        trace.entries[9].pass = true;
+        trace.entries[9].injected.clear();
+
+        let report = Replayer::new(None, &trace)?.replay(&trace)?;
+        assert_eq!(report.diffs.len(), 1);
+        assert_eq!(report.diffs[0].index, 9);
+        assert!(!report.diffs[0].actual_pass);
+        let output = report.to_string();
+        assert!(output.contains("- pass=true injected=[]"), "{}", output);
+        assert!(output.ends_with("12 events replayed, 1 differences"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_with_recorded_config() -> anyhow::Result<()> {
+        let mut trace = record("a b a b C-t")?;
+        let mut config = AppConfig::default();
+        config.set_value("plugins.builtin.dynamicmacro.enabled", "false")?;
+        trace.header.config = Some(config);
+
+        // The hotkey reaches the application when the plugin is disabled.
+        let report = Replayer::new(None, &trace)?.replay(&trace)?;
+        assert_eq!(report.diffs.len(), 1);
+        assert_eq!(report.diffs[0].index, 9);
+        assert!(report.diffs[0].actual_pass);
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_timer() -> anyhow::Result<()> {
+        let temp_dir = tempfile::TempDir::with_prefix("replay")?;
+        let basedir = temp_dir.path().to_string_lossy().to_string();
+        Plugins::new_with_basedir(basedir.clone()).write(
+            "timer.js".to_string(),
+            r#"registerPlugin("test.timer", "Timer", "", (event) => {
+                if (event.type === "keyDown") setTimeout(() => sendKeys("b"), 100);
+                return true;
+            }, []);"#
+                .to_string(),
+        )?;
+
+        let mut trace = empty_trace(Some(AppConfig::default()));
+        let plugins = Plugins::new_with_basedir(basedir.clone());
+        let mut recorder = Replayer::new(Some(plugins), &trace)?;
+        for event in keystrokes("a")? {
+            recorder.js.send_event(event.clone())?;
+            trace.entries.push(TraceEntry {
+                elapsed_ms: 0,
+                event: Some(event),
+                pass: true,
+                modified: None,
+                injected: vec![],
+            });
+        }
+        recorder.js.run_timers_until(100)?;
+        trace.entries.push(TraceEntry {
+            elapsed_ms: 100,
+            event: None,
+            pass: true,
+            modified: None,
+            injected: recorder.backend.injected(),
+        });
+        assert!(!trace.entries[2].injected.is_empty());
+
+        let plugins = Plugins::new_with_basedir(basedir.clone());
+        let report = Replayer::new(Some(plugins), &trace)?.replay(&trace)?;
+        assert!(report.is_ok(), "{}", report);
+
+        // pretend the timer typed nothing at recording time.
+        trace.entries[2].injected.clear();
+        let plugins = Plugins::new_with_basedir(basedir);
+        let report = Replayer::new(Some(plugins), &trace)?.replay(&trace)?;
+        assert_eq!(report.diffs.len(), 1);
+        assert!(report.diffs[0].event.is_none());
+        assert!(report.to_string().starts_with("#2 at 100ms: timer\n"), "{}", report);
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_send_text() -> anyhow::Result<()> {
+        let temp_dir = tempfile::TempDir::with_prefix("replay")?;
+        let basedir = temp_dir.path().to_string_lossy().to_string();
+        Plugins::new_with_basedir(basedir.clone()).write(
+            "text.js".to_string(),
+            r#"registerPlugin("test.text", "Text", "", (event) => {
+                if (event.type === "keyDown") sendText("hi");
+                return true;
+            }, []);"#
+                .to_string(),
+        )?;
+
+        // Recorded by the trace writer of `JS`, as the app does.
+        let path = temp_dir.path().join("trace.jsonl");
+        let plugins = Plugins::new_with_basedir(basedir.clone());
+        let backend = Arc::new(MockBackend::new());
+        let mut js =
+            JS::new_with_config(None, None, Some(plugins), Some(backend), AppConfig::default())?;
+        js.use_storage(None);
+        js.load_user_scripts()?;
+        js.start_trace(path.clone())?;
+        for event in keystrokes("a")? {
+            js.send_event(event)?;
+        }
+        js.stop_trace();
+
+        let trace = crate::trace::read_trace(&path)?;
+        assert_eq!(trace.entries[0].injected.len(), 2);
+        let plugins = Plugins::new_with_basedir(basedir);
+        let report = Replayer::new(Some(plugins), &trace)?.replay(&trace)?;
+        assert!(report.is_ok(), "{}", report);
+        Ok(())
+    }
+}
@@ -1,17 +1,22 @@
+use crate::backend::USER_DATA_FROM_THIS_APP;
 use anyhow::anyhow;
+use apple_sys::CoreFoundation::CGPoint;
 use apple_sys::CoreGraphics::{
-    CGEventCreate, CGEventCreateKeyboardEvent, CGEventFlags, CGEventPost, CGEventSetFlags,
-    CGEventSetIntegerValueField, CGEventSetType, CGEventSourceCreate, CGEventSourceRef, CGKeyCode,
+    CGEventCreate, CGEventCreateKeyboardEvent, CGEventCreateMouseEvent,
+    CGEventCreateScrollWheelEvent2, CGEventFlags, CGEventGetLocation, CGEventKeyboardSetUnicodeString,
+    CGEventPost, CGEventRef,
+    CGEventSetFlags, CGEventSetIntegerValueField, CGEventSetType, CGEventSourceCreate,
+    CGEventSourceRef, CGEventType, CGKeyCode,
 };
 use crate::cg_constants::{
     kCGEventFlagsChanged, kCGEventSourceStatePrivate, kCGEventSourceUserData, kCGHIDEventTap,
+    kCGMouseEventButtonNumber, kCGScrollEventUnitLine, CGMouseButton,
 };
+use std::ptr::null_mut;
 
 #[link(name = "Cocoa", kind = "framework")]
 extern "C" {}
 
-pub const USER_DATA_FROM_THIS_APP: i64 = 5963;
-
 fn build_event_source() -> anyhow::Result<CGEventSourceRef> {
     unsafe {
         let source = CGEventSourceCreate(kCGEventSourceStatePrivate);
@@ -46,6 +51,45 @@ pub fn send_keyboard_event(
     }
 }
 
+// CGEventKeyboardSetUnicodeString may truncate longer strings.
+const MAX_UNICODE_STRING_LENGTH: usize = 20;
+
+// Types `text` with key presses carrying the unicode string, so any character can be typed
+// regardless of the keyboard layout.
+pub fn send_text(text: &str) -> anyhow::Result<()> {
+    let source = build_event_source()?;
+    let utf16: Vec<u16> = text.encode_utf16().collect();
+
+    log::debug!("Sending text: {:?}", text);
+    let mut start = 0;
+    while start < utf16.len() {
+        let mut end = (start + MAX_UNICODE_STRING_LENGTH).min(utf16.len());
+        // Don't split a surrogate pair.
+        if end < utf16.len() && (0xDC00..=0xDFFF).contains(&utf16[end]) {
+            end -= 1;
+        }
+        let chunk = &utf16[start..end];
+        for keydown in [true, false] {
+            unsafe {
+                let event = CGEventCreateKeyboardEvent(source, 0, keydown);
+                if event.is_null() {
+                    return Err(anyhow!("Cannot create keyboard event"));
+                }
+                CGEventKeyboardSetUnicodeString(event, chunk.len() as _, chunk.as_ptr());
+                CGEventSetFlags(event, 0);
+                CGEventSetIntegerValueField(
+                    event,
+                    kCGEventSourceUserData,
+                    USER_DATA_FROM_THIS_APP,
+                );
+                CGEventPost(kCGHIDEventTap, event);
+            }
+        }
+        start = end;
+    }
+    Ok(())
+}
+
 pub fn send_flags_changed_event(flags: CGEventFlags) -> anyhow::Result<()> {
     let source = build_event_source()?;
 
@@ -60,3 +104,66 @@ pub fn send_flags_changed_event(flags: CGEventFlags) -> anyhow::Result<()> {
         Ok(())
     }
 }
+
+// Current cursor position, taken from an empty event.
+fn cursor_position() -> anyhow::Result<CGPoint> {
+    unsafe {
+        let event = CGEventCreate(null_mut());
+        if event.is_null() {
+            return Err(anyhow!("Can't create new CGEvent"));
+        }
+        Ok(CGEventGetLocation(event))
+    }
+}
+
+unsafe fn post(event: CGEventRef, flags: CGEventFlags) {
+    CGEventSetFlags(event, flags);
+    CGEventSetIntegerValueField(event, kCGEventSourceUserData, USER_DATA_FROM_THIS_APP);
+    CGEventPost(kCGHIDEventTap, event);
+}
+
+pub fn send_mouse_event(
+    event_type: CGEventType,
+    button: i64,
+    position: Option<(f64, f64)>,
+    flags: CGEventFlags,
+) -> anyhow::Result<()> {
+    let source = build_event_source()?;
+    let position = match position {
+        Some((x, y)) => CGPoint { x, y },
+        None => cursor_position()?,
+    };
+
+    log::debug!("Sending mouse event: {:?} button={}", event_type, button);
+    unsafe {
+        let event = CGEventCreateMouseEvent(source, event_type, position, button as CGMouseButton);
+        if event.is_null() {
+            return Err(anyhow!("Cannot create mouse event"));
+        }
+        // CGEventCreateMouseEvent ignores the button for left and right mouse events.
+        CGEventSetIntegerValueField(event, kCGMouseEventButtonNumber, button);
+        post(event, flags);
+        Ok(())
+    }
+}
+
+pub fn send_scroll_event(delta_x: i64, delta_y: i64, flags: CGEventFlags) -> anyhow::Result<()> {
+    let source = build_event_source()?;
+
+    log::debug!("Sending scroll event: ({}, {})", delta_x, delta_y);
+    unsafe {
+        let event = CGEventCreateScrollWheelEvent2(
+            source,
+            kCGScrollEventUnitLine,
+            2,
+            delta_y as i32,
+            delta_x as i32,
+            0,
+        );
+        if event.is_null() {
+            return Err(anyhow!("Cannot create scroll wheel event"));
+        }
+        post(event, flags);
+        Ok(())
+    }
+}
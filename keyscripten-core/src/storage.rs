@@ -0,0 +1,219 @@
+use crate::APP_NAME;
+use anyhow::anyhow;
+use boa_engine::JsData;
+use boa_gc::{Finalize, Trace};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+type StorageMap = BTreeMap<String, BTreeMap<String, Value>>;
+
+/// Key-value storage of the plugins, namespaced by the plugin id.
+///
+/// It's persisted in `storage.json` next to `config.json`. The file is read on the first access,
+/// and `flush` replaces it atomically, so a crash leaves either the old or the new file.
+pub struct PluginStorage {
+    // None keeps everything in memory. Harness, replay and check use it.
+    path: Option<PathBuf>,
+    data: Option<StorageMap>,
+    dirty: bool,
+}
+
+impl PluginStorage {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        PluginStorage {
+            path,
+            data: None,
+            dirty: false,
+        }
+    }
+
+    pub fn default_path() -> anyhow::Result<PathBuf> {
+        let config_dir =
+            dirs::config_dir().ok_or_else(|| anyhow!("Cannot get the config directory"))?;
+        Ok(config_dir.join(APP_NAME).join("storage.json"))
+    }
+
+    fn data(&mut self) -> &mut StorageMap {
+        if self.data.is_none() {
+            self.data = Some(self.load());
+        }
+        self.data.get_or_insert_with(StorageMap::new)
+    }
+
+    fn load(&mut self) -> StorageMap {
+        let Some(path) = self.path.clone() else {
+            return StorageMap::new();
+        };
+        match load(&path) {
+            Ok(data) => data,
+            Err(err) => {
+                log::error!("Cannot load plugin storage({:?}): {:?}", path, err);
+                // Keep the broken file for the user. The next flush would overwrite it with the
+                // empty storage.
+                let corrupt_path = path.with_extension("json.corrupt");
+                match fs::rename(&path, &corrupt_path) {
+                    Ok(()) => log::error!("Moved the plugin storage to {:?}", corrupt_path),
+                    Err(err) => {
+                        log::error!(
+                            "Cannot move {:?} to {:?}. The plugin storage isn't saved: {:?}",
+                            path,
+                            corrupt_path,
+                            err
+                        );
+                        self.path = None;
+                    }
+                }
+                StorageMap::new()
+            }
+        }
+    }
+
+    pub fn get(&mut self, plugin_id: &str, key: &str) -> Option<Value> {
+        self.data().get(plugin_id)?.get(key).cloned()
+    }
+
+    pub fn set(&mut self, plugin_id: &str, key: &str, value: Value) {
+        let entries = self.data().entry(plugin_id.to_string()).or_default();
+        if entries.get(key) != Some(&value) {
+            entries.insert(key.to_string(), value);
+            self.dirty = true;
+        }
+    }
+
+    /// Returns false if there was no such key.
+    pub fn delete(&mut self, plugin_id: &str, key: &str) -> bool {
+        let data = self.data();
+        let Some(entries) = data.get_mut(plugin_id) else {
+            return false;
+        };
+        let deleted = entries.remove(key).is_some();
+        if entries.is_empty() {
+            data.remove(plugin_id);
+        }
+        self.dirty |= deleted;
+        deleted
+    }
+
+    pub fn keys(&mut self, plugin_id: &str) -> Vec<String> {
+        match self.data().get(plugin_id) {
+            Some(entries) => entries.keys().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Writes the changes to the file, if there's any.
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if let (Some(path), Some(data)) = (&self.path, &self.data) {
+            save(path, data)?;
+        }
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl Drop for PluginStorage {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            log::error!("Cannot save plugin storage({:?}): {:?}", self.path, err);
+        }
+    }
+}
+
+fn load(path: &Path) -> anyhow::Result<StorageMap> {
+    if !path.exists() {
+        return Ok(StorageMap::new());
+    }
+    let file = File::open(path)?;
+    Ok(serde_json::from_reader(file)?)
+}
+
+// Writes to a temporary file in the same directory, then renames it over the old file.
+fn save(path: &Path, data: &StorageMap) -> anyhow::Result<()> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| anyhow!("Invalid storage path: {:?}", path))?;
+    fs::create_dir_all(dir)?;
+
+    let tmp_path = path.with_extension("json.tmp");
+    let mut f = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&tmp_path)?;
+    f.write_all(serde_json::to_string_pretty(data)?.as_bytes())?;
+    f.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+// Holder to put the storage into the boa `Context`. The storage is flushed when the context is
+// dropped.
+#[derive(Trace, Finalize, JsData)]
+pub(crate) struct StorageData {
+    #[unsafe_ignore_trace]
+    pub(crate) storage: RefCell<PluginStorage>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_storage() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("storage.json");
+
+        let mut storage = PluginStorage::new(Some(path.clone()));
+        storage.set("a", "count", json!(3));
+        storage.set("a", "words", json!(["x", "y"]));
+        storage.set("b", "count", json!(1));
+        assert_eq!(storage.get("a", "count"), Some(json!(3)));
+        assert_eq!(storage.get("b", "words"), None);
+        assert_eq!(storage.keys("a"), vec!["count", "words"]);
+        assert!(storage.delete("b", "count"));
+        assert!(!storage.delete("b", "count"));
+        assert!(!path.exists());
+        storage.flush()?;
+        assert!(path.exists());
+        assert!(!path.with_extension("json.tmp").exists());
+
+        let mut storage = PluginStorage::new(Some(path.clone()));
+        assert_eq!(storage.get("a", "words"), Some(json!(["x", "y"])));
+        assert!(storage.keys("b").is_empty());
+
+        // Changes are saved on drop.
+        storage.set("a", "count", json!(4));
+        drop(storage);
+        let mut storage = PluginStorage::new(Some(path));
+        assert_eq!(storage.get("a", "count"), Some(json!(4)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_storage() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("storage.json");
+        fs::write(&path, "{broken")?;
+
+        let mut storage = PluginStorage::new(Some(path.clone()));
+        assert_eq!(storage.get("a", "count"), None);
+        storage.set("a", "count", json!(1));
+        storage.flush()?;
+
+        // The broken file is kept aside, not overwritten.
+        let corrupt_path = path.with_extension("json.corrupt");
+        assert_eq!(fs::read_to_string(corrupt_path)?, "{broken");
+        let mut storage = PluginStorage::new(Some(path));
+        assert_eq!(storage.get("a", "count"), Some(json!(1)));
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+use crate::app_config::AppConfig;
+use crate::event::Event;
+use crate::APP_NAME;
+use anyhow::anyhow;
+use boa_engine::JsData;
+use boa_gc::{Finalize, Trace};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Bump this when the format of the trace file changes incompatibly. Older versions are still
+/// read.
+///
+/// 2: entries without `event`, for the events injected by timers.
+pub const TRACE_VERSION: u32 = 2;
+
+/// The first line of the trace file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TraceHeader {
+    pub version: u32,
+    pub created_at: String,
+    /// The configuration at the start of the recording. Replays run with it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<AppConfig>,
+}
+
+/// Following lines of the trace file. One entry per event processed by `JS::send_event`, and one
+/// per run of the timers which injected events.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TraceEntry {
+    /// Milliseconds since the recording started. Taken from the monotonic clock.
+    pub elapsed_ms: u64,
+    /// None for the timers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<Event>,
+    /// false if the plugins swallowed the event.
+    pub pass: bool,
+    /// The event delivered instead, if a plugin rewrote it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<Event>,
+    /// Events injected by the plugins while processing this event.
+    pub injected: Vec<Event>,
+}
+
+#[derive(Debug)]
+pub struct TraceFile {
+    pub header: TraceHeader,
+    pub entries: Vec<TraceEntry>,
+}
+
+/// Directory to store trace files by default.
+pub fn trace_dir() -> anyhow::Result<PathBuf> {
+    let datadir = dirs::data_dir().ok_or_else(|| anyhow!("Data directory not found"))?;
+    Ok(datadir.join(APP_NAME).join("traces"))
+}
+
+pub fn default_trace_path() -> anyhow::Result<PathBuf> {
+    let filename = format!("trace-{}.jsonl", Local::now().format("%Y%m%d-%H%M%S"));
+    Ok(trace_dir()?.join(filename))
+}
+
+pub struct TraceWriter {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    started_at: Instant,
+}
+
+impl TraceWriter {
+    pub fn create(path: &Path, config: &AppConfig) -> anyhow::Result<TraceWriter> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = File::create(path)
+            .map_err(|err| anyhow!("Cannot create trace file({:?}): {:?}", path, err))?;
+        let mut writer = BufWriter::new(file);
+        let header = TraceHeader {
+            version: TRACE_VERSION,
+            created_at: Local::now().to_rfc3339(),
+            config: Some(config.clone()),
+        };
+        serde_json::to_writer(&mut writer, &header)?;
+        writer.write_all(b"\n")?;
+
+        Ok(TraceWriter {
+            path: path.to_path_buf(),
+            writer,
+            started_at: Instant::now(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn write(
+        &mut self,
+        event: Event,
+        pass: bool,
+        modified: Option<Event>,
+        injected: Vec<Event>,
+    ) -> anyhow::Result<()> {
+        self.write_entry(TraceEntry {
+            elapsed_ms: self.started_at.elapsed().as_millis() as u64,
+            event: Some(event),
+            pass,
+            modified,
+            injected,
+        })
+    }
+
+    /// Records the events injected by the timers.
+    pub fn write_timer(&mut self, injected: Vec<Event>) -> anyhow::Result<()> {
+        self.write_entry(TraceEntry {
+            elapsed_ms: self.started_at.elapsed().as_millis() as u64,
+            event: None,
+            pass: true,
+            modified: None,
+            injected,
+        })
+    }
+
+    fn write_entry(&mut self, entry: TraceEntry) -> anyhow::Result<()> {
+        serde_json::to_writer(&mut self.writer, &entry)?;
+        self.writer.write_all(b"\n")?;
+        // Flush every entry. The trace is most useful right after the app crashed.
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+pub fn read_trace(path: &Path) -> anyhow::Result<TraceFile> {
+    let file =
+        File::open(path).map_err(|err| anyhow!("Cannot open trace file({:?}): {:?}", path, err))?;
+    let mut lines = BufReader::new(file).lines();
+
+    let header = lines
+        .next()
+        .ok_or_else(|| anyhow!("Empty trace file: {:?}", path))??;
+    let header: TraceHeader = serde_json::from_str(&header)
+        .map_err(|err| anyhow!("Invalid trace header in {:?}: {:?}", path, err))?;
+    if header.version == 0 || header.version > TRACE_VERSION {
+        return Err(anyhow!(
+            "Unsupported trace version {} in {:?} (expected {})",
+            header.version,
+            path,
+            TRACE_VERSION
+        ));
+    }
+
+    let mut entries = Vec::new();
+    for (i, line) in lines.enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: TraceEntry = serde_json::from_str(&line)
+            .map_err(|err| anyhow!("Invalid trace entry at line {}: {:?}", i + 2, err))?;
+        entries.push(entry);
+    }
+    Ok(TraceFile { header, entries })
+}
+
+// Collects the events injected by builtin functions while a trace is recorded.
+#[derive(Trace, Finalize, JsData, Default)]
+pub(crate) struct InjectedEvents {
+    #[unsafe_ignore_trace]
+    pub(crate) events: RefCell<Vec<Event>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cg_constants::{kCGEventKeyDown, kCGEventKeyUp};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_write_and_read() -> anyhow::Result<()> {
+        let temp_dir = TempDir::with_prefix("trace")?;
+        let path = temp_dir.path().join("traces").join("trace.jsonl");
+
+        let mut config = AppConfig::default();
+        config.set_value("plugins.test.a.enabled", "false")?;
+        let mut writer = TraceWriter::create(&path, &config)?;
+        writer.write(
+            Event::new(kCGEventKeyDown, 0, 0),
+            true,
+            Some(Event::new(kCGEventKeyDown, 11, 0)),
+            vec![],
+        )?;
+        writer.write(
+            Event::new(kCGEventKeyDown, 17, 0),
+            false,
+            None,
+            vec![Event::new(kCGEventKeyUp, 0, 0)],
+        )?;
+        writer.write_timer(vec![Event::new(kCGEventKeyDown, 1, 0)])?;
+
+        let trace = read_trace(&path)?;
+        assert_eq!(trace.header.version, TRACE_VERSION);
+        let config = trace.header.config.unwrap();
+        assert_eq!(config.get_value("plugins.test.a.enabled")?, Some("false".to_string()));
+        assert_eq!(trace.entries.len(), 3);
+        assert!(trace.entries[0].pass);
+        assert!(!trace.entries[1].pass);
+        assert_eq!(trace.entries[0].modified.as_ref().unwrap().keycode, 11);
+        assert_eq!(trace.entries[1].event.as_ref().unwrap().keycode, 17);
+        assert!(trace.entries[1].modified.is_none());
+        assert_eq!(trace.entries[1].injected[0].event_type, "keyUp");
+        assert!(trace.entries[0].elapsed_ms <= trace.entries[1].elapsed_ms);
+        assert!(trace.entries[2].event.is_none());
+        assert_eq!(trace.entries[2].injected[0].keycode, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unsupported_version() -> anyhow::Result<()> {
+        let temp_dir = TempDir::with_prefix("trace")?;
+        let path = temp_dir.path().join("trace.jsonl");
+        fs::write(&path, "{\"version\":999,\"created_at\":\"\"}\n")?;
+        assert!(read_trace(&path).is_err());
+        fs::write(&path, "{\"version\":1,\"created_at\":\"\"}\n")?;
+        read_trace(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+use crate::js_realm::PluginRealm;
+use boa_engine::{Context, JsData, JsNativeError, JsResult, JsValue};
+use boa_gc::{Finalize, Trace};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Time a callback may run, including the functions it calls, so that an endless loop in a
+/// callback can't freeze the event tap. The timer callbacks and each batch of Promise jobs get the
+/// same budget.
+pub const DEFAULT_CALLBACK_BUDGET: Duration = Duration::from_millis(100);
+
+/// Time the top level code of a plugin file may run.
+pub const LOAD_BUDGET: Duration = Duration::from_secs(1);
+
+/// A plugin is disabled when its callbacks are aborted this many times.
+pub const MAX_OFFENCES: u32 = 3;
+
+/// Counts the aborted callbacks of each plugin.
+#[derive(Default)]
+pub struct Watchdog {
+    offences: HashMap<String, u32>,
+}
+
+impl Watchdog {
+    /// Records an aborted callback. Returns true if the plugin has to be disabled, then its count
+    /// starts over, so that it gets the same chances after the user enables it again.
+    pub fn record(&mut self, plugin_id: &str) -> bool {
+        let count = self.offences.entry(plugin_id.to_string()).or_default();
+        *count += 1;
+        if *count < MAX_OFFENCES {
+            return false;
+        }
+        self.offences.remove(plugin_id);
+        true
+    }
+
+    pub fn offences(&self, plugin_id: &str) -> u32 {
+        self.offences.get(plugin_id).copied().unwrap_or(0)
+    }
+}
+
+/// The plugin whose code ran out of the budget.
+pub(crate) struct Overrun {
+    // None if the realm had no owner yet.
+    pub(crate) plugin_id: Option<String>,
+    budget: Duration,
+}
+
+impl Overrun {
+    pub(crate) fn message(&self) -> String {
+        format!("Ran longer than {} ms", self.budget.as_millis())
+    }
+}
+
+/// The deadline of the running callback, checked by the `$$tick()` calls which instrument.rs
+/// inserts into the plugin code.
+///
+/// A native call, e.g. a regular expression or `"x".repeat(1e9)`, can't be interrupted. Its time
+/// is charged at the next tick.
+#[derive(Default, Trace, Finalize, JsData)]
+pub(crate) struct Budget {
+    #[unsafe_ignore_trace]
+    deadline: Cell<Option<(Instant, Duration)>>,
+    #[unsafe_ignore_trace]
+    overrun: RefCell<Option<Overrun>>,
+}
+
+/// The budget which `Budget::start` replaced, for `Budget::finish` to restore.
+pub(crate) struct SavedBudget {
+    deadline: Option<(Instant, Duration)>,
+    overrun: Option<Overrun>,
+}
+
+impl Budget {
+    pub(crate) fn start(&self, budget: Duration) -> SavedBudget {
+        SavedBudget {
+            deadline: self.deadline.replace(Some((Instant::now() + budget, budget))),
+            overrun: self.overrun.take(),
+        }
+    }
+
+    /// Returns the plugin which ran out of the budget since `start`, if any.
+    pub(crate) fn finish(&self, saved: SavedBudget) -> Option<Overrun> {
+        self.deadline.set(saved.deadline);
+        self.overrun.replace(saved.overrun)
+    }
+
+    pub(crate) fn is_exceeded(&self) -> bool {
+        self.overrun.borrow().is_some()
+    }
+
+    // $$tick() of the plugin realms. Past the deadline, it throws at every call, so the callback
+    // can't get further than its next function call or loop iteration even if it catches the
+    // error. boa's own runtime limits would be uncatchable, but a Promise job panics on them.
+    pub(crate) fn tick(
+        _this: &JsValue,
+        _args: &[JsValue],
+        context: &mut Context,
+    ) -> JsResult<JsValue> {
+        let Some(budget) = context.get_data::<Budget>() else {
+            return Ok(JsValue::new(true));
+        };
+        let Some((deadline, limit)) = budget.deadline.get() else {
+            return Ok(JsValue::new(true));
+        };
+        if Instant::now() < deadline {
+            return Ok(JsValue::new(true));
+        }
+        let mut overrun = budget.overrun.borrow_mut();
+        let overrun = overrun.get_or_insert_with(|| Overrun {
+            plugin_id: PluginRealm::owner_of(context.realm()),
+            budget: limit,
+        });
+        Err(JsNativeError::error()
+            .with_message(overrun.message())
+            .into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut watchdog = Watchdog::default();
+        for _ in 1..MAX_OFFENCES {
+            assert!(!watchdog.record("a"));
+        }
+        assert!(!watchdog.record("b"));
+        assert!(watchdog.record("a"));
+        assert_eq!(watchdog.offences("a"), 0);
+        assert_eq!(watchdog.offences("b"), 1);
+    }
+}
@@ -3,21 +3,25 @@ use std::path::PathBuf;
 use std::{fs, thread};
 
 use std::str::FromStr;
-use std::sync::{mpsc, Arc, RwLock};
+use std::sync::{mpsc, Arc, RwLock, RwLockReadGuard};
+use std::time::Duration;
 
 use anyhow::anyhow;
 
 use chrono::{Local, SecondsFormat};
 use keyscripten_core::app_config::{AppConfig, PluginConfig};
+use keyscripten_core::backend::default_backend;
 use keyscripten_core::event::Event;
-use keyscripten_core::grab::{grab_run, grab_setup};
 use keyscripten_core::js::{ConfigSchema, ConfigSchemaList, JS};
+use keyscripten_core::registry::{PluginRegistry, SharedPluginRegistry};
 use keyscripten_core::js_console::TimedLogMessage;
-use keyscripten_core::js_operation::JsOperation;
+use keyscripten_core::js_operation::{js_operation_channel, JsOperationSender, JsRequest, JsResponse};
 use keyscripten_core::plugin::Plugins;
+use keyscripten_core::replay::Replayer;
+use keyscripten_core::trace::{read_trace, trace_dir};
 use lazy_static::lazy_static;
 use log::{LevelFilter, Record};
-use tauri::{Listener, Manager, WebviewUrl, WebviewWindowBuilder};
+use tauri::{Manager, WebviewUrl, WebviewWindowBuilder};
 use tauri::menu::{Menu, MenuItem};
 use tauri::tray::TrayIconBuilder;
 
@@ -36,18 +40,20 @@ lazy_static! {
     static ref SETUP_ERROR: RwLock<Option<String>> = RwLock::new(None);
 }
 
-fn build_js<'a>() -> Result<JS, String> {
-    let plugins = Plugins::new().map_err(|err| format!("Plugins::new: {:?}", err))?;
-    let mut js = JS::new(None, None, Some(plugins)).map_err(|err| format!("{:?}", err))?;
-    js.load_user_scripts()
-        .map_err(|err| format!("load_user_scripts: {:?}", err))?;
-    Ok(js)
+lazy_static! {
+    // Published by the JS thread. Commands read the plugins from it instead of loading them again.
+    static ref PLUGIN_REGISTRY: SharedPluginRegistry = SharedPluginRegistry::default();
+}
+
+fn read_registry() -> Result<RwLockReadGuard<'static, PluginRegistry>, String> {
+    PLUGIN_REGISTRY
+        .read()
+        .map_err(|err| format!("Cannot get lock for the plugin registry: {:?}", err))
 }
 
 fn get_filename_by_plugin_id(plugin_id: String) -> Result<String, String> {
-    let js = build_js().map_err(|err| format!("Cannot build js: {:?}", err))?;
-    if let Some(filename) = js.get_filename_by_plugin_id(&plugin_id) {
-        Ok(filename)
+    if let Some(filename) = read_registry()?.filename(&plugin_id) {
+        Ok(filename.to_string())
     } else {
         log::error!("There's no plugin file found for {}", plugin_id);
         Err(format!("There's no plugin file found for {}", plugin_id))
@@ -56,24 +62,40 @@ fn get_filename_by_plugin_id(plugin_id: String) -> Result<String, String> {
 
 #[tauri::command]
 fn get_config_schema() -> Result<ConfigSchemaList, String> {
-    let mut js = build_js()?;
-    js.get_config_schema()
-        .map_err(|err| format!("get_config_schema: {:?}", err))
+    Ok(read_registry()?.config_schema_list())
 }
 
 #[tauri::command]
 fn get_config_schema_for_plugin(plugin_id: String) -> Result<ConfigSchema, String> {
-    let mut js = build_js()?;
-    let schema_list = js.get_config_schema().map_err(|err| format!("{:?}", err))?;
-    for plugin in schema_list.plugins {
-        if plugin.id == plugin_id {
-            return Ok(plugin);
-        }
-    }
-    Err(format!(
-        "Cannot load configuration schema for {}",
-        plugin_id
-    ))
+    read_registry()?
+        .config_schema(&plugin_id)
+        .cloned()
+        .ok_or_else(|| format!("Cannot load configuration schema for {}", plugin_id))
+}
+
+// plugin id -> why its manifest can't be read. These files aren't loaded, but can be edited and
+// deleted with the same plugin id.
+#[tauri::command]
+fn get_manifest_errors() -> Result<HashMap<String, String>, String> {
+    Ok(read_registry()?.manifest_errors.clone())
+}
+
+// Applies the request on the JS thread, and waits for the response. Err means the JS thread
+// didn't respond; a failed operation is reported in `JsResponse::result`.
+// The wait runs on a blocking thread, so that it doesn't hold an async runtime worker.
+#[tauri::command]
+async fn js_request(
+    request: JsRequest,
+    sender: tauri::State<'_, JsOperationSender>,
+) -> Result<JsResponse, String> {
+    log::info!("tauri::command: js_request: {:?}", request);
+    let sender = sender.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        sender.request(request, Duration::from_secs(10))
+    })
+    .await
+    .map_err(|err| format!("{:?}", err))?
+    .map_err(|err| format!("{:?}", err))
 }
 
 #[tauri::command]
@@ -126,6 +148,26 @@ fn get_event_log() -> Result<Vec<Event>, String> {
     Ok(result.iter().cloned().collect())
 }
 
+#[tauri::command]
+fn get_trace_dir() -> Result<String, String> {
+    let dir = trace_dir().map_err(|err| format!("Cannot get trace directory: {:?}", err))?;
+    Ok(dir.to_string_lossy().to_string())
+}
+
+#[tauri::command]
+fn replay_trace(path: String) -> Result<String, String> {
+    log::info!("tauri::command: replay_trace: {}", path);
+
+    let trace = read_trace(PathBuf::from(path).as_path()).map_err(|err| format!("{:?}", err))?;
+    let plugins = Plugins::new().map_err(|err| format!("Plugins::new: {:?}", err))?;
+    let mut replayer = Replayer::new(Some(plugins), &trace)
+        .map_err(|err| format!("Cannot build replayer: {:?}", err))?;
+    let report = replayer
+        .replay(&trace)
+        .map_err(|err| format!("Cannot replay trace: {:?}", err))?;
+    Ok(report.to_string())
+}
+
 #[tauri::command]
 fn add_plugin(plugin_id: String, name: String, description: String) -> Result<(), String> {
     let plugins = Plugins::new().map_err(|err| format!("Cannot add plugin: {:?}", err))?;
@@ -286,28 +328,39 @@ fn main() -> anyhow::Result<()> {
     let app_config = AppConfig::load()?;
     set_log_level_by_config(&app_config);
 
-    let (js_operation_tx, js_operation_rx) = mpsc::channel::<JsOperation>();
+    let (js_operation_tx, js_operation_rx) = js_operation_channel();
     let (setup_tx, setup_rx) = mpsc::channel::<anyhow::Result<()>>();
+    // The version in tauri.conf.json5, stamped by the release workflow. The crates are 0.0.0.
+    let tauri_context = tauri::generate_context!();
+    let app_version = tauri_context.package_info().version.to_string();
 
     thread::spawn(move || {
         log::debug!("Starting handler thread: {:?}", thread::current().id());
         let plugins = Plugins::new().expect("Cannot load plugins");
+        let backend = default_backend().expect("Cannot create input backend");
         let mut js = JS::new(
             Some(js_operation_rx),
             Some(Arc::clone(&VEC_DEQUE)),
             Some(plugins),
+            Some(Arc::clone(&backend)),
         )
         .expect("Cannot create JS instance");
+        if let Err(err) = js.set_app_version(&app_version) {
+            log::error!("Cannot set the app version: {:?}", err);
+        }
+        if let Err(err) = js.share_registry(Arc::clone(&PLUGIN_REGISTRY)) {
+            log::error!("Cannot publish the plugin registry: {:?}", err);
+        }
         if let Err(err) = js.load_user_scripts() {
             log::error!("Cannot load plugin: {:?}", err);
         }
 
-        let result = grab_setup(js);
+        let result = backend.grab_setup(js);
         if let Err(err) = &result {
             log::error!("Cannot run handler: {:?}", err);
         }
         setup_tx.send(result).expect("Send setup message");
-        grab_run();
+        backend.grab_run();
     });
 
 
@@ -331,14 +384,8 @@ fn main() -> anyhow::Result<()> {
                 .menu(&menu)
                 .build(app)?;
 
-            app.listen_any("js-operation", move |event| {
-                // update-config
-                log::info!("js-operation: {:?}", event);
-                let js_operation: JsOperation = serde_json::from_str(event.payload())
-                    .expect("Deserialize js-operation");
-                js_operation_tx.send(js_operation)
-                    .expect("Send message");
-            });
+            // The UI sends the operations by `js_request`.
+            app.manage(js_operation_tx);
 
             log::info!("Waiting CGEventTapCreate");
             let setup_result = setup_rx.recv().expect("Setup message received");
@@ -411,8 +458,11 @@ fn main() -> anyhow::Result<()> {
             save_config_for_plugin,
             load_config_for_plugin,
             get_config_schema_for_plugin,
+            get_manifest_errors,
             update_log_level,
             get_event_log,
+            get_trace_dir,
+            replay_trace,
             add_plugin,
             list_plugins,
             read_plugin_code,
@@ -422,8 +472,9 @@ fn main() -> anyhow::Result<()> {
             read_console_logs,
             get_plugin_filename,
             get_setup_error,
+            js_request,
         ])
-        .build(tauri::generate_context!())
+        .build(tauri_context)
         .expect("error while building tauri application")
         .run(|_app_handle, event| {
             if let tauri::RunEvent::ExitRequested { api, .. } = event {
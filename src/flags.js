@@ -26,3 +26,31 @@ export function flagsToString(flags) {
 
 	return flagNames.join(" | ");
 }
+
+const modifierNames = [
+	["shift", "leftShift", "rightShift", "Shift"],
+	["ctrl", "leftCtrl", "rightCtrl", "Ctrl"],
+	["alt", "leftAlt", "rightAlt", "Alt"],
+	["cmd", "leftCmd", "rightCmd", "Cmd"],
+];
+
+/**
+ * Describes the decoded modifiers of an event, e.g. "LShift RCmd Fn".
+ * @param {object} modifiers `modifiers` of the event log entry.
+ */
+export function modifiersToString(modifiers) {
+	if (!modifiers) return "";
+
+	const names = [];
+	for (const [key, left, right, name] of modifierNames) {
+		if (modifiers[left]) names.push(`L${name}`);
+		if (modifiers[right]) names.push(`R${name}`);
+		if (modifiers[key] && !modifiers[left] && !modifiers[right]) {
+			names.push(name);
+		}
+	}
+	if (modifiers.fn) names.push("Fn");
+	if (modifiers.capsLock) names.push("CapsLock");
+
+	return names.join(" ");
+}
@@ -0,0 +1,22 @@
+import { invoke } from "@tauri-apps/api/core";
+
+// Must match js_operation::PROTOCOL_VERSION.
+export const PROTOCOL_VERSION = 1;
+
+let nextRequestId = 1;
+
+// Applies the operation on the JS thread, and returns the JsOperationOutput, e.g. "Done" or
+// { Stats: {...} }. Throws if the operation failed.
+export async function runJsOperation(operation) {
+	const id = nextRequestId++;
+	const response = await invoke("js_request", {
+		request: { version: PROTOCOL_VERSION, id, operation },
+	});
+	if (response.id !== id) {
+		throw new Error(`Unexpected response id: ${response.id} (expected ${id})`);
+	}
+	if ("Err" in response.result) {
+		throw new Error(response.result.Err);
+	}
+	return response.result.Ok;
+}
@@ -1,84 +1,16 @@
-const keyCodeMap = {
-	58: "alt",
-	61: "alt_gr",
-	51: "backspace",
-	57: "caps_lock",
-	59: "control_left",
-	62: "control_right",
-	125: "down_arrow",
-	53: "escape",
-	122: "f1",
-	109: "f10",
-	103: "f11",
-	111: "f12",
-	120: "f2",
-	99: "f3",
-	118: "f4",
-	96: "f5",
-	97: "f6",
-	98: "f7",
-	100: "f8",
-	101: "f9",
-	63: "function",
-	123: "left_arrow",
-	55: "meta_left",
-	54: "meta_right",
-	// 36: "return",
-	36: "enter",
-	124: "right_arrow",
-	56: "shift_left",
-	60: "shift_right",
-	49: "space",
-	48: "tab",
-	126: "up",
-	50: "`",
-	18: "num1",
-	19: "num2",
-	20: "num3",
-	21: "num4",
-	23: "num5",
-	22: "num6",
-	26: "num7",
-	28: "num8",
-	25: "num9",
-	29: "num0",
-	27: "-",
-	24: "=",
-	12: "q",
-	13: "w",
-	14: "e",
-	15: "r",
-	17: "t",
-	16: "y",
-	32: "u",
-	34: "i",
-	31: "o",
-	35: "p",
-	33: "{",
-	30: "}",
-	0: "a",
-	1: "s",
-	2: "d",
-	3: "f",
-	5: "g",
-	4: "h",
-	38: "j",
-	40: "k",
-	37: "l",
-	41: ";",
-	39: "'",
-	42: "\\",
-	6: "z",
-	7: "x",
-	8: "c",
-	9: "v",
-	11: "b",
-	45: "n",
-	46: "m",
-	43: ",",
-	47: ".",
-	44: "/",
-};
+// keyscripten-core/keycodes.txt is shared with the Rust side. The first name on each line is the
+// canonical one.
+import keycodes from "../keyscripten-core/keycodes.txt?raw";
+
+const keyCodeMap = {};
+for (const line of keycodes.split("\n")) {
+	const trimmed = line.trim();
+	if (trimmed === "" || trimmed.startsWith("#")) {
+		continue;
+	}
+	const [keyCode, name] = trimmed.split(/\s+/);
+	keyCodeMap[keyCode] = name;
+}
 
 /**
  * @param {number} keyCode
@@ -86,3 +18,26 @@ const keyCodeMap = {
 export function getKeyName(keyCode) {
 	return keyCodeMap[keyCode] || null;
 }
+
+const notationPrefixes = [
+	["ctrl", "C-"],
+	["alt", "A-"],
+	["cmd", "M-"],
+	["shift", "S-"],
+];
+
+/**
+ * Describes the key in the hotkey notation, e.g. "C-x".
+ * @param {object} modifiers `modifiers` of the event log entry.
+ * @param {number} keyCode
+ */
+export function keyToNotation(modifiers, keyCode) {
+	const name = getKeyName(keyCode) ?? "unknown";
+	if (!modifiers) return name;
+
+	let prefix = "";
+	for (const [key, notation] of notationPrefixes) {
+		if (modifiers[key]) prefix += notation;
+	}
+	return prefix + name;
+}